@@ -1,3 +1,5 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
 #[derive(Clone, Debug, PartialEq)]
 struct FARule {
     state: i32,
@@ -81,6 +83,7 @@ impl DFA {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 struct DFADesign {
     start_state: i32,
     accept_states: Vec<i32>,
@@ -100,6 +103,228 @@ impl DFADesign {
         let mut dfa = self.to_dfa();
         dfa.read_string(string).accepting()
     }
+
+    // 開始状態から幅優先でたどった順に 1, 2, 3, ... と番号を付け直す。
+    // 同じ状態から出る規則は文字の順にたどり、たどり着けない状態は捨てる
+    fn renumber(&self) -> DFADesign {
+        let mut outgoing: HashMap<i32, Vec<&FARule>> = HashMap::new();
+        for rule in self.rulebook.rules.iter() {
+            outgoing.entry(rule.state).or_default().push(rule);
+        }
+        for rules in outgoing.values_mut() {
+            rules.sort_by_key(|fr| fr.character);
+        }
+        let mut numbers: HashMap<i32, i32> = HashMap::new();
+        let mut queue = VecDeque::new();
+        numbers.insert(self.start_state, 1);
+        queue.push_back(self.start_state);
+        let mut rules = vec![];
+        while let Some(state) = queue.pop_front() {
+            for rule in outgoing.get(&state).into_iter().flatten() {
+                if !numbers.contains_key(&rule.next_state) {
+                    numbers.insert(rule.next_state, numbers.len() as i32 + 1);
+                    queue.push_back(rule.next_state);
+                }
+                rules.push(FARule {
+                    state: numbers[&state],
+                    character: rule.character,
+                    next_state: numbers[&rule.next_state],
+                });
+            }
+        }
+        let mut accept_states: Vec<i32> = self
+            .accept_states
+            .iter()
+            .filter_map(|s| numbers.get(s).cloned())
+            .collect();
+        accept_states.sort();
+        accept_states.dedup();
+        DFADesign {
+            start_state: 1,
+            accept_states,
+            rulebook: DFARuleBook { rules },
+        }
+    }
+
+    // Hopcroft の分割の細分化で区別できない状態をまとめ、renumber で番号を付け直す。
+    // 同じ言語を受理する DFA からは同じものができる
+    fn minimize(&self) -> DFADesign {
+        let dfa = self.renumber();
+        let mut alphabet: Vec<char> = dfa.rulebook.rules.iter().map(|fr| fr.character).collect();
+        alphabet.sort();
+        alphabet.dedup();
+        // 状態 n を添字 n - 1 で扱い、規則のない遷移は最後の添字 dead に向ける
+        let size = dfa
+            .rulebook
+            .rules
+            .iter()
+            .map(|fr| fr.next_state)
+            .max()
+            .unwrap_or(1) as usize;
+        let dead = size;
+        let mut delta = vec![vec![dead; alphabet.len()]; size + 1];
+        for rule in dfa.rulebook.rules.iter() {
+            let i = alphabet.binary_search(&rule.character).unwrap();
+            delta[rule.state as usize - 1][i] = rule.next_state as usize - 1;
+        }
+        let mut inverse = vec![vec![vec![]; size + 1]; alphabet.len()];
+        for (s, row) in delta.iter().enumerate() {
+            for (i, &t) in row.iter().enumerate() {
+                inverse[i][t].push(s);
+            }
+        }
+        let accepting: Vec<bool> = (0..=size)
+            .map(|s| dfa.accept_states.contains(&(s as i32 + 1)) && s != dead)
+            .collect();
+        let (accept, reject): (Vec<usize>, Vec<usize>) = (0..=size).partition(|&s| accepting[s]);
+        let mut blocks: Vec<Vec<usize>> = vec![];
+        let mut block_of = vec![0; size + 1];
+        for block in [accept, reject].iter().filter(|block| !block.is_empty()) {
+            for &s in block.iter() {
+                block_of[s] = blocks.len();
+            }
+            blocks.push(block.clone());
+        }
+
+        let mut waiting: Vec<usize> = (0..blocks.len()).collect();
+        let mut is_waiting = vec![true; blocks.len()];
+        while let Some(splitter) = waiting.pop() {
+            is_waiting[splitter] = false;
+            let splitter = blocks[splitter].clone();
+            for inverse in inverse.iter() {
+                let mut moved: BTreeMap<usize, HashSet<usize>> = BTreeMap::new();
+                for &t in splitter.iter() {
+                    for &s in inverse[t].iter() {
+                        moved.entry(block_of[s]).or_default().insert(s);
+                    }
+                }
+                for (b, moved) in moved {
+                    if moved.len() == blocks[b].len() {
+                        continue;
+                    }
+                    let (inside, outside): (Vec<usize>, Vec<usize>) =
+                        blocks[b].iter().partition(|s| moved.contains(s));
+                    let new = blocks.len();
+                    for &s in inside.iter() {
+                        block_of[s] = new;
+                    }
+                    blocks[b] = outside;
+                    blocks.push(inside);
+                    is_waiting.push(false);
+                    let next = if is_waiting[b] || blocks[new].len() < blocks[b].len() {
+                        new
+                    } else {
+                        b
+                    };
+                    waiting.push(next);
+                    is_waiting[next] = true;
+                }
+            }
+        }
+
+        // dead だけのブロックは元の DFA になかったので捨てる
+        let mut rules = vec![];
+        for block in blocks.iter() {
+            let state = match block.iter().find(|&&s| s != dead) {
+                Some(&state) => state,
+                None => continue,
+            };
+            for (i, &character) in alphabet.iter().enumerate() {
+                let target = &blocks[block_of[delta[state][i]]];
+                if let Some(&next_state) = target.iter().find(|&&s| s != dead) {
+                    rules.push(FARule {
+                        state: block_of[state] as i32 + 1,
+                        character,
+                        next_state: block_of[next_state] as i32 + 1,
+                    });
+                }
+            }
+        }
+        let accept_states = blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| accepting[block[0]])
+            .map(|(b, _)| b as i32 + 1)
+            .collect();
+        DFADesign {
+            start_state: block_of[0] as i32 + 1,
+            accept_states,
+            rulebook: DFARuleBook { rules },
+        }
+        .renumber()
+    }
+
+    // Graphviz の dot 形式。同じ2状態の間の規則は1本の辺にまとめ、
+    // 続いた文字は [a-c] のように範囲で書く
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n  rankdir=LR;\n  node [shape=circle];\n");
+        dot += "  \"\" [shape=none];\n";
+        dot += &format!("  \"\" -> {};\n", dot_id(&self.start_state.to_string()));
+        let mut accept_states = self.accept_states.clone();
+        accept_states.sort();
+        accept_states.dedup();
+        for state in accept_states {
+            dot += &format!("  {} [shape=doublecircle];\n", dot_id(&state.to_string()));
+        }
+        let mut edges: BTreeMap<(i32, i32), Vec<char>> = BTreeMap::new();
+        for rule in self.rulebook.rules.iter() {
+            edges
+                .entry((rule.state, rule.next_state))
+                .or_default()
+                .push(rule.character);
+        }
+        for ((state, next_state), characters) in edges {
+            dot += &format!(
+                "  {} -> {} [label={}];\n",
+                dot_id(&state.to_string()),
+                dot_id(&next_state.to_string()),
+                dot_id(&dot_label(characters))
+            );
+        }
+        dot += "}\n";
+        dot
+    }
+}
+
+// 続いた文字は [a-c] のように範囲で書く
+fn dot_label(mut characters: Vec<char>) -> String {
+    // 改行などの制御文字は \n のように書く
+    let escape = |c: char| {
+        if c.is_control() {
+            c.escape_debug().to_string()
+        } else {
+            c.to_string()
+        }
+    };
+    characters.sort();
+    characters.dedup();
+    let mut runs: Vec<(char, char)> = vec![];
+    for c in characters {
+        match runs.last_mut() {
+            Some((_, end)) if *end as u32 + 1 == c as u32 => *end = c,
+            _ => runs.push((c, c)),
+        }
+    }
+    let single = runs.len() == 1 && runs[0].0 == runs[0].1;
+    let runs: Vec<String> = runs
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                escape(start)
+            } else {
+                format!("{}-{}", escape(start), escape(end))
+            }
+        })
+        .collect();
+    if single {
+        runs.concat()
+    } else {
+        format!("[{}]", runs.concat())
+    }
+}
+
+fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
 }
 
 fn main() {
@@ -167,4 +392,125 @@ fn main() {
         "dfa_design.accepts('baba') -> {}",
         dfa_design.accepts(String::from("baba"))
     );
+    println!("{}", dfa_design.to_dot());
+
+    // 2 と 4、3 と 5 は区別できないのでまとめられる
+    let mut rules = rulebook.rules.clone();
+    rules[0].next_state = 4;
+    rules.extend(vec![
+        FARule {
+            state: 4,
+            character: 'a',
+            next_state: 2,
+        },
+        FARule {
+            state: 4,
+            character: 'b',
+            next_state: 5,
+        },
+        FARule {
+            state: 5,
+            character: 'a',
+            next_state: 3,
+        },
+        FARule {
+            state: 5,
+            character: 'b',
+            next_state: 5,
+        },
+    ]);
+    let redundant = DFADesign {
+        start_state: 1,
+        accept_states: vec![3, 5],
+        rulebook: DFARuleBook { rules },
+    };
+    println!("{}", redundant.minimize().rulebook.inspect());
+    println!(
+        "redundant.minimize() == dfa_design.minimize() -> {}",
+        redundant.minimize() == dfa_design.minimize()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DFADesign, DFARuleBook, FARule};
+
+    fn design(start_state: i32, accept_states: Vec<i32>, rules: &[(i32, char, i32)]) -> DFADesign {
+        DFADesign {
+            start_state,
+            accept_states,
+            rulebook: DFARuleBook {
+                rules: rules
+                    .iter()
+                    .map(|&(state, character, next_state)| FARule {
+                        state,
+                        character,
+                        next_state,
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_minimize() {
+        // a を偶数個含む文字列。0 と 2、1 と 3 は区別できず、9 にはたどり着けない
+        let redundant = design(
+            0,
+            vec![0, 2, 9],
+            &[
+                (0, 'a', 1),
+                (0, 'b', 2),
+                (1, 'a', 2),
+                (1, 'b', 3),
+                (2, 'a', 3),
+                (2, 'b', 0),
+                (3, 'a', 0),
+                (3, 'b', 1),
+                (9, 'a', 9),
+                (9, 'b', 9),
+            ],
+        );
+        let minimal = design(
+            1,
+            vec![1],
+            &[(1, 'a', 2), (1, 'b', 1), (2, 'a', 1), (2, 'b', 2)],
+        );
+        assert_eq!(redundant.minimize(), minimal);
+        assert_eq!(minimal.minimize(), minimal);
+        for input in ["", "a", "ab", "aba", "bbaab"].iter() {
+            assert_eq!(
+                redundant.accepts(input.to_string()),
+                minimal.accepts(input.to_string())
+            );
+        }
+        // どこも受理しない状態も、規則を欠かさないよう残す
+        let trap = design(1, vec![], &[(1, 'a', 2), (2, 'a', 1)]);
+        assert_eq!(trap.minimize(), design(1, vec![], &[(1, 'a', 1)]));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let dfa_design = design(
+            1,
+            vec![2, 3],
+            &[
+                (1, 'c', 2),
+                (1, 'a', 2),
+                (1, 'b', 2),
+                (1, 'x', 2),
+                (2, '"', 1),
+                (2, '\\', 3),
+                (3, '\n', 1),
+            ],
+        );
+        // " と \ は dot の文字列の中でエスケープし、制御文字は \n のように書く
+        assert_eq!(
+            dfa_design.to_dot(),
+            "digraph {\n  rankdir=LR;\n  node [shape=circle];\n  \"\" [shape=none];\n  \
+             \"\" -> \"1\";\n  \"2\" [shape=doublecircle];\n  \"3\" [shape=doublecircle];\n  \
+             \"1\" -> \"2\" [label=\"[a-cx]\"];\n  \"2\" -> \"1\" [label=\"\\\"\"];\n  \
+             \"2\" -> \"3\" [label=\"\\\\\"];\n  \"3\" -> \"1\" [label=\"\\\\n\"];\n}\n"
+        );
+    }
 }
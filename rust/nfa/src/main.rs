@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 #[derive(Clone, Debug, PartialEq)]
 struct FARule {
@@ -121,8 +121,76 @@ impl NFADesign {
         let mut nfa = self.to_nfa();
         nfa.read_string(string).accepting()
     }
+
+    // Graphviz の dot 形式。自由移動は ε と書き、同じ2状態の間の規則は1本の辺にまとめる
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n  rankdir=LR;\n  node [shape=circle];\n");
+        dot += "  \"\" [shape=none];\n";
+        dot += &format!("  \"\" -> {};\n", dot_id(&self.start_state.to_string()));
+        let mut accept_states: Vec<&i32> = self.accept_states.iter().collect();
+        accept_states.sort();
+        for state in accept_states {
+            dot += &format!("  {} [shape=doublecircle];\n", dot_id(&state.to_string()));
+        }
+        let mut edges: BTreeMap<(i32, i32), Vec<char>> = BTreeMap::new();
+        for rule in self.rulebook.rules.iter() {
+            edges.entry((rule.state, rule.next_state)).or_default().push(rule.character);
+        }
+        for ((state, next_state), characters) in edges {
+            dot += &format!(
+                "  {} -> {} [label={}];\n",
+                dot_id(&state.to_string()),
+                dot_id(&next_state.to_string()),
+                dot_id(&dot_label(characters))
+            );
+        }
+        dot += "}\n";
+        dot
+    }
+}
+
+// 続いた文字は [a-c] のように範囲で書く。自由移動があれば先頭に ε
+fn dot_label(mut characters: Vec<char>) -> String {
+    // 改行などの制御文字は \n のように書く
+    let escape = |c: char| {
+        if c.is_control() {
+            c.escape_debug().to_string()
+        } else {
+            c.to_string()
+        }
+    };
+    characters.sort();
+    characters.dedup();
+    let free_move = characters.contains(&'\u{029e}');
+    characters.retain(|&c| c != '\u{029e}');
+    let mut runs: Vec<(char, char)> = vec![];
+    for c in characters {
+        match runs.last_mut() {
+            Some((_, end)) if *end as u32 + 1 == c as u32 => *end = c,
+            _ => runs.push((c, c)),
+        }
+    }
+    let mut parts = vec![];
+    if free_move {
+        parts.push(String::from("ε"));
+    }
+    match runs.as_slice() {
+        [] => {}
+        [(start, end)] if start == end => parts.push(escape(*start)),
+        _ => {
+            let runs: Vec<String> = runs
+                .iter()
+                .map(|&(start, end)| if start == end { escape(start) } else { format!("{}-{}", escape(start), escape(end)) })
+                .collect();
+            parts.push(format!("[{}]", runs.concat()));
+        }
+    }
+    parts.join(", ")
 }
 
+fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
 
 fn main() {
     let rulebook = NFARuleBook {
@@ -170,4 +238,49 @@ fn main() {
     println!("new nfa, accepting? : {}", nfa.accepting());
     nfa.read_string(String::from("bbbbb"));
     println!("read bbbbb; accepting? : {}", nfa.accepting());
+
+    // 2 の倍数か 3 の倍数の個数の a。自由移動でどちらかの輪に入る
+    let rulebook = NFARuleBook {
+        rules: vec![
+            FARule::new(1, '\u{029e}', 2), FARule::new(1, '\u{029e}', 4),
+            FARule::new(2, 'a', 3), FARule::new(3, 'a', 2),
+            FARule::new(4, 'a', 5), FARule::new(5, 'a', 6), FARule::new(6, 'a', 4)
+        ]
+    };
+    let nfa_design = NFADesign {
+        start_state: 1,
+        accept_states: vec![2, 4].into_iter().collect(),
+        rulebook
+    };
+    println!("{}", nfa_design.to_dot());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FARule, NFADesign, NFARuleBook};
+
+    #[test]
+    fn test_to_dot() {
+        // 自由移動は ε。同じ2状態の間の文字と一緒に1本の辺にする
+        let nfa_design = NFADesign {
+            start_state: 1,
+            accept_states: vec![2, 3].into_iter().collect(),
+            rulebook: NFARuleBook {
+                rules: vec![
+                    FARule::new(1, '\u{029e}', 2), FARule::new(1, 'b', 2),
+                    FARule::new(1, 'a', 2), FARule::new(1, 'z', 2),
+                    FARule::new(2, '"', 1), FARule::new(2, '\\', 3),
+                    FARule::new(3, '\n', 1), FARule::new(3, '\u{029e}', 1)
+                ]
+            }
+        };
+        // " と \ は dot の文字列の中でエスケープし、制御文字は \n のように書く
+        assert_eq!(
+            nfa_design.to_dot(),
+            "digraph {\n  rankdir=LR;\n  node [shape=circle];\n  \"\" [shape=none];\n  \
+             \"\" -> \"1\";\n  \"2\" [shape=doublecircle];\n  \"3\" [shape=doublecircle];\n  \
+             \"1\" -> \"2\" [label=\"ε, [a-bz]\"];\n  \"2\" -> \"1\" [label=\"\\\"\"];\n  \
+             \"2\" -> \"3\" [label=\"\\\\\"];\n  \"3\" -> \"1\" [label=\"ε, \\\\n\"];\n}\n"
+        );
+    }
 }
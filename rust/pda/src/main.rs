@@ -1,7 +1,13 @@
 #![allow(dead_code)]
 
 type State = i32;
-const NIL: char = '\u{029e}';
+
+// 規則で読むもの。Epsilon は入力を読まずに進む自由移動
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Label {
+    Epsilon,
+    Character(char),
+}
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 struct Stack {
@@ -63,17 +69,17 @@ impl PDAConfiguration {
 #[derive(Clone, Eq, PartialEq)]
 struct PDARule {
     state: State,
-    character: char,
+    label: Label,
     next_state: State,
     pop_character: char,
     push_characters: Vec<char>,
 }
 
 impl PDARule {
-    fn applies_to(&self, configuration: PDAConfiguration, character: char) -> bool {
+    fn applies_to(&self, configuration: PDAConfiguration, label: Label) -> bool {
         self.state == configuration.state
             && self.pop_character == configuration.stack.top()
-            && self.character == character
+            && self.label == label
     }
 
     fn follow(&self, configuration: &PDAConfiguration) -> PDAConfiguration {
@@ -103,26 +109,26 @@ impl DPDARuleBook {
     fn next_configuration(
         &self,
         configuration: &PDAConfiguration,
-        character: char,
+        label: Label,
     ) -> PDAConfiguration {
-        self.rule_for(configuration, character)
+        self.rule_for(configuration, label)
             .expect("no rule for this configuration")
             .follow(configuration)
     }
 
-    fn applies_to(&self, configuration: &PDAConfiguration, character: char) -> bool {
-        self.rule_for(configuration, character).is_some()
+    fn applies_to(&self, configuration: &PDAConfiguration, label: Label) -> bool {
+        self.rule_for(configuration, label).is_some()
     }
 
-    fn rule_for(&self, configuration: &PDAConfiguration, character: char) -> Option<&PDARule> {
+    fn rule_for(&self, configuration: &PDAConfiguration, label: Label) -> Option<&PDARule> {
         self.rules
             .iter()
-            .find(|rule| rule.clone().applies_to(configuration.clone(), character))
+            .find(|rule| rule.clone().applies_to(configuration.clone(), label))
     }
 
     fn follow_free_moves(&self, configuration: &PDAConfiguration) -> PDAConfiguration {
-        if self.applies_to(configuration, NIL) {
-            self.follow_free_moves(&self.next_configuration(configuration, NIL))
+        if self.applies_to(configuration, Label::Epsilon) {
+            self.follow_free_moves(&self.next_configuration(configuration, Label::Epsilon))
         } else {
             configuration.clone()
         }
@@ -139,9 +145,10 @@ struct DPDA {
 impl DPDA {
     fn next_configuration(&self, character: char) -> PDAConfiguration {
         let current_configuration = self.get_current_configuration();
-        if self.rulebook.applies_to(&current_configuration, character) {
+        let label = Label::Character(character);
+        if self.rulebook.applies_to(&current_configuration, label) {
             self.rulebook
-                .next_configuration(&current_configuration, character)
+                .next_configuration(&current_configuration, label)
         } else {
             current_configuration.stuck()
         }
@@ -207,7 +214,7 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
-    use super::{DPDADesign, DPDARuleBook, PDAConfiguration, PDARule, Stack, DPDA, NIL};
+    use super::{DPDADesign, DPDARuleBook, Label, PDAConfiguration, PDARule, Stack, DPDA};
 
     #[test]
     fn test_stack() {
@@ -224,7 +231,7 @@ mod tests {
     fn test_pdarule() {
         let rule = PDARule {
             state: 1,
-            character: '(',
+            label: Label::Character('('),
             next_state: 2,
             pop_character: '$',
             push_characters: vec!['b', '$'],
@@ -233,7 +240,7 @@ mod tests {
             state: 1,
             stack: Stack::new(vec!['$']),
         };
-        assert!(rule.applies_to(configuration, '('));
+        assert!(rule.applies_to(configuration, Label::Character('(')));
     }
 
     #[test]
@@ -242,28 +249,28 @@ mod tests {
             rules: vec![
                 PDARule {
                     state: 1,
-                    character: '(',
+                    label: Label::Character('('),
                     next_state: 2,
                     pop_character: '$',
                     push_characters: vec!['b', '$'],
                 },
                 PDARule {
                     state: 2,
-                    character: '(',
+                    label: Label::Character('('),
                     next_state: 2,
                     pop_character: 'b',
                     push_characters: vec!['b', 'b'],
                 },
                 PDARule {
                     state: 2,
-                    character: ')',
+                    label: Label::Character(')'),
                     next_state: 2,
                     pop_character: 'b',
                     push_characters: vec![],
                 },
                 PDARule {
                     state: 2,
-                    character: NIL,
+                    label: Label::Epsilon,
                     next_state: 1,
                     pop_character: '$',
                     push_characters: vec!['$'],
@@ -274,7 +281,7 @@ mod tests {
             state: 1,
             stack: Stack::new(vec!['$']),
         };
-        configuration = rulebook.next_configuration(&configuration, '(');
+        configuration = rulebook.next_configuration(&configuration, Label::Character('('));
         assert_eq!(
             configuration,
             PDAConfiguration {
@@ -282,7 +289,7 @@ mod tests {
                 stack: Stack::new(vec!['b', '$'])
             }
         );
-        configuration = rulebook.next_configuration(&configuration, '(');
+        configuration = rulebook.next_configuration(&configuration, Label::Character('('));
         assert_eq!(
             configuration,
             PDAConfiguration {
@@ -290,7 +297,7 @@ mod tests {
                 stack: Stack::new(vec!['b', 'b', '$'])
             }
         );
-        configuration = rulebook.next_configuration(&configuration, ')');
+        configuration = rulebook.next_configuration(&configuration, Label::Character(')'));
         assert_eq!(
             configuration,
             PDAConfiguration {
@@ -346,28 +353,28 @@ mod tests {
             rules: vec![
                 PDARule {
                     state: 1,
-                    character: '(',
+                    label: Label::Character('('),
                     next_state: 2,
                     pop_character: '$',
                     push_characters: vec!['b', '$'],
                 },
                 PDARule {
                     state: 2,
-                    character: '(',
+                    label: Label::Character('('),
                     next_state: 2,
                     pop_character: 'b',
                     push_characters: vec!['b', 'b'],
                 },
                 PDARule {
                     state: 2,
-                    character: ')',
+                    label: Label::Character(')'),
                     next_state: 2,
                     pop_character: 'b',
                     push_characters: vec![],
                 },
                 PDARule {
                     state: 2,
-                    character: NIL,
+                    label: Label::Epsilon,
                     next_state: 1,
                     pop_character: '$',
                     push_characters: vec!['$'],
@@ -390,28 +397,28 @@ mod tests {
             rules: vec![
                 PDARule {
                     state: 1,
-                    character: '(',
+                    label: Label::Character('('),
                     next_state: 2,
                     pop_character: '$',
                     push_characters: vec!['b', '$'],
                 },
                 PDARule {
                     state: 2,
-                    character: '(',
+                    label: Label::Character('('),
                     next_state: 2,
                     pop_character: 'b',
                     push_characters: vec!['b', 'b'],
                 },
                 PDARule {
                     state: 2,
-                    character: ')',
+                    label: Label::Character(')'),
                     next_state: 2,
                     pop_character: 'b',
                     push_characters: vec![],
                 },
                 PDARule {
                     state: 2,
-                    character: NIL,
+                    label: Label::Epsilon,
                     next_state: 1,
                     pop_character: '$',
                     push_characters: vec!['$'],
@@ -445,4 +452,26 @@ mod tests {
         };
         assert!(!dpda_design.accepts(String::from("())")))
     }
+
+    #[test]
+    fn test_epsilon_character_is_readable() {
+        // 以前は U+029E を自由移動の印にしていたので、この文字を読む規則が自由移動になっていた
+        let rulebook = DPDARuleBook {
+            rules: vec![PDARule {
+                state: 1,
+                label: Label::Character('ʞ'),
+                next_state: 2,
+                pop_character: '$',
+                push_characters: vec!['$'],
+            }],
+        };
+        let dpda_design = DPDADesign {
+            start_state: 1,
+            bottom_character: '$',
+            accept_states: vec![2],
+            rulebook,
+        };
+        assert!(!dpda_design.accepts(String::from("")));
+        assert!(dpda_design.accepts(String::from("ʞ")));
+    }
 }
@@ -0,0 +1,42 @@
+// 入力を長くしていったときのマッチにかかる時間を測る。
+// cargo bench --bench matching
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use regex::regex::{Engine, Regex};
+
+// 何度か測って一番速かったもの
+fn measure(regex: &Regex, input: &str) -> Duration {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(regex.matches(black_box(input)));
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let cases = [
+        ("(a|b)*abb", "ab"),
+        ("[a-z]+(-[a-z]+)*", "word-"),
+        ("(a|aa)*(b|c)?", "aa"),
+    ];
+    for &(pattern, unit) in cases.iter() {
+        for &engine in [Engine::Dfa, Engine::Nfa, Engine::Lazy, Engine::Derivative].iter() {
+            let regex = Regex::with_engine(pattern, engine).unwrap();
+            println!("{} ({:?})", pattern, engine);
+            for &n in [1_000, 10_000, 100_000, 1_000_000].iter() {
+                let input = unit.repeat(n / unit.len());
+                let elapsed = measure(&regex, &input);
+                println!(
+                    "  {:>9} chars: {:>10.3?} ({:.1} ns/char)",
+                    input.len(),
+                    elapsed,
+                    elapsed.as_nanos() as f64 / input.len() as f64
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::BTreeSet;
+
+use crate::nfa::{DFADesign, State, StateInt};
+use crate::pattern::Pattern;
+
+impl DFADesign<StateInt> {
+    // 文字列全体を受理するかを返す、ほかに何も使わない Rust の関数のソースを作る。
+    // 状態は renumber した番号で、(状態, 文字) の match で次の状態を決める。
+    // name はそのまま関数名になる
+    pub fn to_rust(&self, name: &str) -> String {
+        let dfa_design = self.renumber();
+        let mut code = format!("pub fn {}(input: &str) -> bool {{\n", name);
+        // 規則がなければ開始状態から動かないので、ループの代わりに空かどうかだけを見る
+        if dfa_design.rulebook.rules.is_empty() {
+            code += "    if !input.is_empty() {\n";
+            code += "        return false;\n";
+            code += "    }\n";
+            code += "    let state: u32 = 0;\n";
+        } else {
+            code += "    let mut state: u32 = 0;\n";
+            code += "    for c in input.chars() {\n";
+            code += "        state = match (state, c) {\n";
+            for rule in dfa_design.rulebook.rules.iter() {
+                let range = rule.label().characters().unwrap();
+                let characters = if range.start == range.end {
+                    format!("{:?}", range.start)
+                } else {
+                    format!("{:?}..={:?}", range.start, range.end)
+                };
+                code += &format!(
+                    "            ({}, {}) => {},\n",
+                    rule.state().to_string(),
+                    characters,
+                    rule.next_state().to_string()
+                );
+            }
+            code += "            _ => return false,\n";
+            code += "        };\n";
+            code += "    }\n";
+        }
+        let accept_states: BTreeSet<i32> = dfa_design
+            .accept_states
+            .iter()
+            .map(|state| state.value())
+            .collect();
+        code += "    match state {\n";
+        if !accept_states.is_empty() {
+            let accept_states: Vec<String> = accept_states
+                .iter()
+                .map(|state| state.to_string())
+                .collect();
+            code += &format!("        {} => true,\n", accept_states.join(" | "));
+        }
+        code += "        _ => false,\n";
+        code += "    }\n";
+        code += "}\n";
+        code
+    }
+}
+
+impl Pattern {
+    // 最小化した DFA を Rust の関数にする
+    pub fn to_rust(&self, name: &str) -> String {
+        self.to_dfa_design().to_rust(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process::Command;
+
+    use crate::parse::parse;
+
+    #[test]
+    fn test_to_rust() {
+        let code = parse("a[b-d]*".to_string()).unwrap().to_rust("is_abc");
+        assert_eq!(
+            code,
+            "pub fn is_abc(input: &str) -> bool {\n    let mut state: u32 = 0;\n    \
+             for c in input.chars() {\n        state = match (state, c) {\n            \
+             (0, 'a') => 1,\n            (1, 'b'..='d') => 1,\n            \
+             _ => return false,\n        };\n    }\n    match state {\n        \
+             1 => true,\n        _ => false,\n    }\n}\n"
+        );
+        // 規則がなければループを作らない
+        let code = parse("()".to_string()).unwrap().to_rust("is_empty");
+        assert_eq!(
+            code,
+            "pub fn is_empty(input: &str) -> bool {\n    if !input.is_empty() {\n        \
+             return false;\n    }\n    let state: u32 = 0;\n    match state {\n        \
+             0 => true,\n        _ => false,\n    }\n}\n"
+        );
+    }
+
+    // 作ったソースを rustc でコンパイルして動かし、Pattern::matches と比べる
+    #[test]
+    fn test_compiled() {
+        let regexes = [
+            "(a|b)*abb",
+            "[a-z]+(-[a-z]+)?",
+            "a{2,3}|x?",
+            "[^a]\\.",
+            "é+|'|\\\\",
+            "[a-z]+&~(if|else)",
+            "[]",
+            "()",
+        ];
+        let inputs = [
+            "", "abb", "babb", "ab", "foo-bar", "foo-", "aa", "aaaa", "x", "b.", "a.", "éé", "'",
+            "\\", "if", "iff", "\n.",
+        ];
+        let mut source = String::new();
+        for (i, regex) in regexes.iter().enumerate() {
+            source += &parse(regex.to_string())
+                .unwrap()
+                .to_rust(&format!("matches_{}", i));
+        }
+        source += "fn main() {\n";
+        source += &format!("    let inputs = {:?};\n", inputs);
+        source += "    for input in inputs.iter() {\n";
+        for i in 0..regexes.len() {
+            source += &format!("        print!(\"{{}}\", matches_{}(input) as u8);\n", i);
+        }
+        source += "        println!();\n";
+        source += "    }\n";
+        source += "}\n";
+
+        let dir = env::temp_dir().join(format!("regex_codegen_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("generated.rs");
+        let binary = dir.join("generated");
+        fs::write(&path, source).unwrap();
+        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let status = Command::new(rustc)
+            .arg(&path)
+            .arg("-D")
+            .arg("warnings")
+            .arg("-o")
+            .arg(&binary)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(&binary).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let output = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), inputs.len());
+        for (input, line) in inputs.iter().zip(lines.iter()) {
+            let expected: String = regexes
+                .iter()
+                .map(|regex| {
+                    let matches = parse(regex.to_string()).unwrap().matches(input.to_string());
+                    if matches {
+                        '1'
+                    } else {
+                        '0'
+                    }
+                })
+                .collect();
+            assert_eq!(*line, expected, "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::eliminate::{is_nothing, nothing};
+use crate::nfa::{CharRange, DFADesign, DFARuleBook, FARule, StateInt};
+use crate::pattern::Pattern::{self, *};
+
+// パターンの微分 (Brzozowski)。文字 c での微分は、c で始まる文字列から c を取り除いたもの
+// すべてにマッチするパターンになる。NFA を経由しないので、部分集合構成法との突き合わせに使える
+impl Pattern {
+    // 空文字列にマッチするか
+    pub fn nullable(&self) -> bool {
+        match self {
+            Empty | StartAnchor | EndAnchor | Repeat(_) | Optional(_) => true,
+            Literal { .. } | Any | CharClass { .. } => false,
+            Concatenate { first, second } | Intersect { first, second } => {
+                first.nullable() && second.nullable()
+            }
+            Choose { first, second } => first.nullable() || second.nullable(),
+            OneOrMore(pattern) | Capture { pattern, .. } => pattern.nullable(),
+            Bounded { pattern, min, .. } => *min == 0 || pattern.nullable(),
+            Complement(pattern) => !pattern.nullable(),
+        }
+    }
+
+    // 結果は下の組み立て方で簡単にしてあるので、微分を繰り返しても種類は有限個で止まる
+    pub fn derivative(&self, character: char) -> Pattern {
+        match self {
+            Empty | StartAnchor | EndAnchor => nothing(),
+            Literal { .. } | Any | CharClass { .. } => {
+                let ranges = self.character_ranges().unwrap();
+                if ranges.iter().any(|range| range.contains(character)) {
+                    Empty
+                } else {
+                    nothing()
+                }
+            }
+            Concatenate { first, second } => {
+                let left = concatenate(first.derivative(character), (**second).clone());
+                if first.nullable() {
+                    choose(left, second.derivative(character))
+                } else {
+                    left
+                }
+            }
+            Choose { first, second } => {
+                choose(first.derivative(character), second.derivative(character))
+            }
+            Intersect { first, second } => {
+                intersect(first.derivative(character), second.derivative(character))
+            }
+            Complement(pattern) => complement(pattern.derivative(character)),
+            Repeat(pattern) | OneOrMore(pattern) => {
+                concatenate(pattern.derivative(character), repeat((**pattern).clone()))
+            }
+            Optional(pattern) | Capture { pattern, .. } => pattern.derivative(character),
+            // 中身が空文字列にマッチするなら、最低の回数は 0 と同じ
+            Bounded { pattern, min, max } => {
+                let min = if pattern.nullable() { 0 } else { *min };
+                match max {
+                    Some(0) => nothing(),
+                    _ => concatenate(
+                        pattern.derivative(character),
+                        bounded(
+                            (**pattern).clone(),
+                            min.saturating_sub(1),
+                            max.map(|m| m - 1),
+                        ),
+                    ),
+                }
+            }
+        }
+    }
+
+    // 1文字ずつ微分して、最後に空文字列にマッチするかを見る
+    pub fn matches_by_derivatives(&self, string: &str) -> bool {
+        let mut pattern = self.clone();
+        for character in string.chars() {
+            pattern = pattern.derivative(character);
+            if is_nothing(&pattern) {
+                return false;
+            }
+        }
+        pattern.nullable()
+    }
+
+    // 微分してできるパターンを状態とする DFA。最小とは限らない。
+    // 同じ区間の文字での微分は同じになるので、パターンに出てくる文字の範囲を重ならないように
+    // 分けたものと、どこにも出てこない文字の範囲ごとに1回ずつ微分する
+    pub fn to_derivative_dfa_design(&self) -> DFADesign<StateInt> {
+        let mut ranges = vec![];
+        self.collect_ranges(&mut ranges);
+        let mut alphabet = CharRange::partition(&ranges);
+        alphabet.extend(CharRange::complement(&ranges));
+        alphabet.sort();
+
+        let mut numbers: HashMap<Pattern, i32> = HashMap::new();
+        numbers.insert(self.clone(), 0);
+        let mut queue = VecDeque::new();
+        queue.push_back(self.clone());
+        let mut rules = vec![];
+        let mut accept_states = HashSet::new();
+        while let Some(pattern) = queue.pop_front() {
+            let state = numbers[&pattern];
+            if pattern.nullable() {
+                accept_states.insert(StateInt::new(state));
+            }
+            for characters in alphabet.iter() {
+                let next = pattern.derivative(characters.start);
+                if is_nothing(&next) {
+                    continue;
+                }
+                if !numbers.contains_key(&next) {
+                    numbers.insert(next.clone(), numbers.len() as i32);
+                    queue.push_back(next.clone());
+                }
+                rules.push(FARule::range(
+                    StateInt::new(state),
+                    *characters,
+                    StateInt::new(numbers[&next]),
+                ));
+            }
+        }
+        DFADesign {
+            start_state: StateInt::new(0),
+            accept_states,
+            rulebook: DFARuleBook { rules },
+        }
+    }
+
+    fn collect_ranges(&self, ranges: &mut Vec<CharRange>) {
+        match self {
+            Empty | StartAnchor | EndAnchor => {}
+            Literal { .. } | Any | CharClass { .. } => {
+                ranges.extend(self.character_ranges().unwrap())
+            }
+            Concatenate { first, second }
+            | Choose { first, second }
+            | Intersect { first, second } => {
+                first.collect_ranges(ranges);
+                second.collect_ranges(ranges);
+            }
+            Repeat(pattern)
+            | OneOrMore(pattern)
+            | Optional(pattern)
+            | Complement(pattern)
+            | Bounded { pattern, .. }
+            | Capture { pattern, .. } => pattern.collect_ranges(ranges),
+        }
+    }
+}
+
+// 以下は微分の結果を組み立てる。並びは右に寄せ、| と & は平らにして重複を除き、
+// 順序を決めて並べる。こうしておくと同じ言語の微分が同じパターンになりやすい
+
+// すべての文字列にマッチするパターン
+fn everything() -> Pattern {
+    Complement(Box::new(nothing()))
+}
+
+fn concatenate(first: Pattern, second: Pattern) -> Pattern {
+    if is_nothing(&first) || is_nothing(&second) {
+        return nothing();
+    }
+    match (first, second) {
+        (Empty, pattern) | (pattern, Empty) => pattern,
+        (
+            Concatenate {
+                first: head,
+                second: tail,
+            },
+            second,
+        ) => concatenate(*head, concatenate(*tail, second)),
+        (first, second) => Concatenate {
+            first: Box::new(first),
+            second: Box::new(second),
+        },
+    }
+}
+
+fn choose(first: Pattern, second: Pattern) -> Pattern {
+    let mut items = BTreeSet::new();
+    alternatives(first, &mut items);
+    alternatives(second, &mut items);
+    items.retain(|pattern| !is_nothing(pattern));
+    if items.contains(&everything()) {
+        return everything();
+    }
+    nest(items, nothing(), |first, second| Choose { first, second })
+}
+
+fn alternatives(pattern: Pattern, items: &mut BTreeSet<Pattern>) {
+    match pattern {
+        Choose { first, second } => {
+            alternatives(*first, items);
+            alternatives(*second, items);
+        }
+        pattern => {
+            items.insert(pattern);
+        }
+    }
+}
+
+fn intersect(first: Pattern, second: Pattern) -> Pattern {
+    let mut items = BTreeSet::new();
+    conjuncts(first, &mut items);
+    conjuncts(second, &mut items);
+    if items.iter().any(is_nothing) {
+        return nothing();
+    }
+    items.remove(&everything());
+    nest(items, everything(), |first, second| Intersect {
+        first,
+        second,
+    })
+}
+
+fn conjuncts(pattern: Pattern, items: &mut BTreeSet<Pattern>) {
+    match pattern {
+        Intersect { first, second } => {
+            conjuncts(*first, items);
+            conjuncts(*second, items);
+        }
+        pattern => {
+            items.insert(pattern);
+        }
+    }
+}
+
+// 右に寄せて入れ子にする。並べるものがなければ none
+fn nest<F>(items: BTreeSet<Pattern>, none: Pattern, node: F) -> Pattern
+where
+    F: Fn(Box<Pattern>, Box<Pattern>) -> Pattern,
+{
+    items
+        .into_iter()
+        .rev()
+        .fold(None, |rest, item| {
+            Some(match rest {
+                None => item,
+                Some(rest) => node(Box::new(item), Box::new(rest)),
+            })
+        })
+        .unwrap_or(none)
+}
+
+fn complement(pattern: Pattern) -> Pattern {
+    match pattern {
+        Complement(pattern) => *pattern,
+        pattern => Complement(Box::new(pattern)),
+    }
+}
+
+fn repeat(pattern: Pattern) -> Pattern {
+    match pattern {
+        Empty => Empty,
+        pattern if is_nothing(&pattern) => Empty,
+        Repeat(_) => pattern,
+        pattern => Repeat(Box::new(pattern)),
+    }
+}
+
+fn bounded(pattern: Pattern, min: u32, max: Option<u32>) -> Pattern {
+    match (min, max) {
+        (0, Some(0)) => Empty,
+        (0, None) => repeat(pattern),
+        (min, max) => Bounded {
+            pattern: Box::new(pattern),
+            min,
+            max,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse;
+    use crate::pattern::Pattern;
+
+    fn pattern(regex: &str) -> Pattern {
+        parse(regex.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_derivative() {
+        let derivative = |regex: &str, c: char| pattern(regex).derivative(c).to_s();
+        assert_eq!(derivative("ab", 'a'), "b");
+        assert_eq!(derivative("ab", 'b'), "[]");
+        assert_eq!(derivative("a*b", 'a'), "a*b");
+        assert_eq!(derivative("(ab)*", 'a'), "b(ab)*");
+        assert_eq!(derivative("a{2,3}", 'a'), "a{1,2}");
+        assert_eq!(derivative("~a*", 'a'), "~a*");
+        // a* にマッチしない文字列から b を取り除いたものは、すべての文字列
+        assert_eq!(derivative("~a*", 'b'), "~[]");
+        assert!(pattern("a?b*").nullable());
+        assert!(!pattern("a+&b*").nullable());
+        assert!(pattern("~a").nullable());
+    }
+
+    #[test]
+    fn test_matches_by_derivatives() {
+        let inputs = [
+            "", "a", "ab", "abb", "aabb", "ba", "abab", "a\nb", "if", "iff",
+        ];
+        for regex in [
+            "(a|b)*abb",
+            "a{1,3}b?",
+            "(a*)*b",
+            "[^a]|a.b",
+            "[a-z]+&~(if|else)",
+            "~(.*ab.*)",
+            "(a|b)*&~(.*b)",
+            "^ab$|()",
+        ]
+        .iter()
+        {
+            let pattern = pattern(regex);
+            for input in inputs.iter() {
+                assert_eq!(
+                    pattern.matches_by_derivatives(input),
+                    pattern.matches(input.to_string()),
+                    "{} on {:?}",
+                    regex,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_derivative_dfa_design() {
+        // 部分集合構成法で作ったものと、最小化すると一致する
+        for regex in [
+            "(a|b)*abb",
+            "[a-c]+x?",
+            "(ab|a)(bc|c)",
+            ".{2,4}",
+            "a*&(aa)*",
+            "~(a*)",
+            "[a-z]+&~(if|while)",
+            "(a|b)*a(a|b){3}",
+        ]
+        .iter()
+        {
+            let pattern = pattern(regex);
+            assert_eq!(
+                pattern.to_derivative_dfa_design().minimize(),
+                pattern.to_dfa_design(),
+                "{}",
+                regex
+            );
+        }
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+use crate::nfa::{CharRange, DFADesign, FARule, Label, NFADesign, State};
+use crate::pattern::Pattern::{self, *};
+
+impl Pattern {
+    // 状態消去法で DFA と同じ言語を表すパターンを作る
+    pub fn from_dfa_design<S>(design: &DFADesign<S>) -> Pattern
+    where
+        S: State + Clone + Eq + Hash,
+    {
+        eliminate(
+            &design.start_state,
+            |state| design.accept_states.contains(state),
+            &design.rulebook.rules,
+        )
+    }
+
+    // 自由移動は空文字列の辺として扱う
+    pub fn from_nfa_design<S>(design: &NFADesign<S>) -> Pattern
+    where
+        S: State + Clone + Eq + Hash,
+    {
+        eliminate(
+            &design.start_state,
+            |state| design.accept_states.contains(state),
+            &design.rulebook.rules,
+        )
+    }
+
+    // 同じ言語を表す、なるべく短いパターンに書き換える
+    pub fn simplify(&self) -> Pattern {
+        match self {
+            Empty | Literal { .. } | Any | StartAnchor | EndAnchor => self.clone(),
+            Capture { index, pattern } => Capture {
+                index: *index,
+                pattern: Box::new(pattern.simplify()),
+            },
+            CharClass { .. } => characters(&self.character_ranges().unwrap()),
+            Concatenate { first, second } => concatenate(first.simplify(), second.simplify()),
+            Choose { first, second } => choose(first.simplify(), second.simplify()),
+            Repeat(pattern) => repeat(pattern.simplify()),
+            OneOrMore(pattern) => one_or_more(pattern.simplify()),
+            Optional(pattern) => optional(pattern.simplify()),
+            Bounded { pattern, min, max } => match pattern.simplify() {
+                Empty => Empty,
+                pattern if is_nothing(&pattern) && *min > 0 => nothing(),
+                pattern if is_nothing(&pattern) => Empty,
+                pattern => Bounded {
+                    pattern: Box::new(pattern),
+                    min: *min,
+                    max: *max,
+                },
+            },
+            Intersect { first, second } => match (first.simplify(), second.simplify()) {
+                (first, _) if is_nothing(&first) => first,
+                (_, second) if is_nothing(&second) => second,
+                (first, second) if first == second => first,
+                (first, second) => Intersect {
+                    first: Box::new(first),
+                    second: Box::new(second),
+                },
+            },
+            Complement(pattern) => match pattern.simplify() {
+                Complement(pattern) => *pattern,
+                pattern => Complement(Box::new(pattern)),
+            },
+        }
+    }
+}
+
+// 0 を新しい開始状態、1 を新しい受理状態とし、元の状態には出てきた順に 2, 3, ... と番号を付ける。
+// 辺のラベルをパターンにして、元の状態を一つずつ消していく
+fn eliminate<S, F>(start_state: &S, accepting: F, rules: &[FARule<S>]) -> Pattern
+where
+    S: State + Clone + Eq + Hash,
+    F: Fn(&S) -> bool,
+{
+    let mut states: Vec<&S> = vec![start_state];
+    for rule in rules.iter() {
+        for state in [rule.state(), rule.next_state()].iter() {
+            if !states.contains(state) {
+                states.push(state);
+            }
+        }
+    }
+    let index = |state: &S| states.iter().position(|s| *s == state).unwrap() + 2;
+
+    let mut edges: BTreeMap<(usize, usize), Pattern> = BTreeMap::new();
+    add_edge(&mut edges, 0, 2, Empty);
+    for (i, state) in states.iter().enumerate() {
+        if accepting(state) {
+            add_edge(&mut edges, i + 2, 1, Empty);
+        }
+    }
+    for rule in rules.iter() {
+        let label = match rule.label() {
+            Label::Epsilon => Empty,
+            Label::Characters(range) => characters(&[range]),
+        };
+        add_edge(
+            &mut edges,
+            index(rule.state()),
+            index(rule.next_state()),
+            label,
+        );
+    }
+
+    let mut remaining: Vec<usize> = (2..states.len() + 2).collect();
+    while !remaining.is_empty() {
+        // 入る辺と出る辺の数の積が小さい状態から消すと、パターンが膨らみにくい
+        let (position, &state) = remaining
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &q)| {
+                let incoming = edges.keys().filter(|&&(p, r)| r == q && p != q).count();
+                let outgoing = edges.keys().filter(|&&(p, r)| p == q && r != q).count();
+                incoming * outgoing
+            })
+            .unwrap();
+        remaining.remove(position);
+        let loop_pattern = edges.remove(&(state, state)).map_or(Empty, repeat);
+        let incoming: Vec<(usize, Pattern)> = edges
+            .iter()
+            .filter(|((_, r), _)| *r == state)
+            .map(|((p, _), pattern)| (*p, pattern.clone()))
+            .collect();
+        let outgoing: Vec<(usize, Pattern)> = edges
+            .iter()
+            .filter(|((p, _), _)| *p == state)
+            .map(|((_, r), pattern)| (*r, pattern.clone()))
+            .collect();
+        edges.retain(|&(p, r), _| p != state && r != state);
+        for (p, first) in incoming.iter() {
+            for (r, last) in outgoing.iter() {
+                let pattern = concatenate(
+                    first.clone(),
+                    concatenate(loop_pattern.clone(), last.clone()),
+                );
+                add_edge(&mut edges, *p, *r, pattern);
+            }
+        }
+    }
+    edges.remove(&(0, 1)).unwrap_or_else(nothing)
+}
+
+fn add_edge(
+    edges: &mut BTreeMap<(usize, usize), Pattern>,
+    from: usize,
+    to: usize,
+    pattern: Pattern,
+) {
+    let pattern = match edges.remove(&(from, to)) {
+        Some(existing) => choose(existing, pattern),
+        None => pattern,
+    };
+    edges.insert((from, to), pattern);
+}
+
+// 以下はパターンを組み立てながら簡単にする。どれも引数と同じ言語を表すものを返す
+
+// どの文字列にもマッチしないパターン
+pub(crate) fn nothing() -> Pattern {
+    CharClass {
+        ranges: vec![],
+        negated: false,
+    }
+}
+
+pub(crate) fn is_nothing(pattern: &Pattern) -> bool {
+    match pattern {
+        CharClass {
+            ranges,
+            negated: false,
+        } => ranges.is_empty(),
+        _ => false,
+    }
+}
+
+// 文字の集合を、., 1文字, [..], [^..] のうち短く書ける形にする
+fn characters(ranges: &[CharRange]) -> Pattern {
+    let ranges = CharRange::complement(&CharRange::complement(ranges));
+    let others = CharRange::complement(&ranges);
+    if others == [CharRange::single('\n')] {
+        Any
+    } else if ranges.len() == 1 && ranges[0].start == ranges[0].end {
+        Literal {
+            character: ranges[0].start,
+        }
+    } else if others.len() < ranges.len() {
+        CharClass {
+            ranges: others,
+            negated: true,
+        }
+    } else {
+        CharClass {
+            ranges,
+            negated: false,
+        }
+    }
+}
+
+// 並びを平らにしてから、x x* と x* x を x+ に書き換える
+fn concatenate(first: Pattern, second: Pattern) -> Pattern {
+    if is_nothing(&first) || is_nothing(&second) {
+        return nothing();
+    }
+    let mut items = sequence(first);
+    items.extend(sequence(second));
+    let mut i = 0;
+    while i < items.len() {
+        if let Repeat(pattern) = &items[i] {
+            let pattern = pattern.clone();
+            let body = sequence((*pattern).clone());
+            let n = body.len();
+            if n > 0 && i >= n && items[i - n..i] == body[..] {
+                items.splice(i - n..=i, Some(OneOrMore(pattern)));
+                i -= n;
+            } else if n > 0 && i + n < items.len() && items[i + 1..=i + n] == body[..] {
+                items.splice(i..=i + n, Some(OneOrMore(pattern)));
+            }
+        }
+        i += 1;
+    }
+    items
+        .into_iter()
+        .rev()
+        .fold(Empty, |rest, item| match rest {
+            Empty => item,
+            rest => Concatenate {
+                first: Box::new(item),
+                second: Box::new(rest),
+            },
+        })
+}
+
+fn sequence(pattern: Pattern) -> Vec<Pattern> {
+    match pattern {
+        Empty => vec![],
+        Concatenate { first, second } => {
+            let mut items = sequence(*first);
+            items.extend(sequence(*second));
+            items
+        }
+        pattern => vec![pattern],
+    }
+}
+
+fn alternatives(pattern: Pattern, result: &mut Vec<Pattern>) {
+    match pattern {
+        Choose { first, second } => {
+            alternatives(*first, result);
+            alternatives(*second, result);
+        }
+        Optional(pattern) => {
+            alternatives(*pattern, result);
+            result.push(Empty);
+        }
+        pattern => result.push(pattern),
+    }
+}
+
+// 選択肢を平らにして重複を除き、1文字の選択肢は一つの文字クラスにまとめる。
+// 空文字列の選択肢は ? で表す
+fn choose(first: Pattern, second: Pattern) -> Pattern {
+    let mut patterns = vec![];
+    alternatives(first, &mut patterns);
+    alternatives(second, &mut patterns);
+    let has_empty = patterns.contains(&Empty);
+    let mut choices: Vec<Pattern> = vec![];
+    let mut ranges = vec![];
+    let mut class_position = None;
+    for pattern in patterns {
+        if pattern == Empty || is_nothing(&pattern) {
+            continue;
+        }
+        match pattern.character_ranges() {
+            Some(r) => {
+                class_position.get_or_insert(choices.len());
+                ranges.extend(r);
+            }
+            None => {
+                if !choices.contains(&pattern) {
+                    choices.push(pattern);
+                }
+            }
+        }
+    }
+    if let Some(position) = class_position {
+        choices.insert(position, characters(&ranges));
+    }
+    let pattern = choices.into_iter().rev().fold(None, |rest, pattern| {
+        Some(match rest {
+            None => pattern,
+            Some(rest) => Choose {
+                first: Box::new(pattern),
+                second: Box::new(rest),
+            },
+        })
+    });
+    match pattern {
+        None if has_empty => Empty,
+        None => nothing(),
+        Some(pattern) if has_empty => optional(pattern),
+        Some(pattern) => pattern,
+    }
+}
+
+fn repeat(pattern: Pattern) -> Pattern {
+    match pattern {
+        Empty => Empty,
+        pattern if is_nothing(&pattern) => Empty,
+        Repeat(_) => pattern,
+        Optional(pattern) | OneOrMore(pattern) => repeat(*pattern),
+        pattern => Repeat(Box::new(pattern)),
+    }
+}
+
+fn one_or_more(pattern: Pattern) -> Pattern {
+    match pattern {
+        Empty => Empty,
+        pattern if is_nothing(&pattern) => pattern,
+        Repeat(_) | OneOrMore(_) => pattern,
+        Optional(pattern) => repeat(*pattern),
+        pattern => OneOrMore(Box::new(pattern)),
+    }
+}
+
+fn optional(pattern: Pattern) -> Pattern {
+    match pattern {
+        Empty => Empty,
+        pattern if is_nothing(&pattern) => Empty,
+        Repeat(_) | Optional(_) => pattern,
+        OneOrMore(pattern) => Repeat(pattern),
+        pattern => Optional(Box::new(pattern)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nfa::{FARule, NFADesign, NFARuleBook, StateInt};
+    use crate::parse::parse;
+    use crate::pattern::Pattern;
+
+    fn pattern(regex: &str) -> Pattern {
+        parse(regex.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for regex in [
+            "",
+            "[]",
+            "a",
+            "a*",
+            "(a|b)*abb",
+            "ab|ac",
+            "(ab|c)*d?",
+            "[a-z_][a-z0-9_]*",
+            ".x|\\n",
+            "a{2,4}(b|c)+",
+            "((a|b)(a|b))*",
+        ]
+        .iter()
+        {
+            let original = pattern(regex);
+            let from_dfa = Pattern::from_dfa_design(&original.to_dfa_design());
+            let from_nfa = Pattern::from_nfa_design(&original.to_nfa_design());
+            assert_eq!(
+                original.equivalent(&from_dfa),
+                Ok(()),
+                "{}",
+                from_dfa.to_s()
+            );
+            assert_eq!(
+                original.equivalent(&from_nfa),
+                Ok(()),
+                "{}",
+                from_nfa.to_s()
+            );
+            // 表示したものを読み直しても同じ言語になる
+            assert_eq!(original.equivalent(&pattern(&from_dfa.to_s())), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_from_dfa_design() {
+        let to_s = |regex: &str| Pattern::from_dfa_design(&pattern(regex).to_dfa_design()).to_s();
+        assert_eq!(to_s("a*"), "a*");
+        assert_eq!(to_s("(a|b)*"), "[a-b]*");
+        assert_eq!(to_s("aa*"), "a+");
+        assert_eq!(to_s("[]"), "[]");
+        assert_eq!(to_s("()"), "");
+        // すべての文字
+        assert_eq!(to_s("[^a]|a"), "[^]");
+    }
+
+    #[test]
+    fn test_from_nfa_design() {
+        // 本の例: 1 から a か b を読んで、最後から3文字目が b の文字列
+        let rule = |state, character, next_state| {
+            FARule::new(StateInt::new(state), character, StateInt::new(next_state))
+        };
+        let nfa_design = NFADesign {
+            start_state: StateInt::new(1),
+            accept_states: [StateInt::new(4)].iter().cloned().collect(),
+            rulebook: NFARuleBook {
+                rules: vec![
+                    rule(1, 'a', 1),
+                    rule(1, 'b', 1),
+                    rule(1, 'b', 2),
+                    rule(2, 'a', 3),
+                    rule(2, 'b', 3),
+                    rule(3, 'a', 4),
+                    rule(3, 'b', 4),
+                ],
+            },
+        };
+        let pattern = Pattern::from_nfa_design(&nfa_design);
+        assert_eq!(pattern.to_s(), "[a-b]*b[a-b][a-b]");
+    }
+
+    #[test]
+    fn test_simplify() {
+        let simplify = |regex: &str| pattern(regex).simplify().to_s();
+        assert_eq!(simplify("(?:a|a|)(?:(?:[b-c]|d)*)*"), "a?[b-d]*");
+        assert_eq!(simplify("a*a"), "a+");
+        assert_eq!(simplify("(?:ab)(?:ab)*c"), "(ab)+c");
+        assert_eq!(simplify("a[]b|c"), "c");
+        assert_eq!(simplify("[^a]|a"), "[^]");
+        assert_eq!(simplify("(?:|a+)"), "a*");
+        assert_eq!(simplify("[]*"), "");
+        assert_eq!(simplify("[a-cb-d]"), "[a-d]");
+        assert_eq!(simplify("[^\\n]"), ".");
+    }
+}
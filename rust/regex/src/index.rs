@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::nfa::{CharRange, DFARuleBook, Label, NFADesign, State};
+
+// 状態の番号をビットの位置にした集合
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    // 0 から size - 1 までを入れられる空の集合
+    pub fn new(size: usize) -> Self {
+        BitSet {
+            words: vec![0; size.div_ceil(64)],
+        }
+    }
+
+    pub fn insert(&mut self, i: usize) {
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    pub fn contains(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn union_with(&mut self, other: &BitSet) {
+        for (word, other) in self.words.iter_mut().zip(other.words.iter()) {
+            *word |= other;
+        }
+    }
+
+    pub fn is_disjoint(&self, other: &BitSet) -> bool {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .all(|(word, other)| word & other == 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    pub fn iter(&self) -> Bits<'_> {
+        Bits {
+            words: &self.words,
+            index: 0,
+            word: self.words.first().cloned().unwrap_or(0),
+        }
+    }
+}
+
+// BitSet に入っている番号を小さい順に返す
+pub struct Bits<'a> {
+    words: &'a [u64],
+    index: usize,
+    word: u64,
+}
+
+impl<'a> Iterator for Bits<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.word == 0 {
+            self.index += 1;
+            self.word = *self.words.get(self.index)?;
+        }
+        let bit = self.word.trailing_zeros() as usize;
+        self.word &= self.word - 1;
+        Some(self.index * 64 + bit)
+    }
+}
+
+// NFA の規則を (状態, ラベル) で引けるようにしたもの。状態には 0 から番号を付け、
+// 自由移動でたどり着ける状態 (自由移動の閉包) は前もって計算しておく。
+// 1文字読むのにかかる時間は規則の数によらず、いまの状態の数だけで決まる
+#[derive(Clone, Debug)]
+pub struct NFAIndex<S: State + Eq + Clone + Hash> {
+    states: Vec<S>,
+    numbers: HashMap<S, usize>,
+    // 状態ごとに、重ならない文字の範囲の順に並べた行き先。行き先は閉包を取ってある
+    transitions: Vec<Vec<(CharRange, BitSet)>>,
+    closures: Vec<BitSet>,
+    start: BitSet,
+    accept: BitSet,
+}
+
+impl<S: State + Eq + Clone + Hash> NFAIndex<S> {
+    pub fn new(nfa_design: &NFADesign<S>) -> Self {
+        let mut states = vec![];
+        let mut numbers = HashMap::new();
+        let mut number = |state: &S, states: &mut Vec<S>| -> usize {
+            *numbers.entry(state.clone()).or_insert_with(|| {
+                states.push(state.clone());
+                states.len() - 1
+            })
+        };
+        let start = number(&nfa_design.start_state, &mut states);
+        let rules: Vec<(usize, Label, usize)> = nfa_design
+            .rulebook
+            .rules
+            .iter()
+            .map(|rule| {
+                (
+                    number(rule.state(), &mut states),
+                    rule.label(),
+                    number(rule.next_state(), &mut states),
+                )
+            })
+            .collect();
+        let accept_states: Vec<usize> = nfa_design
+            .accept_states
+            .iter()
+            .map(|state| number(state, &mut states))
+            .collect();
+        let size = states.len();
+
+        // 規則を出発する状態ごとに分けておく。毎回すべての規則をなめると状態の数 × 規則の数になる
+        let mut free_moves = vec![vec![]; size];
+        let mut outgoing: Vec<Vec<(CharRange, usize)>> = vec![vec![]; size];
+        for &(state, label, next_state) in rules.iter() {
+            match label.characters() {
+                None => free_moves[state].push(next_state),
+                Some(range) => outgoing[state].push((range, next_state)),
+            }
+        }
+        let closures: Vec<BitSet> = (0..size)
+            .map(|state| {
+                let mut closure = BitSet::new(size);
+                let mut stack = vec![state];
+                closure.insert(state);
+                while let Some(state) = stack.pop() {
+                    for &next_state in free_moves[state].iter() {
+                        if !closure.contains(next_state) {
+                            closure.insert(next_state);
+                            stack.push(next_state);
+                        }
+                    }
+                }
+                closure
+            })
+            .collect();
+
+        let mut transitions = vec![vec![]; size];
+        for (transitions, outgoing) in transitions.iter_mut().zip(outgoing.iter()) {
+            let ranges: Vec<CharRange> = outgoing.iter().map(|&(range, _)| range).collect();
+            for piece in CharRange::partition(&ranges) {
+                let mut next_states = BitSet::new(size);
+                for &(range, next_state) in outgoing.iter() {
+                    if range.contains(piece.start) {
+                        next_states.union_with(&closures[next_state]);
+                    }
+                }
+                transitions.push((piece, next_states));
+            }
+        }
+
+        let mut accept = BitSet::new(size);
+        for state in accept_states {
+            accept.insert(state);
+        }
+        NFAIndex {
+            start: closures[start].clone(),
+            states,
+            numbers,
+            transitions,
+            closures,
+            accept,
+        }
+    }
+
+    // 開始状態とそこから自由移動でたどり着ける状態
+    pub fn start(&self) -> &BitSet {
+        &self.start
+    }
+
+    // states から character を読み、自由移動もたどった後の状態
+    pub fn step(&self, states: &BitSet, character: char) -> BitSet {
+        let mut next_states = BitSet::new(self.states.len());
+        for state in states.iter() {
+            let transitions = &self.transitions[state];
+            let i = transitions.partition_point(|(range, _)| range.start <= character);
+            if let Some((range, targets)) = i.checked_sub(1).map(|i| &transitions[i]) {
+                if range.contains(character) {
+                    next_states.union_with(targets);
+                }
+            }
+        }
+        next_states
+    }
+
+    pub fn accepting(&self, states: &BitSet) -> bool {
+        !states.is_disjoint(&self.accept)
+    }
+
+    pub fn accepts(&self, string: &str) -> bool {
+        let mut states = self.start.clone();
+        for character in string.chars() {
+            states = self.step(&states, character);
+            if states.is_empty() {
+                return false;
+            }
+        }
+        self.accepting(&states)
+    }
+
+    // 自由移動でたどり着ける状態を足した集合
+    pub fn closure(&self, states: &BitSet) -> BitSet {
+        let mut closure = BitSet::new(self.states.len());
+        for state in states.iter() {
+            closure.union_with(&self.closures[state]);
+        }
+        closure
+    }
+
+    pub fn to_bitset<'a, I>(&self, states: I) -> BitSet
+    where
+        I: IntoIterator<Item = &'a S>,
+        S: 'a,
+    {
+        let mut set = BitSet::new(self.states.len());
+        for state in states {
+            if let Some(&number) = self.numbers.get(state) {
+                set.insert(number);
+            }
+        }
+        set
+    }
+
+    pub fn states<'a>(&'a self, set: &'a BitSet) -> impl Iterator<Item = &'a S> + 'a {
+        set.iter().map(move |number| &self.states[number])
+    }
+}
+
+// DFA の規則を状態ごとに、文字の範囲の順に並べたもの
+#[derive(Clone, Debug)]
+pub struct DFAIndex<S: State + Eq + Clone + Hash> {
+    transitions: HashMap<S, Vec<(CharRange, S)>>,
+}
+
+impl<S: State + Eq + Clone + Hash> DFAIndex<S> {
+    pub fn new(rulebook: &DFARuleBook<S>) -> Self {
+        let mut transitions: HashMap<S, Vec<(CharRange, S)>> = HashMap::new();
+        for rule in rulebook.rules.iter() {
+            if let Some(range) = rule.label().characters() {
+                transitions
+                    .entry(rule.state().clone())
+                    .or_default()
+                    .push((range, rule.next_state().clone()));
+            }
+        }
+        // DFA なので、ひとつの状態から出る規則の文字の範囲は重ならない
+        for (state, transitions) in transitions.iter_mut() {
+            transitions.sort_by_key(|(range, _)| *range);
+            if let Some(w) = transitions.windows(2).find(|w| w[1].0.start <= w[0].0.end) {
+                panic!(
+                    "overlapping rules from state {}: {} and {}",
+                    state.to_string(),
+                    w[0].0,
+                    w[1].0
+                );
+            }
+        }
+        DFAIndex { transitions }
+    }
+
+    // 規則がない文字を読むと行き先はない
+    pub fn next_state(&self, state: &S, character: char) -> Option<&S> {
+        let transitions = self.transitions.get(state)?;
+        let i = transitions.partition_point(|(range, _)| range.start <= character);
+        match i.checked_sub(1).map(|i| &transitions[i]) {
+            Some((range, next_state)) if range.contains(character) => Some(next_state),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BitSet, DFAIndex, NFAIndex};
+    use crate::nfa::{CharRange, DFARuleBook, FARule, NFADesign, NFARuleBook, StateInt};
+    use crate::parse::parse;
+
+    #[test]
+    fn test_bitset() {
+        let mut set = BitSet::new(130);
+        assert!(set.is_empty());
+        for i in [0, 63, 64, 129].iter() {
+            set.insert(*i);
+        }
+        assert_eq!(set.iter().collect::<Vec<usize>>(), vec![0, 63, 64, 129]);
+        assert!(set.contains(64));
+        assert!(!set.contains(65));
+        let mut other = BitSet::new(130);
+        other.insert(1);
+        assert!(set.is_disjoint(&other));
+        other.union_with(&set);
+        assert_eq!(other.iter().count(), 5);
+        assert!(!set.is_disjoint(&other));
+    }
+
+    #[test]
+    fn test_nfa_index() {
+        let nfa_design = parse("(a|[a-c])*b".to_string()).unwrap().to_nfa_design();
+        let index = NFAIndex::new(&nfa_design);
+        for input in ["", "b", "ab", "cab", "abc", "d", "aaaab"].iter() {
+            assert_eq!(index.accepts(input), nfa_design.accepts(input.to_string()));
+        }
+        // 開始状態の閉包には自由移動の先も入っている
+        let start: Vec<&StateInt> = index.states(index.start()).collect();
+        assert!(start.len() > 1);
+        assert_eq!(index.closure(index.start()), *index.start());
+        assert_eq!(index.to_bitset(index.states(index.start())), *index.start());
+        assert!(index.step(index.start(), 'x').is_empty());
+    }
+
+    #[test]
+    fn test_dfa_index() {
+        let rulebook = DFARuleBook {
+            rules: vec![
+                FARule::range(StateInt::new(1), CharRange::new('m', 'z'), StateInt::new(3)),
+                FARule::range(StateInt::new(1), CharRange::new('a', 'k'), StateInt::new(2)),
+            ],
+        };
+        let index = DFAIndex::new(&rulebook);
+        assert_eq!(
+            index.next_state(&StateInt::new(1), 'b'),
+            Some(&StateInt::new(2))
+        );
+        assert_eq!(
+            index.next_state(&StateInt::new(1), 'z'),
+            Some(&StateInt::new(3))
+        );
+        assert_eq!(index.next_state(&StateInt::new(1), 'l'), None);
+        assert_eq!(index.next_state(&StateInt::new(2), 'a'), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlapping rules from state 1: a-k and k-z")]
+    fn test_dfa_index_overlap() {
+        DFAIndex::new(&DFARuleBook {
+            rules: vec![
+                FARule::range(StateInt::new(1), CharRange::new('k', 'z'), StateInt::new(3)),
+                FARule::range(StateInt::new(1), CharRange::new('a', 'k'), StateInt::new(2)),
+            ],
+        });
+    }
+
+    #[test]
+    fn test_nfa_index_large() {
+        // 状態が多くても、規則を状態ごとに分けてあるので規則の数に比例する時間で作れる
+        let size = 5_000;
+        let mut rules = vec![];
+        for i in 0..size {
+            rules.push(FARule::new(
+                StateInt::new(2 * i),
+                'a',
+                StateInt::new(2 * i + 1),
+            ));
+            rules.push(FARule::free(
+                StateInt::new(2 * i + 1),
+                StateInt::new(2 * i + 2),
+            ));
+        }
+        let nfa_design = NFADesign {
+            start_state: StateInt::new(0),
+            accept_states: vec![StateInt::new(2 * size)].into_iter().collect(),
+            rulebook: NFARuleBook { rules },
+        };
+        let index = NFAIndex::new(&nfa_design);
+        assert!(index.accepts(&"a".repeat(size as usize)));
+        assert!(!index.accepts(&"a".repeat(size as usize - 1)));
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+
+use crate::nfa::{CharRange, DFADesign, StateInt};
+
+// DFA が受理する文字列の集合 (言語)。列挙したり、数えたり、無作為に選んだりする。
+// 受理状態にたどり着けない状態は前もって取り除いておく
+#[derive(Clone, Debug)]
+pub struct Language {
+    // 何も受理しなければ None
+    start: Option<usize>,
+    accepting: Vec<bool>,
+    // 状態ごとに、文字の範囲の順に並べた遷移
+    transitions: Vec<Vec<(CharRange, usize)>>,
+}
+
+impl Language {
+    pub fn new(dfa_design: &DFADesign<StateInt>) -> Self {
+        let mut numbers: HashMap<&StateInt, usize> = HashMap::new();
+        let mut number = |state| {
+            let next = numbers.len();
+            *numbers.entry(state).or_insert(next)
+        };
+        let start = number(&dfa_design.start_state);
+        let edges: Vec<(usize, CharRange, usize)> = dfa_design
+            .rulebook
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                let range = rule.label().characters()?;
+                Some((number(rule.state()), range, number(rule.next_state())))
+            })
+            .collect();
+        let accept_states: Vec<usize> = dfa_design.accept_states.iter().map(number).collect();
+        let size = numbers.len();
+
+        // 受理状態から規則を逆にたどって、受理状態にたどり着ける状態を探す
+        let mut useful = vec![false; size];
+        let mut stack = accept_states.clone();
+        for &state in accept_states.iter() {
+            useful[state] = true;
+        }
+        while let Some(state) = stack.pop() {
+            for &(from, _, to) in edges.iter() {
+                if to == state && !useful[from] {
+                    useful[from] = true;
+                    stack.push(from);
+                }
+            }
+        }
+        let mut accepting = vec![false; size];
+        for state in accept_states {
+            accepting[state] = true;
+        }
+        let mut transitions = vec![vec![]; size];
+        for &(from, range, to) in edges.iter() {
+            if useful[from] && useful[to] {
+                transitions[from].push((range, to));
+            }
+        }
+        for t in transitions.iter_mut() {
+            t.sort_by_key(|&(range, _)| range);
+        }
+        Language {
+            start: Some(start).filter(|&start| useful[start]),
+            accepting,
+            transitions,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start.is_none()
+    }
+
+    // 受理状態にたどり着ける状態だけを見て、たどれる輪がなければ有限
+    pub fn is_finite(&self) -> bool {
+        self.max_length().is_some() || self.is_empty()
+    }
+
+    // 最も長い文字列の長さ。無限に長いものがあるか、何も受理しなければ None
+    pub fn max_length(&self) -> Option<usize> {
+        // 深さ優先でたどり、帰りがけに各状態から受理状態までの最長の長さを決める。
+        // たどっている途中の状態に戻ってきたら輪がある
+        #[derive(Clone, Copy)]
+        enum Mark {
+            Unvisited,
+            Visiting,
+            Done(Option<usize>),
+        }
+        fn visit(
+            language: &Language,
+            state: usize,
+            marks: &mut [Mark],
+        ) -> Result<Option<usize>, ()> {
+            marks[state] = Mark::Visiting;
+            let mut longest = if language.accepting[state] {
+                Some(0)
+            } else {
+                None
+            };
+            for &(_, next_state) in language.transitions[state].iter() {
+                let length = match marks[next_state] {
+                    Mark::Visiting => return Err(()),
+                    Mark::Done(length) => length,
+                    Mark::Unvisited => visit(language, next_state, marks)?,
+                };
+                longest = longest.max(length.map(|length| length + 1));
+            }
+            marks[state] = Mark::Done(longest);
+            Ok(longest)
+        }
+        let mut marks = vec![Mark::Unvisited; self.accepting.len()];
+        visit(self, self.start?, &mut marks).ok().flatten()
+    }
+
+    // 短い順、同じ長さなら文字の順 (shortlex) で最初の文字列
+    pub fn shortest(&self) -> Option<String> {
+        self.strings().next()
+    }
+
+    // すべての文字列を shortlex 順に返す。無限にあれば終わらない
+    pub fn strings(&self) -> Strings<'_> {
+        Strings {
+            language: self,
+            max_length: self.max_length(),
+            length: 0,
+            live: vec![self.accepting.clone()],
+            path: vec![],
+            fresh: true,
+        }
+    }
+
+    // 長さ length の文字列の数。u128 に収まらなければ None
+    pub fn count(&self, length: usize) -> Option<u128> {
+        let start = match self.start {
+            Some(start) => start,
+            None => return Some(0),
+        };
+        let mut counts: Vec<u128> = self.accepting.iter().map(|&a| a as u128).collect();
+        for _ in 0..length {
+            let mut next = vec![0u128; counts.len()];
+            for (state, transitions) in self.transitions.iter().enumerate() {
+                for &(range, next_state) in transitions.iter() {
+                    let paths = (range.size() as u128).checked_mul(counts[next_state])?;
+                    next[state] = next[state].checked_add(paths)?;
+                }
+            }
+            counts = next;
+        }
+        Some(counts[start])
+    }
+
+    // 長さ length の文字列を一様に選ぶ。なければ None。
+    // 各遷移を選ぶ重みは数の対数を f64 で持つので、一様なのは丸め誤差の範囲で
+    pub fn sample(&self, length: usize, random: &mut Random) -> Option<String> {
+        let start = self.start?;
+        // weights[k][s] は s から k 文字で受理状態に着く文字列の数の自然対数。
+        // 1つもなければ -inf。数そのものは f64 にも収まらないことがあり、
+        // 最大値で割って揃えると小さい方が 0 に潰れてしまうので対数で持つ
+        let mut weights: Vec<Vec<f64>> = vec![self
+            .accepting
+            .iter()
+            .map(|&a| if a { 0.0 } else { f64::NEG_INFINITY })
+            .collect()];
+        for k in 1..=length {
+            let next: Vec<f64> = self
+                .transitions
+                .iter()
+                .map(|transitions| {
+                    log_sum_exp(transitions.iter().map(|&(range, next_state)| {
+                        (range.size() as f64).ln() + weights[k - 1][next_state]
+                    }))
+                })
+                .collect();
+            weights.push(next);
+        }
+        if weights[length][start] == f64::NEG_INFINITY {
+            return None;
+        }
+        let mut state = start;
+        let mut string = String::new();
+        for remaining in (0..length).rev() {
+            // いまの状態から先の数 (の対数) を引いてから戻すと、重みは 1 以下の確率になる
+            let log_total = weights[remaining + 1][state];
+            let choices: Vec<(CharRange, usize, f64)> = self.transitions[state]
+                .iter()
+                .map(|&(range, next_state)| {
+                    let weight = (range.size() as f64).ln() + weights[remaining][next_state];
+                    (range, next_state, (weight - log_total).exp())
+                })
+                .filter(|&(_, _, weight)| weight > 0.0)
+                .collect();
+            let total: f64 = choices.iter().map(|&(_, _, weight)| weight).sum();
+            let mut target = random.next_f64() * total;
+            // 丸め誤差で最後まで選ばれなかったときは最後の遷移にする
+            let mut chosen = choices[choices.len() - 1];
+            for &choice in choices.iter() {
+                if target < choice.2 {
+                    chosen = choice;
+                    break;
+                }
+                target -= choice.2;
+            }
+            let (range, next_state, _) = chosen;
+            string.push(range.nth(random.below(range.size())).unwrap());
+            state = next_state;
+        }
+        Some(string)
+    }
+}
+
+// ln(Σ exp(x))。大きいものに合わせてから足すので、どれも exp であふれたり 0 に潰れたりしない
+fn log_sum_exp<I: Iterator<Item = f64>>(values: I) -> f64 {
+    let values: Vec<f64> = values.collect();
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + values.iter().map(|&x| (x - max).exp()).sum::<f64>().ln()
+}
+
+// Language::strings が返すもの。長さごとに、文字の順に深さ優先でたどる
+pub struct Strings<'a> {
+    language: &'a Language,
+    max_length: Option<usize>,
+    length: usize,
+    // live[k][s] は s からちょうど k 文字で受理状態に着けるか
+    live: Vec<Vec<bool>>,
+    // いまの文字列の各文字について、読む前の状態、遷移の位置、範囲の中での位置
+    path: Vec<(usize, usize, u32)>,
+    // length の文字列をまだ一つも返していない
+    fresh: bool,
+}
+
+impl<'a> Strings<'a> {
+    fn extend_live(&mut self, length: usize) {
+        while self.live.len() <= length {
+            let previous = &self.live[self.live.len() - 1];
+            let next = self
+                .language
+                .transitions
+                .iter()
+                .map(|transitions| transitions.iter().any(|&(_, t)| previous[t]))
+                .collect();
+            self.live.push(next);
+        }
+    }
+
+    // state から remaining 文字で受理状態に着くもののうち、最も小さい文字を選んでいく
+    fn descend(&mut self, mut state: usize, remaining: usize) {
+        for remaining in (0..remaining).rev() {
+            let transitions = &self.language.transitions[state];
+            let edge = (0..transitions.len())
+                .find(|&i| self.live[remaining][transitions[i].1])
+                .unwrap();
+            self.path.push((state, edge, 0));
+            state = transitions[edge].1;
+        }
+    }
+
+    // 同じ長さで次の文字列に進める。なければ false
+    fn advance(&mut self) -> bool {
+        while let Some((state, edge, offset)) = self.path.pop() {
+            let remaining = self.length - self.path.len() - 1;
+            let transitions = &self.language.transitions[state];
+            let (range, next_state) = transitions[edge];
+            if offset + 1 < range.size() {
+                self.path.push((state, edge, offset + 1));
+                self.descend(next_state, remaining);
+                return true;
+            }
+            let next_edge =
+                (edge + 1..transitions.len()).find(|&i| self.live[remaining][transitions[i].1]);
+            if let Some(next_edge) = next_edge {
+                self.path.push((state, next_edge, 0));
+                self.descend(transitions[next_edge].1, remaining);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn string(&self) -> String {
+        self.path
+            .iter()
+            .map(|&(state, edge, offset)| {
+                let (range, _) = self.language.transitions[state][edge];
+                range.nth(offset).unwrap()
+            })
+            .collect()
+    }
+}
+
+impl<'a> Iterator for Strings<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let start = self.language.start?;
+        loop {
+            if !self.fresh && self.advance() {
+                return Some(self.string());
+            }
+            if !self.fresh {
+                self.length += 1;
+            }
+            if self.max_length.is_some_and(|max| self.length > max) {
+                return None;
+            }
+            self.fresh = false;
+            self.extend_live(self.length);
+            if self.live[self.length][start] {
+                self.descend(start, self.length);
+                return Some(self.string());
+            }
+            self.fresh = true;
+            self.length += 1;
+        }
+    }
+}
+
+// 種から決まる疑似乱数 (SplitMix64)。同じ種からは同じ列ができる
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // 0 以上 1 未満
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // 0 以上 n 未満
+    fn below(&mut self, n: u32) -> u32 {
+        (((self.next_u64() >> 32) * n as u64) >> 32) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Language, Random};
+    use crate::regex::Regex;
+
+    fn language(regex: &str) -> Language {
+        Regex::new(regex).unwrap().language()
+    }
+
+    fn strings(regex: &str, n: usize) -> Vec<String> {
+        language(regex).strings().take(n).collect()
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(
+            strings("(a|b)*", 7),
+            vec!["", "a", "b", "aa", "ab", "ba", "bb"]
+        );
+        assert_eq!(strings("a{2}|b|", 10), vec!["", "b", "aa"]);
+        assert_eq!(strings("(aa)*", 3), vec!["", "aa", "aaaa"]);
+        assert_eq!(strings("[a-c]x|y", 10), vec!["y", "ax", "bx", "cx"]);
+        assert!(strings("[]", 10).is_empty());
+        assert!(strings("a[]", 10).is_empty());
+    }
+
+    #[test]
+    fn test_finite() {
+        assert!(language("a{1,3}|bc").is_finite());
+        assert_eq!(language("a{1,3}|bc").max_length(), Some(3));
+        assert!(!language("a*b").is_finite());
+        assert_eq!(language("a*b").max_length(), None);
+        // 受理状態に着けない輪は数えない
+        assert!(language("ab|a(c*)[]").is_finite());
+        assert!(language("[]").is_empty());
+        assert!(language("[]").is_finite());
+        assert!(!language("").is_empty());
+        assert_eq!(language("(ab)+c?").shortest(), Some("ab".to_string()));
+        assert_eq!(language("[]").shortest(), None);
+    }
+
+    #[test]
+    fn test_count() {
+        let l = language("[a-c]{2}x?");
+        assert_eq!(
+            (l.count(1), l.count(2), l.count(3), l.count(4)),
+            (Some(0), Some(9), Some(9), Some(0))
+        );
+        // 改行とサロゲートは . に入らない
+        assert_eq!(language(".").count(1), Some(0x110000 - 0x800 - 1));
+        assert_eq!(language(".*").count(7), None);
+        assert_eq!(language("[]").count(0), Some(0));
+    }
+
+    #[test]
+    fn test_sample() {
+        let regex = Regex::new("[a-z]{2,4}@(ex|example)\\.com").unwrap();
+        let language = regex.language();
+        let mut random = Random::new(42);
+        for length in (9..=11).chain(14..=16) {
+            let sample = language.sample(length, &mut random).unwrap();
+            assert_eq!(sample.chars().count(), length);
+            assert!(regex.matches(&sample), "{}", sample);
+        }
+        assert_eq!(language.sample(12, &mut random), None);
+        // 同じ種からは同じものが選ばれる
+        let sample = |seed| language.sample(15, &mut Random::new(seed));
+        assert_eq!(sample(7), sample(7));
+
+        // 8 通りの文字列がどれも選ばれる
+        let language = Regex::new("(a|b){3}").unwrap().language();
+        let mut seen: Vec<String> = (0..200)
+            .map(|_| language.sample(3, &mut random).unwrap())
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 8);
+
+        // 数が f64 に収まらないほど長くても選べる
+        let language = Regex::new(".{100}").unwrap().language();
+        let sample = language.sample(100, &mut random).unwrap();
+        assert_eq!(sample.chars().count(), 100);
+        assert!(!sample.contains('\n'));
+
+        // 枝ごとの数が何百桁も違っても、ある長さの文字列があれば必ず選べる
+        let regex = Regex::new("a{60}.{0,100}").unwrap();
+        let language = regex.language();
+        for seed in 0..20 {
+            let sample = language.sample(100, &mut Random::new(seed)).unwrap();
+            assert!(regex.matches(&sample), "{}", sample);
+        }
+        assert!(language.sample(60, &mut Random::new(1)).is_some());
+        assert_eq!(language.sample(161, &mut Random::new(1)), None);
+    }
+}
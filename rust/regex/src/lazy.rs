@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, TryLockError};
+
+use crate::index::{BitSet, NFAIndex};
+use crate::nfa::{CharRange, NFADesign, StateInt};
+
+// 1回の matches でキャッシュを MIN_FLUSHES 回以上捨て、しかも作った状態1つあたりに
+// 読めた文字が MIN_CHARACTERS_PER_STATE より少なければ、キャッシュが効いていないとみなす
+const MIN_FLUSHES: usize = 3;
+const MIN_CHARACTERS_PER_STATE: usize = 10;
+
+// 読んだ文字に応じて DFA の状態 (NFA の状態の集合) をその場で作る。
+// 作った状態と遷移は capacity 個までキャッシュし、あふれたら全部捨てて作り直す。
+// 捨ててばかりで先に進まないときは、残りを NFA のまま読む。
+// キャッシュはスレッドの間で共有する。ほかのスレッドが使っている間は待たずに NFA のまま読む
+#[derive(Debug)]
+pub struct LazyDFA {
+    index: NFAIndex<StateInt>,
+    alphabet: Vec<CharRange>,
+    capacity: usize,
+    cache: Mutex<Cache>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    states: Vec<BitSet>,
+    numbers: HashMap<BitSet, usize>,
+    // 状態ごとに、alphabet の i 番目の範囲を読んだときの行き先。None はまだ調べていない
+    transitions: Vec<Vec<Option<usize>>>,
+    flushes: usize,
+    fallbacks: usize,
+}
+
+impl Cache {
+    fn state(&mut self, states: BitSet, classes: usize) -> usize {
+        if let Some(&number) = self.numbers.get(&states) {
+            return number;
+        }
+        self.states.push(states.clone());
+        self.numbers.insert(states, self.states.len() - 1);
+        self.transitions.push(vec![None; classes]);
+        self.states.len() - 1
+    }
+
+    fn flush(&mut self) {
+        self.states.clear();
+        self.numbers.clear();
+        self.transitions.clear();
+        self.flushes += 1;
+    }
+}
+
+impl LazyDFA {
+    // capacity は 2 以上
+    pub fn new(nfa_design: &NFADesign<StateInt>, capacity: usize) -> Self {
+        assert!(capacity >= 2, "capacity must be at least 2");
+        LazyDFA {
+            index: nfa_design.index(),
+            alphabet: nfa_design.rulebook.alphabet(),
+            capacity,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    // 規則に出てこない文字は None
+    fn class(&self, character: char) -> Option<usize> {
+        let i = self
+            .alphabet
+            .partition_point(|range| range.start <= character);
+        i.checked_sub(1)
+            .filter(|&i| self.alphabet[i].contains(character))
+    }
+
+    pub fn matches(&self, string: &str) -> bool {
+        self.run(string)
+            .is_some_and(|states| self.index.accepting(&states))
+    }
+
+    // string を読み終えたときの NFA の状態の集合。途中で行き先がなくなれば None
+    pub fn run(&self, string: &str) -> Option<BitSet> {
+        let mut cache = match self.cache.try_lock() {
+            Ok(cache) => cache,
+            Err(TryLockError::WouldBlock) => {
+                return self.simulate(self.index.start().clone(), string)
+            }
+            Err(TryLockError::Poisoned(e)) => self.recover(e.into_inner()),
+        };
+        let classes = self.alphabet.len();
+        let flushes = cache.flushes;
+        let mut created = 0;
+        let mut state = cache.state(self.index.start().clone(), classes);
+        for (read, (position, character)) in string.char_indices().enumerate() {
+            let class = self.class(character)?;
+            state = match cache.transitions[state][class] {
+                Some(next_state) => next_state,
+                None => {
+                    let next_states = self.index.step(&cache.states[state], character);
+                    if cache.numbers.contains_key(&next_states) {
+                        let next_state = cache.numbers[&next_states];
+                        cache.transitions[state][class] = Some(next_state);
+                        next_state
+                    } else if cache.states.len() < self.capacity {
+                        created += 1;
+                        let next_state = cache.state(next_states, classes);
+                        cache.transitions[state][class] = Some(next_state);
+                        next_state
+                    } else {
+                        cache.flush();
+                        if cache.flushes - flushes >= MIN_FLUSHES
+                            && read + 1 < created * MIN_CHARACTERS_PER_STATE
+                        {
+                            cache.fallbacks += 1;
+                            let rest = &string[position + character.len_utf8()..];
+                            return self.simulate(next_states, rest);
+                        }
+                        created += 1;
+                        cache.state(next_states, classes)
+                    }
+                }
+            };
+            if cache.states[state].is_empty() {
+                return None;
+            }
+        }
+        Some(cache.states[state].clone())
+    }
+
+    // キャッシュを使わずに NFA の状態の集合のまま読む
+    fn simulate(&self, mut states: BitSet, string: &str) -> Option<BitSet> {
+        for character in string.chars() {
+            states = self.index.step(&states, character);
+            if states.is_empty() {
+                return None;
+            }
+        }
+        Some(states)
+    }
+
+    // run が返す集合の番号と状態の対応
+    pub fn index(&self) -> &NFAIndex<StateInt> {
+        &self.index
+    }
+
+    // キャッシュを使っている途中でほかのスレッドがパニックしたら、中身は信用せずに捨てる
+    fn recover<'a>(&'a self, mut cache: MutexGuard<'a, Cache>) -> MutexGuard<'a, Cache> {
+        cache.flush();
+        self.cache.clear_poison();
+        cache
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cache> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| self.recover(e.into_inner()))
+    }
+
+    // いまキャッシュにある状態の数
+    pub fn cached_states(&self) -> usize {
+        self.lock().states.len()
+    }
+
+    // キャッシュがあふれて NFA のまま読んだ回数
+    pub fn fallbacks(&self) -> usize {
+        self.lock().fallbacks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LazyDFA;
+    use crate::parse::parse;
+
+    fn lazy_dfa(regex: &str, capacity: usize) -> LazyDFA {
+        LazyDFA::new(&parse(regex.to_string()).unwrap().to_nfa_design(), capacity)
+    }
+
+    // a と b からなる決まった疑似乱数列
+    fn random_string(length: usize) -> String {
+        let mut x: u32 = 12345;
+        (0..length)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                if x >> 16 & 1 == 0 {
+                    'a'
+                } else {
+                    'b'
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matches() {
+        let dfa = lazy_dfa("(a|b)*abb", 100);
+        assert!(dfa.matches("abb"));
+        assert!(dfa.matches("babaabb"));
+        assert!(!dfa.matches("abba"));
+        assert!(!dfa.matches("abcabb"));
+        assert!(!dfa.matches(""));
+        // 読んだ文字で行き着いた状態だけを作る。NFA の状態の集合なので最小化はしていない
+        assert_eq!(dfa.cached_states(), 5);
+        assert_eq!(dfa.fallbacks(), 0);
+    }
+
+    #[test]
+    fn test_bounded_cache() {
+        // 後ろから 8 文字目が a。DFA は 2^8 個以上の状態になる
+        let regex = "(a|b)*a(a|b){7}";
+        let nfa_design = parse(regex.to_string()).unwrap().to_nfa_design();
+        let small = lazy_dfa(regex, 16);
+        let large = lazy_dfa(regex, 1000);
+        for length in [0, 5, 8, 9, 100, 2000].iter() {
+            let input = random_string(*length);
+            let expected = nfa_design.accepts(input.clone());
+            assert_eq!(small.matches(&input), expected, "{}", input);
+            assert_eq!(large.matches(&input), expected, "{}", input);
+            assert!(small.cached_states() <= 16);
+        }
+        assert!(small.fallbacks() > 0);
+        assert_eq!(large.fallbacks(), 0);
+        assert!(large.cached_states() > 16);
+    }
+}
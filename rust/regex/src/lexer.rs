@@ -0,0 +1,298 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use crate::index::BitSet;
+use crate::nfa::CharRange;
+use crate::parse::{parse, ParseError};
+use crate::pattern::Pattern;
+
+// (字句の種類, パターン) の規則を並べた字句解析器。規則ごとの NFA を自由移動で
+// ひとつにつなぎ、部分集合構成法で DFA にしておく。受理状態には、そこで受理する規則の
+// うち最も前にあるものの種類を付ける。入力は先頭から最長一致で区切っていく。
+// 規則は文字列全体とマッチさせるので、^ と $ は何も読まない
+#[derive(Debug)]
+pub struct Lexer<K> {
+    kinds: Vec<K>,
+    alphabet: Vec<CharRange>,
+    // 状態 0 が開始状態。状態ごとに、alphabet の i 番目の範囲を読んだときの行き先
+    transitions: Vec<Vec<Option<usize>>>,
+    // 状態ごとに、受理するなら規則の番号
+    accepts: Vec<Option<usize>>,
+}
+
+impl<K: Clone> Lexer<K> {
+    pub fn new(rules: Vec<(K, Pattern)>) -> Self {
+        let (kinds, patterns): (Vec<K>, Vec<Pattern>) = rules.into_iter().unzip();
+        let (nfa_design, rule_accepts) = Pattern::combine(&patterns);
+        let index = nfa_design.index();
+        let rule_accepts: Vec<BitSet> = rule_accepts
+            .iter()
+            .map(|states| index.to_bitset(states))
+            .collect();
+        let alphabet = nfa_design.rulebook.alphabet();
+
+        let mut states = vec![index.start().clone()];
+        let mut numbers = HashMap::new();
+        numbers.insert(index.start().clone(), 0);
+        let mut transitions = vec![];
+        let mut accepts = vec![];
+        let mut queue = VecDeque::new();
+        queue.push_back(0);
+        while let Some(state) = queue.pop_front() {
+            let row = alphabet
+                .iter()
+                .map(|range| {
+                    let next_states = index.step(&states[state], range.start);
+                    if next_states.is_empty() {
+                        return None;
+                    }
+                    let next_state = *numbers.entry(next_states.clone()).or_insert_with(|| {
+                        states.push(next_states);
+                        queue.push_back(states.len() - 1);
+                        states.len() - 1
+                    });
+                    Some(next_state)
+                })
+                .collect();
+            transitions.push(row);
+            accepts.push(
+                rule_accepts
+                    .iter()
+                    .position(|accept| !accept.is_disjoint(&states[state])),
+            );
+        }
+        Lexer {
+            kinds,
+            alphabet,
+            transitions,
+            accepts,
+        }
+    }
+
+    // 規則を正規表現で書く
+    pub fn from_regexes(rules: &[(K, &str)]) -> Result<Self, ParseError> {
+        let rules = rules
+            .iter()
+            .map(|(kind, regex)| Ok((kind.clone(), parse(regex.to_string())?)))
+            .collect::<Result<Vec<(K, Pattern)>, ParseError>>()?;
+        Ok(Lexer::new(rules))
+    }
+
+    // DFA の状態の数
+    pub fn states(&self) -> usize {
+        self.transitions.len()
+    }
+
+    // 字句と、どの規則にも合わなかった部分を前から順に返す
+    pub fn tokens<'l, 't>(&'l self, input: &'t str) -> Tokens<'l, 't, K> {
+        Tokens {
+            lexer: self,
+            input,
+            position: 0,
+        }
+    }
+
+    // 規則に出てこない文字は None
+    fn class(&self, character: char) -> Option<usize> {
+        let i = self
+            .alphabet
+            .partition_point(|range| range.start <= character);
+        i.checked_sub(1)
+            .filter(|&i| self.alphabet[i].contains(character))
+    }
+
+    // start から始まる最も長い字句の終わりと規則の番号。空の字句は返さない
+    fn longest(&self, input: &str, start: usize) -> Option<(usize, usize)> {
+        let mut state = 0;
+        let mut longest = None;
+        for (position, character) in input[start..].char_indices() {
+            state = match self
+                .class(character)
+                .and_then(|i| self.transitions[state][i])
+            {
+                Some(next_state) => next_state,
+                None => break,
+            };
+            if let Some(rule) = self.accepts[state] {
+                longest = Some((start + position + character.len_utf8(), rule));
+            }
+        }
+        longest
+    }
+}
+
+// 入力の中で規則にマッチした部分。位置はバイト単位
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token<'t, K> {
+    pub kind: K,
+    pub text: &'t str,
+    pub start: usize,
+    pub end: usize,
+}
+
+// どの規則にも合わなかった部分。次に字句が始まる位置までをまとめて1つにする
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError<'t> {
+    pub text: &'t str,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl<'t> fmt::Display for LexError<'t> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unexpected {:?} at {}", self.text, self.start)
+    }
+}
+
+impl<'t> std::error::Error for LexError<'t> {}
+
+// Lexer::tokens が返すイテレータ
+#[derive(Debug)]
+pub struct Tokens<'l, 't, K> {
+    lexer: &'l Lexer<K>,
+    input: &'t str,
+    position: usize,
+}
+
+impl<'l, 't, K: Clone> Iterator for Tokens<'l, 't, K> {
+    type Item = Result<Token<'t, K>, LexError<'t>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.position;
+        if start == self.input.len() {
+            return None;
+        }
+        if let Some((end, rule)) = self.lexer.longest(self.input, start) {
+            self.position = end;
+            return Some(Ok(Token {
+                kind: self.lexer.kinds[rule].clone(),
+                text: &self.input[start..end],
+                start,
+                end,
+            }));
+        }
+        // 読み飛ばして、また字句が始まるところで止まる
+        let end = self.input[start..]
+            .char_indices()
+            .skip(1)
+            .map(|(position, _)| start + position)
+            .find(|&position| self.lexer.longest(self.input, position).is_some())
+            .unwrap_or(self.input.len());
+        self.position = end;
+        Some(Err(LexError {
+            text: &self.input[start..end],
+            start,
+            end,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LexError, Lexer, Token};
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Kind {
+        Keyword,
+        Identifier,
+        Number,
+        Operator,
+        Space,
+    }
+    use Kind::*;
+
+    fn lexer() -> Lexer<Kind> {
+        Lexer::from_regexes(&[
+            (Keyword, "if|else|while"),
+            (Identifier, "[a-z][a-z0-9_]*"),
+            (Number, "[0-9]+"),
+            (Operator, "==|=|<|\\+|\\*"),
+            (Space, "[ \n]+"),
+        ])
+        .unwrap()
+    }
+
+    fn kinds(input: &str) -> Vec<(Kind, &str)> {
+        lexer()
+            .tokens(input)
+            .map(|token| token.unwrap())
+            .filter(|token| token.kind != Space)
+            .map(|token| (token.kind, token.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokens() {
+        // 同じ長さなら前の規則が勝ち、長く読める方が勝つ
+        assert_eq!(
+            kinds("if x == 10 else ifx"),
+            vec![
+                (Keyword, "if"),
+                (Identifier, "x"),
+                (Operator, "=="),
+                (Number, "10"),
+                (Keyword, "else"),
+                (Identifier, "ifx"),
+            ]
+        );
+        assert_eq!(
+            kinds("while x<5\n x = x*3"),
+            vec![
+                (Keyword, "while"),
+                (Identifier, "x"),
+                (Operator, "<"),
+                (Number, "5"),
+                (Identifier, "x"),
+                (Operator, "="),
+                (Identifier, "x"),
+                (Operator, "*"),
+                (Number, "3"),
+            ]
+        );
+        assert_eq!(
+            lexer().tokens("x1").next(),
+            Some(Ok(Token {
+                kind: Identifier,
+                text: "x1",
+                start: 0,
+                end: 2
+            }))
+        );
+        assert_eq!(lexer().tokens("").next(), None);
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let tokens: Vec<_> = lexer().tokens("x = ?!? 3 é").collect();
+        assert_eq!(tokens.len(), 9);
+        assert_eq!(
+            tokens[4],
+            Err(LexError {
+                text: "?!?",
+                start: 4,
+                end: 7
+            })
+        );
+        assert_eq!(tokens[6].as_ref().unwrap().kind, Number);
+        assert_eq!(
+            tokens[8],
+            Err(LexError {
+                text: "é",
+                start: 10,
+                end: 12
+            })
+        );
+        assert_eq!(
+            tokens[4].as_ref().unwrap_err().to_string(),
+            "unexpected \"?!?\" at 4"
+        );
+    }
+
+    #[test]
+    fn test_states() {
+        // 規則が増えても DFA はひとつ。空文字列しか受理しない規則は字句にならない
+        let lexer = Lexer::from_regexes(&[(Number, "()"), (Number, "[0-9]+")]).unwrap();
+        assert_eq!(lexer.states(), 2);
+        assert_eq!(lexer.tokens("12").count(), 1);
+    }
+}
@@ -1,3 +1,13 @@
+pub mod codegen;
+pub mod derivative;
+pub mod eliminate;
+pub mod index;
+pub mod language;
+pub mod lazy;
+pub mod lexer;
 pub mod nfa;
 pub mod parse;
 pub mod pattern;
+pub mod regex;
+pub mod set;
+pub mod vm;
@@ -1,14 +1,249 @@
 use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process;
 
-use regex::parse::parse;
+use regex::parse::ParseError;
+use regex::regex::{Engine, Regex};
 
+const USAGE: &str = "usage: regex [-vcno] [-H | -h] PATTERN [FILE...]";
+
+// grep と同じく、選ばれた行があれば 0、なければ 1、エラーがあれば 2 で終わる
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 3 {
-        eprintln!("too much args");
-        std::process::exit(1);
-    }
-    let regex = &args[1];
-    let pat = parse(regex.to_string());
-    println!("{}", pat.matches(String::from(&args[2])));
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("regex: {}", e);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let regex = match compile(&options.pattern) {
+        Ok(regex) => regex,
+        Err(e) => {
+            eprintln!("regex: {}", e);
+            process::exit(2);
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut selected = false;
+    let mut failed = false;
+    for file in options.files.iter() {
+        let result = if file == "-" {
+            let stdin = io::stdin();
+            let input = stdin.lock();
+            grep(&regex, &options, "(standard input)", input, &mut out)
+        } else {
+            File::open(file).and_then(|f| grep(&regex, &options, file, BufReader::new(f), &mut out))
+        };
+        match result {
+            Ok(count) => selected |= count > 0,
+            // 出力先が閉じられたら (head に渡したときなど) 黙って終わる
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+            Err(e) => {
+                eprintln!("regex: {}: {}", file, e);
+                failed = true;
+            }
+        }
+    }
+    process::exit(if failed {
+        2
+    } else if selected {
+        0
+    } else {
+        1
+    });
+}
+
+// 行を選ぶのは部分文字列を探す is_match と find_iter だけで、DFA 全体は使わない。
+// (a|b)*a(a|b){20} のように DFA の状態が爆発するパターンでもすぐに始められるよう、
+// Engine::Lazy でコンパイルする
+fn compile(pattern: &str) -> Result<Regex, ParseError> {
+    Regex::with_engine(pattern, Engine::Lazy)
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    pattern: String,
+    // 何も指定しなければ標準入力 ("-") だけ
+    files: Vec<String>,
+    // マッチしない行を選ぶ
+    invert: bool,
+    // 行の代わりに選んだ行の数を出す
+    count: bool,
+    line_number: bool,
+    // 行の代わりにマッチした部分を1行ずつ出す
+    only_matching: bool,
+    // 行の前にファイル名を付けるか。指定しなければファイルが2つ以上のとき付ける
+    with_filename: bool,
+}
+
+impl Options {
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut flags = vec![];
+        let mut operands = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                operands.extend(args.by_ref());
+            } else if arg.len() > 1 && arg.starts_with('-') {
+                flags.extend(arg.chars().skip(1));
+            } else {
+                operands.push(arg);
+            }
+        }
+        if operands.is_empty() {
+            return Err("no pattern given".to_string());
+        }
+        let pattern = operands.remove(0);
+        let files = if operands.is_empty() {
+            vec!["-".to_string()]
+        } else {
+            operands
+        };
+        let mut options = Options {
+            pattern,
+            with_filename: files.len() > 1,
+            files,
+            invert: false,
+            count: false,
+            line_number: false,
+            only_matching: false,
+        };
+        for flag in flags {
+            match flag {
+                'v' => options.invert = true,
+                'c' => options.count = true,
+                'n' => options.line_number = true,
+                'o' => options.only_matching = true,
+                'H' => options.with_filename = true,
+                'h' => options.with_filename = false,
+                _ => return Err(format!("unknown option -{}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// input を1行ずつ調べて out に書き、選んだ行の数を返す。
+// UTF-8 でないバイト列は U+FFFD に置き換えて読む
+fn grep<R: BufRead, W: Write>(
+    regex: &Regex,
+    options: &Options,
+    name: &str,
+    mut input: R,
+    out: &mut W,
+) -> io::Result<usize> {
+    let prefix = |line_number: usize| {
+        let mut prefix = String::new();
+        if options.with_filename {
+            prefix += &format!("{}:", name);
+        }
+        if options.line_number {
+            prefix += &format!("{}:", line_number);
+        }
+        prefix
+    };
+    let mut count = 0;
+    let mut buffer = vec![];
+    let mut line_number = 0;
+    while input.read_until(b'\n', &mut buffer)? > 0 {
+        line_number += 1;
+        if buffer.last() == Some(&b'\n') {
+            buffer.pop();
+        }
+        let line = String::from_utf8_lossy(&buffer);
+        if regex.is_match(&line) != options.invert {
+            count += 1;
+            if options.count {
+                // 数えるだけ
+            } else if options.only_matching {
+                // -v と一緒なら選んだ行にマッチはないので何も出さない
+                for m in regex.find_iter(&line).filter(|m| !m.as_str().is_empty()) {
+                    writeln!(out, "{}{}", prefix(line_number), m.as_str())?;
+                }
+            } else {
+                writeln!(out, "{}{}", prefix(line_number), line)?;
+            }
+        }
+        buffer.clear();
+    }
+    if options.count {
+        if options.with_filename {
+            writeln!(out, "{}:{}", name, count)?;
+        } else {
+            writeln!(out, "{}", count)?;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, grep, Options};
+
+    fn options(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn run(args: &[&str], input: &str) -> (usize, String) {
+        let options = options(args).unwrap();
+        let regex = compile(&options.pattern).unwrap();
+        let mut out = vec![];
+        let count = grep(&regex, &options, "input", input.as_bytes(), &mut out).unwrap();
+        (count, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_options() {
+        let parsed = options(&["-vn", "-c", "a+", "x.txt", "y.txt"]).unwrap();
+        assert_eq!(parsed.pattern, "a+");
+        assert_eq!(parsed.files, vec!["x.txt", "y.txt"]);
+        assert!(parsed.invert && parsed.line_number && parsed.count && parsed.with_filename);
+        assert!(!parsed.only_matching);
+
+        let parsed = options(&["-h", "--", "-a", "x.txt", "y.txt"]).unwrap();
+        assert_eq!(parsed.pattern, "-a");
+        assert!(!parsed.with_filename);
+        assert_eq!(options(&["a"]).unwrap().files, vec!["-"]);
+        assert!(options(&["-H", "a"]).unwrap().with_filename);
+        assert!(options(&[]).is_err());
+        assert!(options(&["-x", "a"]).is_err());
+    }
+
+    #[test]
+    fn test_grep() {
+        let input = "apple\nbanana\ncherry\nbanana split\n";
+        assert_eq!(
+            run(&["an"], input),
+            (2, "banana\nbanana split\n".to_string())
+        );
+        assert_eq!(
+            run(&["-v", "an"], input),
+            (2, "apple\ncherry\n".to_string())
+        );
+        assert_eq!(run(&["-c", "an"], input), (2, "2\n".to_string()));
+        assert_eq!(
+            run(&["-n", "^b"], input),
+            (2, "2:banana\n4:banana split\n".to_string())
+        );
+        assert_eq!(
+            run(&["-on", "an+"], input),
+            (2, "2:an\n2:an\n4:an\n4:an\n".to_string())
+        );
+        assert_eq!(
+            run(&["-Hc", "x"], "no match\n"),
+            (0, "input:0\n".to_string())
+        );
+        // 最後の行に改行がなくてもよい。空のマッチは -o では出さない
+        assert_eq!(run(&["-o", "p*"], "apple\nkiwi"), (2, "pp\n".to_string()));
+        // DFA にすると状態が 2^21 個になるパターンでもすぐに終わる
+        let input = format!("{}\n{}\n", "ab".repeat(20), "b".repeat(30));
+        assert_eq!(
+            run(&["-c", "(a|b)*a(a|b){20}"], &input),
+            (1, "1\n".to_string())
+        );
+    }
 }
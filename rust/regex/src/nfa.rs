@@ -1,11 +1,14 @@
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::hash::Hash;
 
+use crate::index::{BitSet, DFAIndex, NFAIndex};
+
 pub trait State {
     fn to_string(&self) -> String;
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct StateInt {
     value: i32,
 }
@@ -14,6 +17,33 @@ impl StateInt {
     pub fn new(value: i32) -> Self {
         StateInt { value }
     }
+
+    pub fn value(&self) -> i32 {
+        self.value
+    }
+}
+
+// 0 から順に重複のない状態を払い出す
+#[derive(Debug, Default)]
+pub struct StateAllocator {
+    next: i32,
+}
+
+impl StateAllocator {
+    pub fn new() -> Self {
+        StateAllocator { next: 0 }
+    }
+
+    pub fn allocate(&mut self) -> StateInt {
+        let state = StateInt::new(self.next);
+        self.next += 1;
+        state
+    }
+
+    // これまでに払い出した状態の数
+    pub fn count(&self) -> i32 {
+        self.next
+    }
 }
 
 impl State for StateInt {
@@ -22,8 +52,9 @@ impl State for StateInt {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-struct StateSet {
+// 部分集合構成法で作る DFA の状態。NFA の状態の集合
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StateSet {
     values: BTreeSet<i32>,
 }
 
@@ -39,13 +70,6 @@ impl StateSet {
         let values = states.into_iter().map(|s| s.value).collect();
         return StateSet::new(values);
     }
-    fn to_hashset(&self) -> HashSet<StateInt> {
-        let mut r = HashSet::new();
-        for v in self.values.clone().into_iter() {
-            r.insert(StateInt::new(v));
-        }
-        return r;
-    }
 }
 
 impl State for StateSet {
@@ -60,19 +84,195 @@ impl State for StateSet {
     }
 }
 
+// start から end まで (両端を含む) の文字
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CharRange {
+    pub start: char,
+    pub end: char,
+}
+
+impl CharRange {
+    pub fn new(start: char, end: char) -> Self {
+        CharRange { start, end }
+    }
+
+    pub fn single(character: char) -> Self {
+        CharRange::new(character, character)
+    }
+
+    pub fn contains(&self, character: char) -> bool {
+        self.start <= character && character <= self.end
+    }
+
+    // 範囲に入る文字の数。両端は文字なので、サロゲート (U+D800..U+DFFF) は
+    // 全部含むか一つも含まないかのどちらか
+    pub fn size(&self) -> u32 {
+        let (start, end) = (self.start as u32, self.end as u32);
+        let surrogates = if start < 0xD800 && end > 0xDFFF {
+            0x800
+        } else {
+            0
+        };
+        end - start + 1 - surrogates
+    }
+
+    // 小さい方から数えて index 番目 (0 から) の文字
+    pub fn nth(&self, index: u32) -> Option<char> {
+        let start = self.start as u32;
+        let mut value = start.checked_add(index)?;
+        if start < 0xD800 && value >= 0xD800 {
+            value = value.checked_add(0x800)?;
+        }
+        char::from_u32(value).filter(|&c| c <= self.end)
+    }
+
+    // ranges に含まれない文字を、重ならない範囲の列で返す
+    pub fn complement(ranges: &[CharRange]) -> Vec<CharRange> {
+        let mut ranges = ranges.to_vec();
+        ranges.sort();
+        let mut result = vec![];
+        let mut next = Some('\u{0}');
+        for range in ranges.iter() {
+            if let Some(start) = next {
+                if start < range.start {
+                    result.push(CharRange::new(start, previous_char(range.start).unwrap()));
+                }
+                if start <= range.end {
+                    next = next_char(range.end);
+                }
+            }
+        }
+        if let Some(start) = next {
+            result.push(CharRange::new(start, char::MAX));
+        }
+        result
+    }
+
+    // 規則に出てくる範囲を、どの規則についても全部含まれるか全く含まれないかの
+    // どちらかになるまで細かく分ける
+    pub fn partition(ranges: &[CharRange]) -> Vec<CharRange> {
+        let mut bounds: Vec<u32> = vec![];
+        for range in ranges.iter() {
+            bounds.push(range.start as u32);
+            bounds.push(range.end as u32 + 1);
+        }
+        bounds.sort();
+        bounds.dedup();
+        bounds
+            .windows(2)
+            .filter_map(|w| {
+                let start = char_at_or_after(w[0])?;
+                let end = char_at_or_before(w[1] - 1)?;
+                if start <= end && ranges.iter().any(|r| r.contains(start)) {
+                    Some(CharRange::new(start, end))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for CharRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+// サロゲートの範囲は char にならないので飛ばす
+fn char_at_or_after(value: u32) -> Option<char> {
+    if (0xd800..0xe000).contains(&value) {
+        Some('\u{e000}')
+    } else {
+        std::char::from_u32(value)
+    }
+}
+
+fn char_at_or_before(value: u32) -> Option<char> {
+    if (0xd800..0xe000).contains(&value) {
+        Some('\u{d7ff}')
+    } else {
+        std::char::from_u32(value)
+    }
+}
+
+fn next_char(c: char) -> Option<char> {
+    if c == char::MAX {
+        None
+    } else {
+        char_at_or_after(c as u32 + 1)
+    }
+}
+
+fn previous_char(c: char) -> Option<char> {
+    if c == '\u{0}' {
+        None
+    } else {
+        char_at_or_before(c as u32 - 1)
+    }
+}
+
+// 規則で進むときに読むもの。Epsilon は何も読まずに進む自由移動
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Label {
+    Epsilon,
+    Characters(CharRange),
+}
+
+impl Label {
+    pub fn contains(&self, character: char) -> bool {
+        match self {
+            Label::Epsilon => false,
+            Label::Characters(range) => range.contains(character),
+        }
+    }
+
+    pub fn characters(&self) -> Option<CharRange> {
+        match self {
+            Label::Epsilon => None,
+            Label::Characters(range) => Some(*range),
+        }
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Label::Epsilon => write!(f, "ε"),
+            Label::Characters(range) => write!(f, "{}", range),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct FARule<S: State> {
     state: S,
-    character: char,
+    label: Label,
     next_state: S,
 }
 
 #[allow(dead_code)]
 impl<S: State + Eq + Clone> FARule<S> {
     pub fn new(state: S, character: char, next_state: S) -> Self {
+        FARule::range(state, CharRange::single(character), next_state)
+    }
+
+    pub fn range(state: S, characters: CharRange, next_state: S) -> Self {
+        FARule::with_label(state, Label::Characters(characters), next_state)
+    }
+
+    pub fn free(state: S, next_state: S) -> Self {
+        FARule::with_label(state, Label::Epsilon, next_state)
+    }
+
+    pub fn with_label(state: S, label: Label, next_state: S) -> Self {
         FARule {
             state,
-            character,
+            label,
             next_state,
         }
     }
@@ -84,7 +284,7 @@ impl<S: State + Eq + Clone> FARule<S> {
     ) -> FARule<StateSet> {
         FARule {
             state: StateSet::from_hashset(state),
-            character,
+            label: Label::Characters(CharRange::single(character)),
             next_state: StateSet::from_hashset(next_state),
         }
     }
@@ -93,7 +293,7 @@ impl<S: State + Eq + Clone> FARule<S> {
         format!(
             "#<FARule #{} --#{}--> #{}>",
             self.state.to_string(),
-            self.character.to_string(),
+            self.label,
             self.next_state.to_string()
         )
     }
@@ -103,53 +303,70 @@ impl<S: State + Eq + Clone> FARule<S> {
     }
 
     fn applies_to(&self, state: &S, character: char) -> bool {
-        (&self.state == state) && (self.character == character)
+        (&self.state == state) && self.label.contains(character)
+    }
+
+    pub fn is_free_move(&self) -> bool {
+        self.label == Label::Epsilon
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn label(&self) -> Label {
+        self.label
+    }
+
+    pub fn next_state(&self) -> &S {
+        &self.next_state
     }
 }
 
-#[derive(Clone)]
-struct DFARuleBook<S: State> {
-    rules: Vec<FARule<S>>,
+#[derive(Clone, Debug, PartialEq)]
+pub struct DFARuleBook<S: State> {
+    pub rules: Vec<FARule<S>>,
 }
 
 impl<S: State + Eq + Clone> DFARuleBook<S> {
-    fn next_state(&self, state: S, character: char) -> S {
-        self.rule_for(state, character).follow()
+    // 規則がない文字を読むと行き先はない (どこにも受理されない)。
+    // 規則を毎回すべてなめるので、文字列を読ませるときは DFAIndex を使う
+    pub fn next_state(&self, state: S, character: char) -> Option<S> {
+        self.rule_for(state, character).map(|fr| fr.follow())
     }
-    fn rule_for(&self, state: S, character: char) -> FARule<S> {
-        let rules = self.rules.clone();
-        let rules: Vec<FARule<S>> = rules
-            .into_iter()
-            .filter(|fr| fr.applies_to(&state, character))
-            .collect();
-        if rules.len() > 1 {
+    fn rule_for(&self, state: S, character: char) -> Option<&FARule<S>> {
+        let mut rules = self
+            .rules
+            .iter()
+            .filter(|fr| fr.applies_to(&state, character));
+        let rule = rules.next();
+        if rules.next().is_some() {
             panic!("more than two rules are detected :(");
         }
-        return rules[0].clone();
+        rule
     }
 }
 
 #[derive(Clone)]
-struct DFA<S: State> {
-    current_state: S,
+struct DFA<S: State + Eq + Clone + Hash> {
+    current_state: Option<S>,
     accept_states: HashSet<S>,
-    rulebook: DFARuleBook<S>,
+    index: DFAIndex<S>,
 }
 
-impl<S: State + Eq + Clone> DFA<S> {
+impl<S: State + Eq + Clone + Hash> DFA<S> {
     fn accepting(&self) -> bool {
-        for s in self.accept_states.iter() {
-            if *s == self.current_state {
-                return true;
-            }
+        match &self.current_state {
+            Some(state) => self.accept_states.contains(state),
+            None => false,
         }
-        return false;
     }
 
     fn read_character(&mut self, character: char) {
-        self.current_state = self
-            .rulebook
-            .next_state(self.current_state.clone(), character)
+        self.current_state = match self.current_state.take() {
+            Some(state) => self.index.next_state(&state, character).cloned(),
+            None => None,
+        }
     }
 
     fn read_string(&mut self, string: String) -> Self {
@@ -161,25 +378,385 @@ impl<S: State + Eq + Clone> DFA<S> {
     }
 }
 
-struct DFADesign<S: State> {
-    start_state: S,
-    accept_states: HashSet<S>,
-    rulebook: DFARuleBook<S>,
+#[derive(Clone, Debug)]
+pub struct DFADesign<S: State> {
+    pub start_state: S,
+    pub accept_states: HashSet<S>,
+    pub rulebook: DFARuleBook<S>,
 }
 
-impl<S: State + Clone + Eq> DFADesign<S> {
+impl<S: State + Clone + Eq + Hash> DFADesign<S> {
     fn to_dfa(&self) -> DFA<S> {
         return DFA {
-            current_state: self.start_state.clone(),
+            current_state: Some(self.start_state.clone()),
             accept_states: self.accept_states.clone(),
-            rulebook: self.rulebook.clone(),
+            index: DFAIndex::new(&self.rulebook),
         };
     }
 
-    fn accepts(&self, string: String) -> bool {
+    pub fn accepts(&self, string: String) -> bool {
         let mut dfa = self.to_dfa();
         dfa.read_string(string).accepting()
     }
+
+    // 開始状態から幅優先でたどった順に 0, 1, 2, ... と番号を付け直す。
+    // 同じ状態から出る規則は文字の順にたどる
+    pub fn renumber(&self) -> DFADesign<StateInt> {
+        let mut outgoing: HashMap<&S, Vec<&FARule<S>>> = HashMap::new();
+        for rule in self.rulebook.rules.iter() {
+            outgoing.entry(&rule.state).or_default().push(rule);
+        }
+        for rules in outgoing.values_mut() {
+            rules.sort_by_key(|fr| fr.label);
+        }
+        let mut numbers: HashMap<&S, i32> = HashMap::new();
+        let mut queue = VecDeque::new();
+        numbers.insert(&self.start_state, 0);
+        queue.push_back(&self.start_state);
+        let mut rules = vec![];
+        while let Some(state) = queue.pop_front() {
+            for rule in outgoing.get(state).into_iter().flatten() {
+                if !numbers.contains_key(&rule.next_state) {
+                    numbers.insert(&rule.next_state, numbers.len() as i32);
+                    queue.push_back(&rule.next_state);
+                }
+                rules.push(FARule::with_label(
+                    StateInt::new(numbers[state]),
+                    rule.label,
+                    StateInt::new(numbers[&rule.next_state]),
+                ));
+            }
+        }
+        let accept_states = self
+            .accept_states
+            .iter()
+            .filter_map(|s| numbers.get(s))
+            .map(|&n| StateInt::new(n))
+            .collect();
+        DFADesign {
+            start_state: StateInt::new(0),
+            accept_states,
+            rulebook: DFARuleBook { rules },
+        }
+    }
+
+    // Hopcroft の分割の細分化で状態数を最小にする。行き先が同じで隣り合う範囲はまとめ、
+    // renumber で番号を付け直すので、同じ言語を受理する DFA からは同じものができる
+    pub fn minimize(&self) -> DFADesign<StateInt> {
+        let dfa = self.renumber();
+        let mut size = 1;
+        for rule in dfa.rulebook.rules.iter() {
+            size = size.max(rule.next_state.value() as usize + 1);
+        }
+        // 規則のない遷移はすべて、どこも受理しない状態 dead に向ける
+        let dead = size;
+        let ranges: Vec<CharRange> = dfa
+            .rulebook
+            .rules
+            .iter()
+            .filter_map(|fr| fr.label.characters())
+            .collect();
+        let symbols = CharRange::partition(&ranges);
+        let mut delta = vec![vec![dead; symbols.len()]; size + 1];
+        for rule in dfa.rulebook.rules.iter() {
+            for (i, symbol) in symbols.iter().enumerate() {
+                if rule.label.contains(symbol.start) {
+                    delta[rule.state.value() as usize][i] = rule.next_state.value() as usize;
+                }
+            }
+        }
+        // inverse[i][t] は記号 i で t に移る状態
+        let mut inverse = vec![vec![vec![]; size + 1]; symbols.len()];
+        for (s, row) in delta.iter().enumerate() {
+            for (i, &t) in row.iter().enumerate() {
+                inverse[i][t].push(s);
+            }
+        }
+        let accepting: Vec<bool> = (0..=size)
+            .map(|s| s < size && dfa.accept_states.contains(&StateInt::new(s as i32)))
+            .collect();
+        let (accept, reject): (Vec<usize>, Vec<usize>) = (0..=size).partition(|&s| accepting[s]);
+        let mut blocks: Vec<Vec<usize>> = vec![];
+        let mut block_of = vec![0; size + 1];
+        for block in [accept, reject].iter().filter(|block| !block.is_empty()) {
+            for &s in block.iter() {
+                block_of[s] = blocks.len();
+            }
+            blocks.push(block.clone());
+        }
+
+        let mut waiting: Vec<usize> = (0..blocks.len()).collect();
+        let mut is_waiting = vec![true; blocks.len()];
+        while let Some(splitter) = waiting.pop() {
+            is_waiting[splitter] = false;
+            let splitter = blocks[splitter].clone();
+            for inverse in inverse.iter() {
+                // splitter に移る状態を、いま属しているブロックごとに集める
+                let mut moved: BTreeMap<usize, HashSet<usize>> = BTreeMap::new();
+                for &t in splitter.iter() {
+                    for &s in inverse[t].iter() {
+                        moved.entry(block_of[s]).or_default().insert(s);
+                    }
+                }
+                for (b, moved) in moved {
+                    if moved.len() == blocks[b].len() {
+                        continue;
+                    }
+                    let (inside, outside): (Vec<usize>, Vec<usize>) =
+                        blocks[b].iter().partition(|s| moved.contains(s));
+                    let new = blocks.len();
+                    for &s in inside.iter() {
+                        block_of[s] = new;
+                    }
+                    blocks[b] = outside;
+                    blocks.push(inside);
+                    is_waiting.push(false);
+                    // 待っていないなら小さい方だけを調べればよい
+                    let next = if is_waiting[b] || blocks[new].len() < blocks[b].len() {
+                        new
+                    } else {
+                        b
+                    };
+                    waiting.push(next);
+                    is_waiting[next] = true;
+                }
+            }
+        }
+
+        let dead = block_of[dead];
+        let mut rules = vec![];
+        for (b, block) in blocks.iter().enumerate().filter(|&(b, _)| b != dead) {
+            let mut merged: Vec<(CharRange, usize)> = vec![];
+            for (i, symbol) in symbols.iter().enumerate() {
+                let target = block_of[delta[block[0]][i]];
+                if target == dead {
+                    continue;
+                }
+                match merged.last_mut() {
+                    Some((range, t))
+                        if *t == target && next_char(range.end) == Some(symbol.start) =>
+                    {
+                        range.end = symbol.end
+                    }
+                    _ => merged.push((*symbol, target)),
+                }
+            }
+            rules.extend(merged.into_iter().map(|(range, t)| {
+                FARule::range(StateInt::new(b as i32), range, StateInt::new(t as i32))
+            }));
+        }
+        let accept_states = blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| accepting[block[0]])
+            .map(|(b, _)| StateInt::new(b as i32))
+            .collect();
+        DFADesign {
+            start_state: StateInt::new(block_of[0] as i32),
+            accept_states,
+            rulebook: DFARuleBook { rules },
+        }
+        .renumber()
+    }
+}
+
+impl<S: State + Clone + Eq + Hash> DFADesign<S> {
+    // 両方にマッチする文字列
+    pub fn intersection<T>(&self, other: &DFADesign<T>) -> DFADesign<StateInt>
+    where
+        T: State + Clone + Eq + Hash,
+    {
+        self.product(other, |a, b| a && b)
+    }
+
+    // どちらかにマッチする文字列
+    pub fn union<T>(&self, other: &DFADesign<T>) -> DFADesign<StateInt>
+    where
+        T: State + Clone + Eq + Hash,
+    {
+        self.product(other, |a, b| a || b)
+    }
+
+    // self にマッチして other にはマッチしない文字列
+    pub fn difference<T>(&self, other: &DFADesign<T>) -> DFADesign<StateInt>
+    where
+        T: State + Clone + Eq + Hash,
+    {
+        self.product(other, |a, b| a && !b)
+    }
+
+    // self にマッチしないすべての文字列
+    pub fn complement(&self) -> DFADesign<StateInt> {
+        self.product(self, |a, _| !a)
+    }
+
+    // 二つの DFA の状態の組を状態とする DFA を作り、最小化して返す。
+    // 規則のない遷移で行き着く状態を None とし、組 (None, None) が受理状態になるときは
+    // 規則に出てこない文字も含めたすべての文字について遷移を持つ死んだ状態として明示する
+    fn product<T, F>(&self, other: &DFADesign<T>, accepting: F) -> DFADesign<StateInt>
+    where
+        T: State + Clone + Eq + Hash,
+        F: Fn(bool, bool) -> bool,
+    {
+        let ranges: Vec<CharRange> = self
+            .rulebook
+            .rules
+            .iter()
+            .filter_map(|fr| fr.label.characters())
+            .chain(
+                other
+                    .rulebook
+                    .rules
+                    .iter()
+                    .filter_map(|fr| fr.label.characters()),
+            )
+            .collect();
+        let mut alphabet = CharRange::partition(&ranges);
+        let dead_accepts = accepting(false, false);
+        if dead_accepts {
+            alphabet.extend(CharRange::complement(&ranges));
+            alphabet.sort();
+        }
+
+        let (index, other_index) = (
+            DFAIndex::new(&self.rulebook),
+            DFAIndex::new(&other.rulebook),
+        );
+        let start = (
+            Some(self.start_state.clone()),
+            Some(other.start_state.clone()),
+        );
+        let mut numbers: HashMap<(Option<S>, Option<T>), i32> = HashMap::new();
+        numbers.insert(start.clone(), 0);
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        let mut rules = vec![];
+        let mut accept_states = HashSet::new();
+        while let Some((first, second)) = queue.pop_front() {
+            let state = numbers[&(first.clone(), second.clone())];
+            let accepts = (
+                first
+                    .as_ref()
+                    .is_some_and(|s| self.accept_states.contains(s)),
+                second
+                    .as_ref()
+                    .is_some_and(|s| other.accept_states.contains(s)),
+            );
+            if accepting(accepts.0, accepts.1) {
+                accept_states.insert(StateInt::new(state));
+            }
+            for characters in alphabet.iter() {
+                let target = (
+                    first
+                        .as_ref()
+                        .and_then(|s| index.next_state(s, characters.start))
+                        .cloned(),
+                    second
+                        .as_ref()
+                        .and_then(|s| other_index.next_state(s, characters.start))
+                        .cloned(),
+                );
+                if target == (None, None) && !dead_accepts {
+                    continue;
+                }
+                if !numbers.contains_key(&target) {
+                    numbers.insert(target.clone(), numbers.len() as i32);
+                    queue.push_back(target.clone());
+                }
+                rules.push(FARule::range(
+                    StateInt::new(state),
+                    *characters,
+                    StateInt::new(numbers[&target]),
+                ));
+            }
+        }
+        DFADesign {
+            start_state: StateInt::new(0),
+            accept_states,
+            rulebook: DFARuleBook { rules },
+        }
+        .minimize()
+    }
+}
+
+impl DFADesign<StateInt> {
+    // 二つの DFA を同時に動かし、受理するかどうかの組が wanted を満たす最短の文字列を探す。
+    // 幅優先で、同じ長さなら文字の小さい方から調べる。規則のない遷移は受理しない状態とみなす
+    pub fn shortest_string_where<F>(&self, other: &DFADesign<StateInt>, wanted: F) -> Option<String>
+    where
+        F: Fn(bool, bool) -> bool,
+    {
+        let ranges: Vec<CharRange> = self
+            .rulebook
+            .rules
+            .iter()
+            .chain(other.rulebook.rules.iter())
+            .filter_map(|fr| fr.label.characters())
+            .collect();
+        let symbols = CharRange::partition(&ranges);
+        let accepting = |design: &DFADesign<StateInt>, state: &Option<StateInt>| match state {
+            Some(state) => design.accept_states.contains(state),
+            None => false,
+        };
+        let (index, other_index) = (
+            DFAIndex::new(&self.rulebook),
+            DFAIndex::new(&other.rulebook),
+        );
+        let next = |index: &DFAIndex<StateInt>, state: &Option<StateInt>, character: char| {
+            state
+                .as_ref()
+                .and_then(|state| index.next_state(state, character))
+                .cloned()
+        };
+
+        let start = (
+            Some(self.start_state.clone()),
+            Some(other.start_state.clone()),
+        );
+        let mut previous: HashMap<(Option<StateInt>, Option<StateInt>), Option<_>> = HashMap::new();
+        previous.insert(start.clone(), None);
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        while let Some(pair) = queue.pop_front() {
+            if wanted(accepting(self, &pair.0), accepting(other, &pair.1)) {
+                let mut characters = vec![];
+                let mut current = &pair;
+                while let Some((before, character)) = &previous[current] {
+                    characters.push(*character);
+                    current = before;
+                }
+                return Some(characters.into_iter().rev().collect());
+            }
+            for symbol in symbols.iter() {
+                let target = (
+                    next(&index, &pair.0, symbol.start),
+                    next(&other_index, &pair.1, symbol.start),
+                );
+                if target == (None, None) || previous.contains_key(&target) {
+                    continue;
+                }
+                previous.insert(target.clone(), Some((pair.clone(), symbol.start)));
+                queue.push_back(target);
+            }
+        }
+        None
+    }
+}
+
+impl<S: State> DFADesign<S> {
+    // Graphviz の dot 形式で書き出す
+    pub fn to_dot(&self) -> String {
+        to_dot(&self.start_state, &self.accept_states, &self.rulebook.rules)
+    }
+}
+
+// 受理状態の集合は順序によらず、規則は並び順まで比べる
+impl<S: State + Eq + Hash> PartialEq for DFADesign<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.start_state == other.start_state
+            && self.accept_states == other.accept_states
+            && self.rulebook == other.rulebook
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -187,8 +764,10 @@ pub struct NFARuleBook<S: State> {
     pub rules: Vec<FARule<S>>,
 }
 
+// 規則を毎回すべてなめるので、一度だけ引くとき用。
+// 文字列を読ませるときは NFAIndex を使う
 impl<S: State + Eq + Clone + Hash> NFARuleBook<S> {
-    fn next_states(&self, states: &HashSet<S>, character: char) -> HashSet<S> {
+    pub fn next_states(&self, states: &HashSet<S>, character: char) -> HashSet<S> {
         let mut r = HashSet::new();
         for state in states.into_iter() {
             for next_state in self.follow_rules_for(state, character).into_iter() {
@@ -205,18 +784,18 @@ impl<S: State + Eq + Clone + Hash> NFARuleBook<S> {
             .collect()
     }
 
-    fn follow_free_moves(&self, states: &HashSet<S>) -> HashSet<S> {
-        let more_states = self.next_states(states, '\u{029e}');
-        if more_states.is_subset(states) {
-            return states.clone();
-        } else {
-            let new_states: HashSet<S> = states
-                .union(&more_states)
-                .into_iter()
-                .map(|e| e.clone())
-                .collect();
-            return self.follow_free_moves(&new_states);
+    // 新しく加わった状態から出る自由移動だけをたどる
+    pub fn follow_free_moves(&self, states: &HashSet<S>) -> HashSet<S> {
+        let mut result = states.clone();
+        let mut stack: Vec<&S> = states.iter().collect();
+        while let Some(state) = stack.pop() {
+            for rule in self.rules.iter() {
+                if rule.is_free_move() && rule.state == *state && result.insert(rule.follow()) {
+                    stack.push(&rule.next_state);
+                }
+            }
         }
+        result
     }
 
     fn rules_for(&self, state: &S, character: char) -> Vec<&FARule<S>> {
@@ -228,91 +807,169 @@ impl<S: State + Eq + Clone + Hash> NFARuleBook<S> {
         return filtered;
     }
 
-    fn alphabet(&self) -> Vec<char> {
-        let mut uniq: Vec<char> = self
+    pub fn alphabet(&self) -> Vec<CharRange> {
+        let ranges: Vec<CharRange> = self
             .rules
             .iter()
-            .map(|fr| fr.character)
-            .filter(|&c| c != '\u{029e}')
+            .filter_map(|fr| fr.label.characters())
             .collect();
-        uniq.sort();
-        uniq.dedup();
-        return uniq;
+        CharRange::partition(&ranges)
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-struct NFA<S: State + Eq + Clone + Hash> {
-    current_states: HashSet<S>,
-    accept_states: HashSet<S>,
-    rulebook: NFARuleBook<S>,
+#[derive(Clone, Debug)]
+pub struct NFADesign<S: State + Eq + Clone + Hash> {
+    pub start_state: S,
+    pub accept_states: HashSet<S>,
+    pub rulebook: NFARuleBook<S>,
 }
 
-impl<S: State + Eq + Clone + Hash> NFA<S> {
-    fn accepting(&self) -> bool {
-        let cs = self.get_current_states();
-        !cs.is_disjoint(&self.accept_states)
+impl<S: State + Eq + Clone + Hash> NFADesign<S> {
+    pub fn accepts(&self, string: String) -> bool {
+        self.index().accepts(&string)
     }
 
-    fn read_character(&mut self, character: char) {
-        let cs = self.get_current_states();
-        self.current_states = self.rulebook.next_states(&cs, character)
+    // 状態と文字から行き先を引ける形にしたもの。何度も動かすときはこれを使い回す
+    pub fn index(&self) -> NFAIndex<S> {
+        NFAIndex::new(self)
     }
 
-    fn read_string(&mut self, string: String) -> Self {
-        let chars: Vec<char> = string.chars().collect();
-        for char in chars {
-            self.read_character(char)
-        }
-        self.clone()
+    // Graphviz の dot 形式で書き出す。自由移動は ε と書く
+    pub fn to_dot(&self) -> String {
+        to_dot(&self.start_state, &self.accept_states, &self.rulebook.rules)
     }
+}
 
-    fn get_current_states(&self) -> HashSet<S> {
-        self.rulebook.follow_free_moves(&self.current_states)
+// 状態は State::to_string の名前で書く。同じ2状態の間の規則は1本の辺にまとめ、
+// 文字の範囲は [a-cx] のような集合にする。出力が毎回同じになるよう、
+// 受理状態は名前の順、辺は規則に最初に出てきた順に並べる
+fn to_dot<S: State>(start_state: &S, accept_states: &HashSet<S>, rules: &[FARule<S>]) -> String {
+    let mut dot = String::from("digraph {\n  rankdir=LR;\n  node [shape=circle];\n");
+    dot += "  \"\" [shape=none];\n";
+    dot += &format!("  \"\" -> {};\n", dot_id(&start_state.to_string()));
+    let mut accept_states: Vec<String> = accept_states.iter().map(|s| s.to_string()).collect();
+    accept_states.sort();
+    for state in accept_states.iter() {
+        dot += &format!("  {} [shape=doublecircle];\n", dot_id(state));
     }
-}
 
-#[derive(Debug)]
-pub struct NFADesign<S: State + Eq + Clone + Hash> {
-    pub start_state: S,
-    pub accept_states: HashSet<S>,
-    pub rulebook: NFARuleBook<S>,
+    let mut edges: Vec<((String, String), Vec<Label>)> = vec![];
+    let mut numbers: HashMap<(String, String), usize> = HashMap::new();
+    for rule in rules.iter() {
+        let key = (rule.state.to_string(), rule.next_state.to_string());
+        let i = *numbers.entry(key.clone()).or_insert_with(|| {
+            edges.push((key, vec![]));
+            edges.len() - 1
+        });
+        edges[i].1.push(rule.label);
+    }
+    for ((state, next_state), labels) in edges.iter() {
+        dot += &format!(
+            "  {} -> {} [label={}];\n",
+            dot_id(state),
+            dot_id(next_state),
+            dot_id(&dot_label(labels))
+        );
+    }
+    dot += "}\n";
+    dot
 }
 
-impl<S: State + Eq + Clone + Hash> NFADesign<S> {
-    fn to_nfa(&self, current_states: HashSet<S>) -> NFA<S> {
-        NFA {
-            current_states,
-            accept_states: self.accept_states.clone(),
-            rulebook: self.rulebook.clone(),
+// 重なったり隣り合ったりする範囲はつなげる
+fn dot_label(labels: &[Label]) -> String {
+    let mut ranges: Vec<CharRange> = labels.iter().filter_map(|l| l.characters()).collect();
+    ranges.sort();
+    let mut merged: Vec<CharRange> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if next_char(last.end).is_none_or(|c| c >= range.start) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
         }
     }
-
-    fn to_nfa_init(&self) -> NFA<S> {
-        let mut cs = HashSet::new();
-        cs.insert(self.start_state.clone());
-        NFA {
-            current_states: cs,
-            accept_states: self.accept_states.clone(),
-            rulebook: self.rulebook.clone(),
+    // 改行などの制御文字は \n のように書く
+    let escape = |c: char| {
+        if c.is_control() {
+            c.escape_debug().to_string()
+        } else {
+            c.to_string()
         }
+    };
+    let characters: Vec<String> = merged
+        .iter()
+        .map(|range| {
+            if range.start == range.end {
+                escape(range.start)
+            } else {
+                format!("{}-{}", escape(range.start), escape(range.end))
+            }
+        })
+        .collect();
+    let mut parts = vec![];
+    if labels.contains(&Label::Epsilon) {
+        parts.push(Label::Epsilon.to_string());
     }
+    match merged.as_slice() {
+        [] => {}
+        [range] if range.start == range.end => parts.push(characters.concat()),
+        _ => parts.push(format!("[{}]", characters.concat())),
+    }
+    parts.join(", ")
+}
 
-    pub fn accepts(&self, string: String) -> bool {
-        let mut nfa = self.to_nfa_init();
-        nfa.read_string(string).accepting()
+fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl NFADesign<StateInt> {
+    // 部分集合構成法で同じ言語を受理する DFA を作る
+    pub fn to_dfa_design(&self) -> DFADesign<StateSet> {
+        NFASimulation::new(self.clone()).to_dfa_design()
+    }
+
+    // 状態と規則を決まった順に並べた文字列。スナップショットテスト用
+    pub fn inspect(&self) -> String {
+        let mut accept_states: Vec<&StateInt> = self.accept_states.iter().collect();
+        accept_states.sort();
+        let accept_states: Vec<String> = accept_states.iter().map(|s| s.to_string()).collect();
+        let mut s = format!(
+            "#<NFADesign start: {}, accept: [{}]>\n",
+            self.start_state.to_string(),
+            accept_states.join(", ")
+        );
+        for rule in self.rulebook.rules.iter() {
+            s += &format!("  {}\n", rule.inspect());
+        }
+        s
     }
 }
 
 struct NFASimulation {
     nfa_design: NFADesign<StateInt>,
+    index: NFAIndex<StateInt>,
 }
 
 impl NFASimulation {
+    fn new(nfa_design: NFADesign<StateInt>) -> Self {
+        let index = nfa_design.index();
+        NFASimulation { nfa_design, index }
+    }
+
+    fn to_bitset(&self, state: &StateSet) -> BitSet {
+        let states: Vec<StateInt> = state.values.iter().map(|&v| StateInt::new(v)).collect();
+        self.index.closure(&self.index.to_bitset(states.iter()))
+    }
+
+    fn to_state_set(&self, states: &BitSet) -> StateSet {
+        StateSet {
+            values: self.index.states(states).map(|s| s.value).collect(),
+        }
+    }
+
     fn next_state(&self, state: StateSet, character: char) -> StateSet {
-        let mut nfa = self.nfa_design.to_nfa(state.to_hashset());
-        nfa.read_character(character);
-        return StateSet::from_hashset(nfa.get_current_states());
+        let states = self.to_bitset(&state);
+        self.to_state_set(&self.index.step(&states, character))
     }
 
     fn rules_for(&self, state: StateSet) -> HashSet<FARule<StateSet>> {
@@ -320,66 +977,85 @@ impl NFASimulation {
         for c in self.nfa_design.rulebook.alphabet().into_iter() {
             r.insert(FARule {
                 state: state.clone(),
-                character: c,
-                next_state: self.next_state(state.clone(), c),
+                label: Label::Characters(c),
+                next_state: self.next_state(state.clone(), c.start),
             });
         }
         return r;
     }
 
+    // 見つかった状態から出る規則だけを調べ、新しい状態がなくなるまで繰り返す
     fn discover_states_and_rules(
         &self,
         states: HashSet<StateSet>,
     ) -> (HashSet<StateSet>, HashSet<FARule<StateSet>>) {
+        let mut queue: VecDeque<StateSet> = states.iter().cloned().collect();
+        let mut states = states;
         let mut rules = HashSet::new();
-        for state in states.clone().into_iter() {
-            rules.extend(self.rules_for(state));
-        }
-        let mut more_states = HashSet::new();
-        for rule in rules.iter() {
-            more_states.insert(rule.follow());
-        }
-        if more_states.is_subset(&states) {
-            return (states, rules);
-        } else {
-            let mut new_states = HashSet::new();
-            new_states.extend(states);
-            new_states.extend(more_states);
-            return self.discover_states_and_rules(new_states);
+        while let Some(state) = queue.pop_front() {
+            for rule in self.rules_for(state).into_iter() {
+                if states.insert(rule.follow()) {
+                    queue.push_back(rule.follow());
+                }
+                rules.insert(rule);
+            }
         }
+        (states, rules)
     }
 
     fn to_dfa_design(&self) -> DFADesign<StateSet> {
-        let start_state =
-            StateSet::from_hashset(self.nfa_design.to_nfa_init().get_current_states());
+        let start_state = self.to_state_set(self.index.start());
         let mut initial_state = HashSet::new();
         initial_state.insert(start_state.clone());
         let (states, rules) = self.discover_states_and_rules(initial_state);
-        let mut r = vec![];
-        for s in rules.into_iter() {
-            r.push(s);
-        }
         let accept_states = states
-            .clone()
             .into_iter()
-            .filter(|s| self.nfa_design.to_nfa(s.to_hashset()).accepting())
+            .filter(|s| self.index.accepting(&self.to_bitset(s)))
             .collect();
 
         return DFADesign {
             start_state,
             accept_states,
-            rulebook: DFARuleBook { rules: r },
+            rulebook: DFARuleBook {
+                rules: rules.into_iter().collect(),
+            },
         };
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{DFARuleBook, FARule, NFARuleBook};
-    use super::{NFADesign, NFASimulation, NFA};
+    use super::{CharRange, DFADesign, DFARuleBook, FARule, Label, NFARuleBook};
+    use super::{NFADesign, NFASimulation};
     use super::{State, StateInt, StateSet};
+    use crate::index::BitSet;
+    use crate::parse::parse;
     use std::collections::HashSet;
 
+    #[test]
+    fn test_char_range() {
+        let r = |a, b| CharRange::new(a, b);
+        assert_eq!(
+            CharRange::partition(&[r('a', 'z'), r('m', 'm'), r('0', '9')]),
+            vec![r('0', '9'), r('a', 'l'), r('m', 'm'), r('n', 'z')]
+        );
+        assert_eq!(
+            CharRange::complement(&[r('b', 'c'), r('a', 'a'), r('x', char::MAX)]),
+            vec![r('\u{0}', '`'), r('d', 'w')]
+        );
+        assert_eq!(
+            CharRange::complement(&[r('\u{0}', '\u{d7ff}')]),
+            vec![r('\u{e000}', char::MAX)]
+        );
+        assert_eq!(CharRange::complement(&[]), vec![r('\u{0}', char::MAX)]);
+        // サロゲートは数えず、飛ばして数える
+        assert_eq!(r('a', 'c').size(), 3);
+        assert_eq!(r('\u{d7ff}', '\u{e000}').size(), 2);
+        assert_eq!(r('\u{d7ff}', '\u{e000}').nth(1), Some('\u{e000}'));
+        assert_eq!(r('a', 'c').nth(2), Some('c'));
+        assert_eq!(r('a', 'c').nth(3), None);
+    }
+
     #[test]
     fn test_stateint() {
         let si = StateInt { value: 10 };
@@ -398,9 +1074,19 @@ mod tests {
                 FARule::new(StateInt::new(3), 'b', StateInt::new(3)),
             ],
         };
-        assert_eq!(rulebook.next_state(StateInt::new(1), 'a'), StateInt::new(2));
-        assert_eq!(rulebook.next_state(StateInt::new(1), 'b'), StateInt::new(1));
-        assert_eq!(rulebook.next_state(StateInt::new(2), 'b'), StateInt::new(3));
+        assert_eq!(
+            rulebook.next_state(StateInt::new(1), 'a'),
+            Some(StateInt::new(2))
+        );
+        assert_eq!(
+            rulebook.next_state(StateInt::new(1), 'b'),
+            Some(StateInt::new(1))
+        );
+        assert_eq!(
+            rulebook.next_state(StateInt::new(2), 'b'),
+            Some(StateInt::new(3))
+        );
+        assert_eq!(rulebook.next_state(StateInt::new(1), 'c'), None);
     }
 
     #[test]
@@ -417,15 +1103,15 @@ mod tests {
         };
         assert_eq!(
             rulebook.next_state(StateSet::new(vec![1, 2]), 'a'),
-            StateSet::new(vec![2, 3])
+            Some(StateSet::new(vec![2, 3]))
         );
         assert_eq!(
             rulebook.next_state(StateSet::new(vec![1, 2]), 'b'),
-            StateSet::new(vec![1, 2])
+            Some(StateSet::new(vec![1, 2]))
         );
         assert_eq!(
             rulebook.next_state(StateSet::new(vec![2, 3]), 'b'),
-            StateSet::new(vec![3, 4])
+            Some(StateSet::new(vec![3, 4]))
         );
     }
 
@@ -522,18 +1208,20 @@ mod tests {
                 FARule::new(StateInt::new(3), 'b', StateInt::new(4)),
             ],
         };
-        let mut nfa = NFA {
-            current_states: cs,
+        let index = NFADesign {
+            start_state: cs.into_iter().next().unwrap(),
             accept_states: acs,
             rulebook,
-        };
-        assert!(!nfa.accepting());
-        nfa.read_character('b');
-        assert!(!nfa.accepting());
-        nfa.read_character('a');
-        assert!(!nfa.accepting());
-        nfa.read_character('b');
-        assert!(nfa.accepting());
+        }
+        .index();
+        let mut states = index.start().clone();
+        assert!(!index.accepting(&states));
+        states = index.step(&states, 'b');
+        assert!(!index.accepting(&states));
+        states = index.step(&states, 'a');
+        assert!(!index.accepting(&states));
+        states = index.step(&states, 'b');
+        assert!(index.accepting(&states));
 
         let mut cs = HashSet::new();
         cs.insert(StateInt::new(1));
@@ -550,21 +1238,21 @@ mod tests {
                 FARule::new(StateInt::new(3), 'b', StateInt::new(4)),
             ],
         };
-        let mut nfa = NFA {
-            current_states: cs,
+        let index = NFADesign {
+            start_state: cs.into_iter().next().unwrap(),
             accept_states: acs,
             rulebook,
-        };
-        nfa.read_string(String::from("bbbbb"));
-        assert!(nfa.accepting());
+        }
+        .index();
+        assert!(index.accepts("bbbbb"));
     }
 
     #[test]
     fn test_follow_free_moves_int() {
         let rulebook = NFARuleBook {
             rules: vec![
-                FARule::new(StateInt::new(1), '\u{029e}', StateInt::new(2)),
-                FARule::new(StateInt::new(1), '\u{029e}', StateInt::new(4)),
+                FARule::free(StateInt::new(1), StateInt::new(2)),
+                FARule::free(StateInt::new(1), StateInt::new(4)),
                 FARule::new(StateInt::new(2), 'a', StateInt::new(3)),
                 FARule::new(StateInt::new(3), 'a', StateInt::new(2)),
                 FARule::new(StateInt::new(4), 'a', StateInt::new(5)),
@@ -608,34 +1296,28 @@ mod tests {
                 FARule::new(StateSet::new(vec![3, 4]), 'b', StateSet::new(vec![4, 5])),
             ],
         };
-        let mut nfa = NFA {
-            current_states: cs,
+        let index = NFADesign {
+            start_state: cs.into_iter().next().unwrap(),
             accept_states: acs,
             rulebook,
-        };
-        assert!(!nfa.accepting());
-        nfa.read_character('b');
-        assert!(!nfa.accepting());
-        nfa.read_character('a');
-        assert!(!nfa.accepting());
-        nfa.read_character('b');
-        assert!(nfa.accepting());
+        }
+        .index();
+        let mut states = index.start().clone();
+        assert!(!index.accepting(&states));
+        states = index.step(&states, 'b');
+        assert!(!index.accepting(&states));
+        states = index.step(&states, 'a');
+        assert!(!index.accepting(&states));
+        states = index.step(&states, 'b');
+        assert!(index.accepting(&states));
     }
 
     #[test]
     fn test_follow_free_moves_set() {
         let rulebook = NFARuleBook {
             rules: vec![
-                FARule::new(
-                    StateSet::new(vec![1, 2]),
-                    '\u{029e}',
-                    StateSet::new(vec![2, 3]),
-                ),
-                FARule::new(
-                    StateSet::new(vec![1, 2]),
-                    '\u{029e}',
-                    StateSet::new(vec![4, 5]),
-                ),
+                FARule::free(StateSet::new(vec![1, 2]), StateSet::new(vec![2, 3])),
+                FARule::free(StateSet::new(vec![1, 2]), StateSet::new(vec![4, 5])),
                 FARule::new(StateSet::new(vec![2, 3]), 'a', StateSet::new(vec![3, 4])),
                 FARule::new(StateSet::new(vec![3, 4]), 'a', StateSet::new(vec![2, 3])),
                 FARule::new(StateSet::new(vec![4, 5]), 'a', StateSet::new(vec![5, 6])),
@@ -666,8 +1348,8 @@ mod tests {
     fn test_nfa_design_int() {
         let rulebook = NFARuleBook {
             rules: vec![
-                FARule::new(StateInt::new(1), '\u{029e}', StateInt::new(2)),
-                FARule::new(StateInt::new(1), '\u{029e}', StateInt::new(4)),
+                FARule::free(StateInt::new(1), StateInt::new(2)),
+                FARule::free(StateInt::new(1), StateInt::new(4)),
                 FARule::new(StateInt::new(2), 'a', StateInt::new(3)),
                 FARule::new(StateInt::new(3), 'a', StateInt::new(2)),
                 FARule::new(StateInt::new(4), 'a', StateInt::new(5)),
@@ -693,16 +1375,8 @@ mod tests {
     fn test_nfa_design_set() {
         let rulebook = NFARuleBook {
             rules: vec![
-                FARule::new(
-                    StateSet::new(vec![1, 2]),
-                    '\u{029e}',
-                    StateSet::new(vec![2, 3]),
-                ),
-                FARule::new(
-                    StateSet::new(vec![1, 2]),
-                    '\u{029e}',
-                    StateSet::new(vec![4, 5]),
-                ),
+                FARule::free(StateSet::new(vec![1, 2]), StateSet::new(vec![2, 3])),
+                FARule::free(StateSet::new(vec![1, 2]), StateSet::new(vec![4, 5])),
                 FARule::new(StateSet::new(vec![2, 3]), 'a', StateSet::new(vec![3, 4])),
                 FARule::new(StateSet::new(vec![3, 4]), 'a', StateSet::new(vec![2, 3])),
                 FARule::new(StateSet::new(vec![4, 5]), 'a', StateSet::new(vec![5, 6])),
@@ -730,10 +1404,10 @@ mod tests {
             rules: vec![
                 FARule::new(StateInt::new(1), 'a', StateInt::new(1)),
                 FARule::new(StateInt::new(1), 'a', StateInt::new(2)),
-                FARule::new(StateInt::new(1), '\u{029e}', StateInt::new(2)),
+                FARule::free(StateInt::new(1), StateInt::new(2)),
                 FARule::new(StateInt::new(2), 'b', StateInt::new(3)),
                 FARule::new(StateInt::new(3), 'b', StateInt::new(1)),
-                FARule::new(StateInt::new(3), '\u{029e}', StateInt::new(2)),
+                FARule::free(StateInt::new(3), StateInt::new(2)),
             ],
         };
         let mut accept_states = HashSet::new();
@@ -746,20 +1420,23 @@ mod tests {
         let mut r = HashSet::new();
         r.insert(StateInt::new(1));
         r.insert(StateInt::new(2));
-        assert_eq!(nfa_design.to_nfa_init().get_current_states(), r);
+        let index = nfa_design.index();
+        let current_states =
+            |states: &BitSet| -> HashSet<StateInt> { index.states(states).cloned().collect() };
+        assert_eq!(current_states(index.start()), r);
 
         let mut s = HashSet::new();
         s.insert(StateInt::new(2));
         let mut r = HashSet::new();
         r.insert(StateInt::new(2));
-        assert_eq!(nfa_design.to_nfa(s).get_current_states(), r);
+        assert_eq!(current_states(&index.closure(&index.to_bitset(&s))), r);
 
         let mut s = HashSet::new();
         s.insert(StateInt::new(3));
         let mut r = HashSet::new();
         r.insert(StateInt::new(2));
         r.insert(StateInt::new(3));
-        assert_eq!(nfa_design.to_nfa(s).get_current_states(), r);
+        assert_eq!(current_states(&index.closure(&index.to_bitset(&s))), r);
     }
 
     #[test]
@@ -768,10 +1445,10 @@ mod tests {
             rules: vec![
                 FARule::new(StateInt::new(1), 'a', StateInt::new(1)),
                 FARule::new(StateInt::new(1), 'a', StateInt::new(2)),
-                FARule::new(StateInt::new(1), '\u{029e}', StateInt::new(2)),
+                FARule::free(StateInt::new(1), StateInt::new(2)),
                 FARule::new(StateInt::new(2), 'b', StateInt::new(3)),
                 FARule::new(StateInt::new(3), 'b', StateInt::new(1)),
-                FARule::new(StateInt::new(3), '\u{029e}', StateInt::new(2)),
+                FARule::free(StateInt::new(3), StateInt::new(2)),
             ],
         };
         let mut accept_states = HashSet::new();
@@ -781,7 +1458,7 @@ mod tests {
             accept_states,
             rulebook,
         };
-        let simulation = NFASimulation { nfa_design };
+        let simulation = NFASimulation::new(nfa_design);
         let mut s = HashSet::new();
         s.insert(StateInt::new(1));
         s.insert(StateInt::new(2));
@@ -848,13 +1525,16 @@ mod tests {
             rules: vec![
                 FARule::new(StateInt::new(1), 'a', StateInt::new(1)),
                 FARule::new(StateInt::new(1), 'a', StateInt::new(2)),
-                FARule::new(StateInt::new(1), '\u{029e}', StateInt::new(2)),
+                FARule::free(StateInt::new(1), StateInt::new(2)),
                 FARule::new(StateInt::new(2), 'b', StateInt::new(3)),
                 FARule::new(StateInt::new(3), 'b', StateInt::new(1)),
-                FARule::new(StateInt::new(3), '\u{029e}', StateInt::new(2)),
+                FARule::free(StateInt::new(3), StateInt::new(2)),
             ],
         };
-        assert_eq!(rulebook.alphabet(), vec!['a', 'b']);
+        assert_eq!(
+            rulebook.alphabet(),
+            vec![CharRange::single('a'), CharRange::single('b')]
+        );
         let mut accept_states = HashSet::new();
         accept_states.insert(StateInt::new(3));
         let nfa_design = NFADesign {
@@ -862,24 +1542,24 @@ mod tests {
             accept_states,
             rulebook,
         };
-        let simulation = NFASimulation { nfa_design };
+        let simulation = NFASimulation::new(nfa_design);
         let mut s = HashSet::new();
         s.insert(StateInt::new(1));
         s.insert(StateInt::new(2));
         let mut rules = HashSet::new();
         rules.insert(FARule {
             state: StateSet::new(vec![1, 2]),
-            character: 'a',
+            label: Label::Characters(CharRange::single('a')),
             next_state: StateSet::new(vec![1, 2]),
         });
         rules.insert(FARule {
             state: StateSet::new(vec![1, 2]),
-            character: 'a',
+            label: Label::Characters(CharRange::single('a')),
             next_state: StateSet::new(vec![1, 2]),
         });
         rules.insert(FARule {
             state: StateSet::new(vec![1, 2]),
-            character: 'b',
+            label: Label::Characters(CharRange::single('b')),
             next_state: StateSet::new(vec![2, 3]),
         });
         assert_eq!(simulation.rules_for(StateSet::from_hashset(s)), rules);
@@ -894,42 +1574,42 @@ mod tests {
         let mut rules = HashSet::new();
         rules.insert(FARule {
             state: StateSet::new(vec![1, 2]),
-            character: 'a',
+            label: Label::Characters(CharRange::single('a')),
             next_state: StateSet::new(vec![1, 2]),
         });
         rules.insert(FARule {
             state: StateSet::new(vec![1, 2]),
-            character: 'b',
+            label: Label::Characters(CharRange::single('b')),
             next_state: StateSet::new(vec![2, 3]),
         });
         rules.insert(FARule {
             state: StateSet::new(vec![2, 3]),
-            character: 'a',
+            label: Label::Characters(CharRange::single('a')),
             next_state: StateSet::new(vec![]),
         });
         rules.insert(FARule {
             state: StateSet::new(vec![2, 3]),
-            character: 'b',
+            label: Label::Characters(CharRange::single('b')),
             next_state: StateSet::new(vec![1, 2, 3]),
         });
         rules.insert(FARule {
             state: StateSet::new(vec![]),
-            character: 'a',
+            label: Label::Characters(CharRange::single('a')),
             next_state: StateSet::new(vec![]),
         });
         rules.insert(FARule {
             state: StateSet::new(vec![]),
-            character: 'b',
+            label: Label::Characters(CharRange::single('b')),
             next_state: StateSet::new(vec![]),
         });
         rules.insert(FARule {
             state: StateSet::new(vec![1, 2, 3]),
-            character: 'a',
+            label: Label::Characters(CharRange::single('a')),
             next_state: StateSet::new(vec![1, 2]),
         });
         rules.insert(FARule {
             state: StateSet::new(vec![1, 2, 3]),
-            character: 'b',
+            label: Label::Characters(CharRange::single('b')),
             next_state: StateSet::new(vec![1, 2, 3]),
         });
         assert_eq!(
@@ -944,13 +1624,16 @@ mod tests {
             rules: vec![
                 FARule::new(StateInt::new(1), 'a', StateInt::new(1)),
                 FARule::new(StateInt::new(1), 'a', StateInt::new(2)),
-                FARule::new(StateInt::new(1), '\u{029e}', StateInt::new(2)),
+                FARule::free(StateInt::new(1), StateInt::new(2)),
                 FARule::new(StateInt::new(2), 'b', StateInt::new(3)),
                 FARule::new(StateInt::new(3), 'b', StateInt::new(1)),
-                FARule::new(StateInt::new(3), '\u{029e}', StateInt::new(2)),
+                FARule::free(StateInt::new(3), StateInt::new(2)),
             ],
         };
-        assert_eq!(rulebook.alphabet(), vec!['a', 'b']);
+        assert_eq!(
+            rulebook.alphabet(),
+            vec![CharRange::single('a'), CharRange::single('b')]
+        );
         let mut accept_states = HashSet::new();
         accept_states.insert(StateInt::new(3));
         let nfa_design = NFADesign {
@@ -958,10 +1641,167 @@ mod tests {
             accept_states,
             rulebook,
         };
-        let simulation = NFASimulation { nfa_design };
+        let simulation = NFASimulation::new(nfa_design);
         let dfa_design = simulation.to_dfa_design();
         assert!(!dfa_design.accepts(String::from("aaa")));
         assert!(dfa_design.accepts(String::from("aab")));
         assert!(dfa_design.accepts(String::from("bbbabb")));
     }
+
+    fn dfa_design(start: i32, accept: &[i32], rules: &[(i32, char, i32)]) -> DFADesign<StateInt> {
+        DFADesign {
+            start_state: StateInt::new(start),
+            accept_states: accept.iter().map(|&s| StateInt::new(s)).collect(),
+            rulebook: DFARuleBook {
+                rules: rules
+                    .iter()
+                    .map(|&(s, c, t)| FARule::new(StateInt::new(s), c, StateInt::new(t)))
+                    .collect(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_boolean_operations() {
+        let dfa_design = |regex: &str| {
+            parse(regex.to_string())
+                .unwrap()
+                .to_nfa_design()
+                .to_dfa_design()
+        };
+        // キーワードではない識別子
+        let identifier = dfa_design("[a-z_][a-z0-9_]*");
+        let keyword = dfa_design("if|else|while");
+        let name = identifier.difference(&keyword);
+        assert!(name.accepts("iff".to_string()));
+        assert!(name.accepts("els".to_string()));
+        assert!(!name.accepts("while".to_string()));
+        assert!(!name.accepts("9lives".to_string()));
+        assert_eq!(keyword.difference(&identifier), dfa_design("[]").minimize());
+
+        let even = dfa_design("(aa)*");
+        let triple = dfa_design("(aaa)*");
+        assert_eq!(even.intersection(&triple), dfa_design("(a{6})*").minimize());
+        assert_eq!(even.union(&triple), dfa_design("(aa)*|(aaa)*").minimize());
+
+        // 規則に出てこない文字も補集合には含まれる
+        let not_even = even.complement();
+        assert!(!not_even.accepts("aa".to_string()));
+        assert!(not_even.accepts("a".to_string()));
+        assert!(not_even.accepts("b".to_string()));
+        assert!(not_even.accepts("aa\u{10ffff}".to_string()));
+        assert_eq!(not_even.complement(), even.minimize());
+        assert_eq!(
+            dfa_design("[]").complement(),
+            dfa_design("(.|\n)*").minimize()
+        );
+    }
+
+    #[test]
+    fn test_minimize() {
+        // b で終わる文字列。1 と 2 は区別できず、4 にはたどり着けない
+        let design = dfa_design(
+            1,
+            &[3, 4],
+            &[
+                (1, 'a', 2),
+                (1, 'b', 3),
+                (2, 'a', 2),
+                (2, 'b', 3),
+                (3, 'a', 1),
+                (3, 'b', 3),
+                (4, 'a', 4),
+            ],
+        );
+        let minimal = design.minimize();
+        assert_eq!(
+            minimal,
+            dfa_design(
+                0,
+                &[1],
+                &[(0, 'a', 0), (0, 'b', 1), (1, 'a', 0), (1, 'b', 1)]
+            )
+        );
+        for input in ["", "b", "ab", "ba", "abab", "bbba"].iter() {
+            assert_eq!(
+                design.accepts(input.to_string()),
+                minimal.accepts(input.to_string())
+            );
+        }
+
+        // 行き先が同じ隣り合う文字はひとつの範囲にまとめ、受理できなくなる状態は消す
+        let design = dfa_design(
+            1,
+            &[2, 3],
+            &[(1, 'a', 2), (1, 'b', 3), (2, 'c', 5), (5, 'c', 5)],
+        );
+        let minimal = design.minimize();
+        assert_eq!(minimal.accept_states.len(), 1);
+        assert_eq!(
+            minimal.rulebook.rules,
+            vec![FARule::range(
+                StateInt::new(0),
+                CharRange::new('a', 'b'),
+                StateInt::new(1)
+            )]
+        );
+
+        // 何も受理しないなら開始状態だけが残る
+        assert_eq!(
+            dfa_design(1, &[], &[(1, 'a', 2)]).minimize(),
+            dfa_design(0, &[], &[])
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let design = dfa_design(
+            1,
+            &[3, 2],
+            &[
+                (1, 'a', 2),
+                (1, 'c', 2),
+                (1, 'b', 2),
+                (2, '"', 3),
+                (1, 'x', 3),
+                (3, '\\', 1),
+            ],
+        );
+        // " と \ は dot の文字列の中でエスケープし、制御文字は \n のように書く
+        assert_eq!(
+            design.to_dot(),
+            "digraph {\n  rankdir=LR;\n  node [shape=circle];\n  \"\" [shape=none];\n  \
+             \"\" -> \"1\";\n  \"2\" [shape=doublecircle];\n  \"3\" [shape=doublecircle];\n  \
+             \"1\" -> \"2\" [label=\"[a-c]\"];\n  \"2\" -> \"3\" [label=\"\\\"\"];\n  \
+             \"1\" -> \"3\" [label=\"x\"];\n  \"3\" -> \"1\" [label=\"\\\\\"];\n}\n"
+        );
+
+        // 自由移動は ε。同じ2状態の間の文字と一緒に1本の辺にする
+        let design = NFADesign {
+            start_state: StateInt::new(1),
+            accept_states: vec![StateInt::new(2)].into_iter().collect(),
+            rulebook: NFARuleBook {
+                rules: vec![
+                    FARule::free(StateInt::new(1), StateInt::new(2)),
+                    FARule::range(StateInt::new(1), CharRange::new('0', '9'), StateInt::new(2)),
+                    FARule::new(StateInt::new(1), 'z', StateInt::new(2)),
+                    FARule::new(StateInt::new(2), '\n', StateInt::new(1)),
+                ],
+            },
+        };
+        assert_eq!(
+            design.to_dot(),
+            "digraph {\n  rankdir=LR;\n  node [shape=circle];\n  \"\" [shape=none];\n  \
+             \"\" -> \"1\";\n  \"2\" [shape=doublecircle];\n  \
+             \"1\" -> \"2\" [label=\"ε, [0-9z]\"];\n  \"2\" -> \"1\" [label=\"\\\\n\"];\n}\n"
+        );
+
+        // 部分集合構成法の状態は集合のまま名前にする
+        let dot = parse("a|b".to_string())
+            .unwrap()
+            .to_nfa_design()
+            .to_dfa_design()
+            .to_dot();
+        assert!(dot.contains("[label=\"[a-b]\"]"));
+    }
 }
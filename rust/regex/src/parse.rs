@@ -1,8 +1,45 @@
+use std::fmt;
+
+use crate::nfa::CharRange;
 use crate::pattern::Pattern::{self, *};
+use crate::pattern::METACHARACTERS;
+
+// 読めなかった位置 (バイト単位) と、そこで期待していたもの
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub regex: String,
+    pub position: usize,
+    pub expected: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let found = match self.regex[self.position..].chars().next() {
+            Some(c) => format!("'{}'", c),
+            None => String::from("end of input"),
+        };
+        let column = self.regex[..self.position].chars().count();
+        write!(
+            f,
+            "expected {} but found {} at {}\n{}\n{}^",
+            self.expected,
+            found,
+            self.position,
+            self.regex,
+            " ".repeat(column)
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
 
 struct Reader {
     regex: Vec<char>,
     index: i32,
+    // 開いている括弧の数
+    depth: usize,
+    // これまでに読んだグループの数
+    groups: usize,
 }
 
 impl Reader {
@@ -10,6 +47,8 @@ impl Reader {
         return Reader {
             regex: regex.chars().collect(),
             index: 0,
+            depth: 0,
+            groups: 0,
         };
     }
 
@@ -25,28 +64,37 @@ impl Reader {
         if self.at_end() {
             return false;
         }
-        if vec!['*', '|', '(', ')'].contains(&self.current()) {
+        if METACHARACTERS.contains(&self.current()) {
             return false;
         }
         return true;
     }
 
-    fn expect(&mut self, c: char) {
-        if self.at_end() {
-            eprintln!("expected {} but input ends", c);
-            std::process::exit(1);
+    fn error(&self, expected: &str) -> ParseError {
+        let regex: String = self.regex.iter().collect();
+        let position = self.regex[..self.index as usize]
+            .iter()
+            .map(|c| c.len_utf8())
+            .sum();
+        ParseError {
+            regex,
+            position,
+            expected: expected.to_string(),
         }
-        if self.current() == c {
-            self.step()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.is(c) {
+            self.step();
+            Ok(())
         } else {
-            eprintln!("expected {} but not", c);
-            std::process::exit(1);
+            Err(self.error(&format!("'{}'", c)))
         }
     }
 
     fn is(&mut self, c: char) -> bool {
         if self.at_end() {
-            return false
+            return false;
         }
         return self.current() == c;
     }
@@ -54,74 +102,457 @@ impl Reader {
     fn step(&mut self) {
         self.index += 1;
     }
+
+    // 選択肢か & でつないだ項の終わり。括弧の外では $ も選択肢を終える
+    fn at_alternative_end(&mut self) -> bool {
+        self.at_end()
+            || self.is('|')
+            || self.is('&')
+            || self.is(')')
+            || (self.depth == 0 && self.is('$'))
+    }
 }
 
-pub fn parse(reg: String) -> Pattern {
+pub fn parse(reg: String) -> Result<Pattern, ParseError> {
     let mut reader = Reader::new(&reg);
-    return choose(&mut reader);
+    let pattern = choose(&mut reader)?;
+    if !reader.at_end() {
+        return Err(reader.error("end of input"));
+    }
+    Ok(pattern)
 }
 
-// choose = concatenate_or_empty ("|" choose)?
-fn choose(r: &mut Reader) -> Pattern {
-    let f = concatenate_or_empty(r);
+// choose = alternative ("|" choose)?
+fn choose(r: &mut Reader) -> Result<Pattern, ParseError> {
+    let f = alternative(r)?;
     if r.is('|') {
         r.step();
-        let s = choose(r);
-        return Choose {
+        let s = choose(r)?;
+        Ok(Choose {
             first: Box::new(f),
             second: Box::new(s),
+        })
+    } else {
+        Ok(f)
+    }
+}
+
+// alternative = "^"? intersect "$"?
+// ^ と $ は括弧の外でだけ、選択肢の先頭と末尾に書ける
+fn alternative(r: &mut Reader) -> Result<Pattern, ParseError> {
+    let start = r.depth == 0 && r.is('^');
+    if start {
+        r.step();
+    }
+    let mut pattern = intersect(r)?;
+    if start {
+        pattern = Concatenate {
+            first: Box::new(StartAnchor),
+            second: Box::new(pattern),
+        };
+    }
+    if r.depth == 0 && r.is('$') {
+        r.step();
+        pattern = Concatenate {
+            first: Box::new(pattern),
+            second: Box::new(EndAnchor),
         };
+    }
+    Ok(pattern)
+}
+
+// intersect = concatenate_or_empty ("&" intersect)?
+fn intersect(r: &mut Reader) -> Result<Pattern, ParseError> {
+    let first = concatenate_or_empty(r)?;
+    if r.is('&') {
+        r.step();
+        let second = intersect(r)?;
+        Ok(Intersect {
+            first: Box::new(first),
+            second: Box::new(second),
+        })
     } else {
-        return f;
+        Ok(first)
     }
 }
 
 // connatenate_or_empty = concatenate | empty
-fn concatenate_or_empty(r: &mut Reader) -> Pattern {
-    if r.is('|') {
-        return Empty
+fn concatenate_or_empty(r: &mut Reader) -> Result<Pattern, ParseError> {
+    if r.at_alternative_end() {
+        Ok(Empty)
     } else {
-        return concatenate(r)
+        concatenate(r)
     }
 }
 
 // concatenate = repeat (concatenate)?
-fn concatenate(r: &mut Reader) -> Pattern {
-    let first = Box::new(repeat(r));
-    let second = Box::new(if r.is_literal() {
-        concatenate(r)
-    } else {
+fn concatenate(r: &mut Reader) -> Result<Pattern, ParseError> {
+    let first = Box::new(repeat(r)?);
+    let second = Box::new(if r.at_alternative_end() {
         Empty
+    } else {
+        concatenate(r)?
     });
-    return Concatenate { first, second };
+    Ok(Concatenate { first, second })
 }
 
-// repeat = brackets("*")?
-fn repeat(r: &mut Reader) -> Pattern {
-    let b = brackets(r);
+// repeat = "~" repeat | brackets("*" | "+" | "?" | bound)?
+fn repeat(r: &mut Reader) -> Result<Pattern, ParseError> {
+    if r.is('~') {
+        r.step();
+        return Ok(Complement(Box::new(repeat(r)?)));
+    }
+    let b = Box::new(brackets(r)?);
     if r.is('*') {
         r.step();
-        return Repeat(Box::new(b));
+        Ok(Repeat(b))
+    } else if r.is('+') {
+        r.step();
+        Ok(OneOrMore(b))
+    } else if r.is('?') {
+        r.step();
+        Ok(Optional(b))
+    } else if r.is('{') {
+        let (min, max) = bound(r)?;
+        Ok(Bounded {
+            pattern: b,
+            min,
+            max,
+        })
     } else {
-        return b;
+        Ok(*b)
     }
 }
 
-// brackets = "(" choose ")" | literal
-fn brackets(r: &mut Reader) -> Pattern {
-    return if r.is('(') {
+// {m,n} に書ける回数の上限。展開した NFA や命令列が大きくなりすぎないようにする
+const MAX_REPEAT: u32 = 1000;
+
+// bound = "{" number ("," number?)? "}"
+fn bound(r: &mut Reader) -> Result<(u32, Option<u32>), ParseError> {
+    r.expect('{')?;
+    let min = number(r)?;
+    let max = if r.is(',') {
         r.step();
-        let pat = choose(r);
-        r.expect(')');
-        pat
+        if r.is('}') {
+            None
+        } else {
+            let start = r.index;
+            let max = number(r)?;
+            if max < min {
+                r.index = start;
+                return Err(r.error(&format!("a number not less than {}", min)));
+            }
+            Some(max)
+        }
+    } else {
+        Some(min)
+    };
+    r.expect('}')?;
+    Ok((min, max))
+}
+
+fn number(r: &mut Reader) -> Result<u32, ParseError> {
+    let start = r.index;
+    let mut digits = String::new();
+    while !r.at_end() && r.current().is_ascii_digit() {
+        digits.push(r.current());
+        r.step();
+    }
+    let number: u32 = digits.parse().map_err(|_| {
+        r.index = start;
+        r.error("a number")
+    })?;
+    if number > MAX_REPEAT {
+        r.index = start;
+        return Err(r.error(&format!("a number not greater than {}", MAX_REPEAT)));
+    }
+    Ok(number)
+}
+
+// brackets = "(" ("?:")? choose ")" | "." | class | literal
+// (?: ) で囲んだものはグループにならない。グループには開き括弧の順に 1 から番号を付ける
+fn brackets(r: &mut Reader) -> Result<Pattern, ParseError> {
+    if r.is('(') {
+        r.step();
+        let index = if r.is('?') {
+            r.step();
+            r.expect(':')?;
+            None
+        } else {
+            r.groups += 1;
+            Some(r.groups)
+        };
+        r.depth += 1;
+        let pat = choose(r)?;
+        r.expect(')')?;
+        r.depth -= 1;
+        Ok(match index {
+            Some(index) => Capture {
+                index,
+                pattern: Box::new(pat),
+            },
+            None => pat,
+        })
+    } else if r.is('.') {
+        r.step();
+        Ok(Any)
+    } else if r.is('[') {
+        class(r)
     } else {
         literal(r)
+    }
+}
+
+// class = "[" "^"? (class_character ("-" class_character)?)* "]"
+fn class(r: &mut Reader) -> Result<Pattern, ParseError> {
+    r.expect('[')?;
+    let negated = r.is('^');
+    if negated {
+        r.step();
+    }
+    let mut ranges = vec![];
+    while !r.is(']') {
+        let start_index = r.index;
+        let start = class_character(r)?;
+        // 最後の - は文字として読む
+        let end = if r.is('-') && r.regex.get(r.index as usize + 1) != Some(&']') {
+            r.step();
+            class_character(r)?
+        } else {
+            start
+        };
+        if end < start {
+            r.index = start_index;
+            return Err(r.error("a range in order"));
+        }
+        ranges.push(CharRange::new(start, end));
+    }
+    r.step();
+    Ok(CharClass { ranges, negated })
+}
+
+fn class_character(r: &mut Reader) -> Result<char, ParseError> {
+    if r.at_end() {
+        return Err(r.error("']'"));
+    }
+    if r.is('\\') {
+        return escape(r);
+    }
+    let c = r.current();
+    r.step();
+    Ok(c)
+}
+
+// \n \t \r と、英数字以外の文字の前のバックスラッシュだけを認める
+fn escape(r: &mut Reader) -> Result<char, ParseError> {
+    r.expect('\\')?;
+    if r.at_end() {
+        return Err(r.error("an escaped character"));
+    }
+    let c = match r.current() {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c if c.is_alphanumeric() => return Err(r.error("an escaped character")),
+        c => c,
     };
+    r.step();
+    Ok(c)
 }
 
-// literal
-fn literal(r: &mut Reader) -> Pattern {
+// literal = "\\" character | character
+fn literal(r: &mut Reader) -> Result<Pattern, ParseError> {
+    if r.is('\\') {
+        let character = escape(r)?;
+        return Ok(Literal { character });
+    }
+    if r.is('^') || r.is('$') {
+        return Err(
+            r.error("a literal (^ and $ may only start and end an alternative outside brackets)")
+        );
+    }
+    if !r.is_literal() {
+        return Err(r.error("a literal"));
+    }
     let c = r.current();
     r.step();
-    return Literal { character: c };
+    Ok(Literal { character: c })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, ParseError};
+
+    fn error(regex: &str) -> ParseError {
+        parse(regex.to_string()).unwrap_err()
+    }
+
+    #[test]
+    fn test_parse() {
+        let pattern = parse("(a(|b))*".to_string()).unwrap();
+        assert!(pattern.matches("aaba".to_string()));
+        assert!(!pattern.matches("abb".to_string()));
+        assert!(parse("a|".to_string()).unwrap().matches("".to_string()));
+        assert!(parse("".to_string()).unwrap().matches("".to_string()));
+        assert!(parse("a(b)c".to_string())
+            .unwrap()
+            .matches("abc".to_string()));
+    }
+
+    #[test]
+    fn test_errors() {
+        let e = error(")");
+        assert_eq!((e.position, e.expected.as_str()), (0, "end of input"));
+        let e = error("*a");
+        assert_eq!((e.position, e.expected.as_str()), (0, "a literal"));
+        let e = error("(a|");
+        assert_eq!((e.position, e.expected.as_str()), (3, "')'"));
+        let e = error("a**");
+        assert_eq!((e.position, e.expected.as_str()), (2, "a literal"));
+        let e = error("a(");
+        assert_eq!((e.position, e.expected.as_str()), (2, "')'"));
+    }
+
+    #[test]
+    fn test_extended_syntax() {
+        let matches =
+            |regex: &str, s: &str| parse(regex.to_string()).unwrap().matches(s.to_string());
+        assert!(matches("a.c", "abc"));
+        assert!(!matches("a.c", "a\nc"));
+        assert!(matches("[a-c0-9_]+", "b0_9a"));
+        assert!(!matches("[a-c0-9_]+", ""));
+        assert!(!matches("[a-c]", "d"));
+        assert!(matches("[^a-c]", "d"));
+        assert!(matches("[^a-c]", "\n"));
+        assert!(!matches("[^a-c]", "b"));
+        assert!(!matches("[]", ""));
+        assert!(!matches("[]", "a"));
+        assert!(matches("[-a]", "-"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[\\]]", "]"));
+        assert!(matches("colou?r", "color"));
+        assert!(matches("colou?r", "colour"));
+        assert!(!matches("colou?r", "colouur"));
+        assert!(matches("(ab){2}", "abab"));
+        assert!(!matches("(ab){2}", "ab"));
+        assert!(matches("a{1,3}", "aaa"));
+        assert!(!matches("a{1,3}", "aaaa"));
+        assert!(!matches("a{1,3}", ""));
+        assert!(matches("a{2,}", "aaaaa"));
+        assert!(!matches("a{2,}", "a"));
+        assert!(matches("a{0}", ""));
+        assert!(matches("\\*\\.\\(\\\\", "*.(\\"));
+        assert!(matches("a\\nb", "a\nb"));
+        // 否定した文字クラスも必ず1文字を読む
+        assert!(!matches("[^x]b", "b"));
+    }
+
+    #[test]
+    fn test_extended_syntax_errors() {
+        let e = error("[a-");
+        assert_eq!((e.position, e.expected.as_str()), (3, "']'"));
+        let e = error("[z-a]");
+        assert_eq!((e.position, e.expected.as_str()), (1, "a range in order"));
+        let e = error("a{,2}");
+        assert_eq!((e.position, e.expected.as_str()), (2, "a number"));
+        let e = error("a{3,2}");
+        assert_eq!(
+            (e.position, e.expected.as_str()),
+            (4, "a number not less than 3")
+        );
+        let e = error("a{2");
+        assert_eq!((e.position, e.expected.as_str()), (3, "'}'"));
+        let e = error("a{2,1001}");
+        assert_eq!(
+            (e.position, e.expected.as_str()),
+            (4, "a number not greater than 1000")
+        );
+        let e = error("a{20000}");
+        assert_eq!(
+            (e.position, e.expected.as_str()),
+            (2, "a number not greater than 1000")
+        );
+        let e = error("a+*");
+        assert_eq!((e.position, e.expected.as_str()), (2, "a literal"));
+        let e = error("\\d");
+        assert_eq!(
+            (e.position, e.expected.as_str()),
+            (1, "an escaped character")
+        );
+        let e = error("a\\");
+        assert_eq!(
+            (e.position, e.expected.as_str()),
+            (2, "an escaped character")
+        );
+        let e = error("a]");
+        assert_eq!((e.position, e.expected.as_str()), (1, "a literal"));
+    }
+
+    #[test]
+    fn test_anchors() {
+        let to_s = |regex: &str| parse(regex.to_string()).unwrap().to_s();
+        assert_eq!(to_s("^ab$"), "^ab$");
+        assert_eq!(to_s("^a|b$|^$"), "^a|b$|^$");
+        // 文字列全体とのマッチでは何もしない
+        assert!(parse("^a|b$".to_string()).unwrap().matches("b".to_string()));
+        let e = error("a^b");
+        assert_eq!(e.position, 1);
+        assert!(e.expected.starts_with("a literal (^ and $"));
+        assert_eq!(error("(^a)").position, 1);
+        assert_eq!(error("(a$)").position, 2);
+        let e = error("a$b");
+        assert_eq!((e.position, e.expected.as_str()), (2, "end of input"));
+        assert!(parse("[$^]".to_string()).unwrap().matches("^".to_string()));
+    }
+
+    #[test]
+    fn test_groups() {
+        let pattern = parse("(a(b))(?:c)(d|)".to_string()).unwrap();
+        assert_eq!(pattern.to_s(), "(a(b))c(d|)");
+        assert!(pattern.matches("abc".to_string()));
+        let e = error("(?a)");
+        assert_eq!((e.position, e.expected.as_str()), (2, "':'"));
+    }
+
+    #[test]
+    fn test_intersect_and_complement() {
+        let to_s = |regex: &str| parse(regex.to_string()).unwrap().to_s();
+        for regex in [
+            "a&b|c", "(a|b)&c", "~(ab)", "~a*", "(~a)*", "~~a", "~()", "ab&", "\\&\\~",
+        ]
+        .iter()
+        {
+            assert_eq!(&to_s(regex), regex);
+        }
+        // ^ と $ は & より弱く結び付く
+        assert_eq!(to_s("^a&b$"), "^(a&b)$");
+        let matches =
+            |regex: &str, s: &str| parse(regex.to_string()).unwrap().matches(s.to_string());
+        assert!(matches("[a-z]+&~(if|else)", "iff"));
+        assert!(!matches("[a-z]+&~(if|else)", "if"));
+        assert!(matches("~(.*ab.*)", "bbaa\n"));
+        assert!(!matches("~(.*ab.*)", "bbaab"));
+        assert!(matches("~()", "\n"));
+        assert!(!matches("~()", ""));
+        assert!(matches("(a|b)*&(a|b){3}", "aba"));
+        let e = error("a&^b");
+        assert_eq!(e.position, 2);
+        let e = error("a~");
+        assert_eq!((e.position, e.expected.as_str()), (2, "a literal"));
+    }
+
+    #[test]
+    fn test_error_message() {
+        assert_eq!(
+            error("(a|").to_string(),
+            "expected ')' but found end of input at 3\n(a|\n   ^"
+        );
+        // 位置はバイト単位だが、^ は文字単位でそろえる
+        let e = error("éé)");
+        assert_eq!(e.position, 4);
+        assert_eq!(
+            e.to_string(),
+            "expected end of input but found ')' at 4\néé)\n  ^"
+        );
+    }
 }
@@ -1,9 +1,8 @@
-use rand;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use crate::nfa::{FARule, NFADesign, NFARuleBook, StateInt};
+use crate::nfa::{CharRange, DFADesign, FARule, NFADesign, NFARuleBook, StateAllocator, StateInt};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Pattern {
     Empty,
     Literal {
@@ -18,10 +17,61 @@ pub enum Pattern {
         second: Box<Pattern>,
     },
     Repeat(Box<Pattern>),
+    // 改行以外の任意の1文字
+    Any,
+    // [a-z0-9] や [^a]。ranges が空の [] はどの文字にもマッチしない
+    CharClass {
+        ranges: Vec<CharRange>,
+        negated: bool,
+    },
+    OneOrMore(Box<Pattern>),
+    Optional(Box<Pattern>),
+    // {min,max}。max が None なら上限なし
+    Bounded {
+        pattern: Box<Pattern>,
+        min: u32,
+        max: Option<u32>,
+    },
+    // ^ と $。文字を読まずに、文字列の先頭か末尾にいることだけを確かめる
+    StartAnchor,
+    EndAnchor,
+    // 括弧で囲んだ部分。マッチした位置を index 番目のグループとして取り出せる
+    Capture {
+        index: usize,
+        pattern: Box<Pattern>,
+    },
+    // a&b は両方に、~a は a にマッチしない文字列すべてにマッチする
+    Intersect {
+        first: Box<Pattern>,
+        second: Box<Pattern>,
+    },
+    Complement(Box<Pattern>),
 }
 
 use Pattern::*;
 
+// バックスラッシュを付けないと文字そのものとして読めない文字
+pub const METACHARACTERS: [char; 16] = [
+    '\\', '|', '*', '+', '?', '(', ')', '[', ']', '{', '}', '.', '^', '$', '&', '~',
+];
+
+fn escape(character: char) -> String {
+    match character {
+        '\n' => String::from("\\n"),
+        '\t' => String::from("\\t"),
+        '\r' => String::from("\\r"),
+        c if METACHARACTERS.contains(&c) => format!("\\{}", c),
+        c => c.to_string(),
+    }
+}
+
+fn escape_in_class(character: char) -> String {
+    match character {
+        '-' => String::from("\\-"),
+        c => escape(c),
+    }
+}
+
 impl Pattern {
     pub fn matches(&self, s: String) -> bool {
         self.to_nfa_design().accepts(s)
@@ -29,24 +79,27 @@ impl Pattern {
 
     pub fn precedence(&self) -> i32 {
         match self {
-            Empty => 3,
-            Literal { character: _ } => 3,
+            Empty => 4,
+            Literal { character: _ } => 4,
             Concatenate {
                 first: _,
                 second: _,
-            } => 3,
+            } => 2,
             Choose {
                 first: _,
                 second: _,
             } => 0,
-            Repeat(_) => 2,
+            Intersect { .. } => 1,
+            Repeat(_) => 3,
+            Any | CharClass { .. } | StartAnchor | EndAnchor | Capture { .. } => 4,
+            OneOrMore(_) | Optional(_) | Bounded { .. } | Complement(_) => 3,
         }
     }
 
     pub fn to_s(&self) -> String {
         match self {
             Empty => String::from(""),
-            Literal { character } => character.to_string(),
+            Literal { character } => escape(*character),
             Concatenate { first, second } => format!(
                 "{}{}",
                 &*first.bracket(self.precedence()),
@@ -57,7 +110,38 @@ impl Pattern {
                 &*first.bracket(self.precedence()),
                 &*second.bracket(self.precedence())
             ),
-            Repeat(pat) => format!("{}*", &*pat.bracket(self.precedence())),
+            Repeat(pat) => format!("{}*", pat.operand()),
+            Any => String::from("."),
+            CharClass { ranges, negated } => {
+                let mut s = String::from(if *negated { "[^" } else { "[" });
+                for range in ranges.iter() {
+                    s += &escape_in_class(range.start);
+                    if range.start != range.end {
+                        s += &format!("-{}", escape_in_class(range.end));
+                    }
+                }
+                s + "]"
+            }
+            OneOrMore(pat) => format!("{}+", pat.operand()),
+            Optional(pat) => format!("{}?", pat.operand()),
+            Bounded { pattern, min, max } => match max {
+                Some(max) if max == min => format!("{}{{{}}}", pattern.operand(), min),
+                Some(max) => format!("{}{{{},{}}}", pattern.operand(), min, max),
+                None => format!("{}{{{},}}", pattern.operand(), min),
+            },
+            StartAnchor => String::from("^"),
+            EndAnchor => String::from("$"),
+            Capture { pattern, .. } => format!("({})", pattern.to_s()),
+            Intersect { first, second } => format!(
+                "{}&{}",
+                &*first.bracket(self.precedence()),
+                &*second.bracket(self.precedence())
+            ),
+            // ~ は繰り返しより後に付くので、~a* は ~(a*) と読む
+            Complement(pat) => match **pat {
+                Empty => String::from("~()"),
+                _ => format!("~{}", pat.bracket(3)),
+            },
         }
     }
 
@@ -69,50 +153,140 @@ impl Pattern {
         }
     }
 
+    // * や + を付ける相手。a** のように繰り返しが続くと読めないので括弧を付ける
+    fn operand(&self) -> String {
+        match self {
+            Empty => String::from("()"),
+            _ => self.bracket(4),
+        }
+    }
+
+    // 文字の集合を表すパターンなら、その範囲の列を返す
+    pub(crate) fn character_ranges(&self) -> Option<Vec<CharRange>> {
+        match self {
+            Literal { character } => Some(vec![CharRange::single(*character)]),
+            Any => Some(CharRange::complement(&[CharRange::single('\n')])),
+            CharClass {
+                ranges,
+                negated: false,
+            } => Some(ranges.clone()),
+            CharClass {
+                ranges,
+                negated: true,
+            } => Some(CharRange::complement(ranges)),
+            _ => None,
+        }
+    }
+
+    // 最小化した DFA。同じ言語を表すパターンからは同じものができる。
+    // to_nfa_design と同じく、^ と $ は何も読まないものとして扱う
+    pub fn to_dfa_design(&self) -> DFADesign<StateInt> {
+        self.to_nfa_design().to_dfa_design().minimize()
+    }
+
+    // 同じ言語を表すなら Ok、そうでなければ片方だけがマッチする最短の文字列を返す。
+    // 比べるのは文字列全体とのマッチ (matches) だけ。^ と $ は何も読まないので、
+    // ^a と a は同じとみなすが、Regex::find のように部分文字列を探すときは違う結果になる
+    pub fn equivalent(&self, other: &Pattern) -> Result<(), String> {
+        match self
+            .to_dfa_design()
+            .shortest_string_where(&other.to_dfa_design(), |a, b| a != b)
+        {
+            Some(witness) => Err(witness),
+            None => Ok(()),
+        }
+    }
+
+    // self にマッチする文字列がすべて other にもマッチするなら Ok、
+    // そうでなければ self だけにマッチする最短の文字列を返す。
+    // equivalent と同じく、文字列全体とのマッチだけを比べる
+    pub fn is_subset_of(&self, other: &Pattern) -> Result<(), String> {
+        match self
+            .to_dfa_design()
+            .shortest_string_where(&other.to_dfa_design(), |a, b| a && !b)
+        {
+            Some(witness) => Err(witness),
+            None => Ok(()),
+        }
+    }
+
+    // 文字列全体とのマッチに使う NFA。^ と $ は何も読まない (to_nfa_design_with を参照) ので、
+    // この NFA やそこから作る DFA は、部分文字列を探すときの ^ と $ の意味を表さない
     pub fn to_nfa_design(&self) -> NFADesign<StateInt> {
+        self.to_nfa_design_with(&mut StateAllocator::new())
+    }
+
+    // パターンごとの NFA を、新しい開始状態からの自由移動でひとつにつなぐ。
+    // 受理状態は全部のパターンのものを合わせ、パターンごとの受理状態も並べて返す
+    pub fn combine(patterns: &[Pattern]) -> (NFADesign<StateInt>, Vec<HashSet<StateInt>>) {
+        let mut allocator = StateAllocator::new();
+        let start_state = allocator.allocate();
+        let mut rules = vec![];
+        let mut accept_states = HashSet::new();
+        let mut pattern_accept_states = vec![];
+        for pattern in patterns.iter() {
+            let design = pattern.to_nfa_design_with(&mut allocator);
+            rules.push(FARule::free(start_state.clone(), design.start_state));
+            rules.extend(design.rulebook.rules);
+            accept_states.extend(design.accept_states.iter().cloned());
+            pattern_accept_states.push(design.accept_states);
+        }
+        let nfa_design = NFADesign {
+            start_state,
+            accept_states,
+            rulebook: NFARuleBook { rules },
+        };
+        (nfa_design, pattern_accept_states)
+    }
+
+    // 状態は allocator から順に払い出すので、同じパターンからは常に同じ番号の NFA ができる
+    pub fn to_nfa_design_with(&self, allocator: &mut StateAllocator) -> NFADesign<StateInt> {
         match self {
-            Empty => {
-                let start_state: i32 = rand::random();
+            // 文字列全体とのマッチでは、^ と $ は選択肢の両端にしか書けないので (parse を参照)
+            // いつも満たされる
+            Empty | StartAnchor | EndAnchor => {
+                let start_state = allocator.allocate();
                 let mut accept_states = HashSet::new();
-                accept_states.insert(StateInt::new(start_state));
+                accept_states.insert(start_state.clone());
                 let rules: Vec<FARule<StateInt>> = vec![];
                 let rulebook = NFARuleBook { rules };
                 return NFADesign {
-                    start_state: StateInt::new(start_state),
+                    start_state,
                     accept_states,
                     rulebook,
                 };
             }
-            Literal { character } => {
-                let start_state: i32 = rand::random();
-                let accept_state: i32 = rand::random();
-                let rule = FARule::new(
-                    StateInt::new(start_state),
-                    *character,
-                    StateInt::new(accept_state),
-                );
-                let rulebook = NFARuleBook { rules: vec![rule] };
+            Literal { .. } | Any | CharClass { .. } => {
+                let start_state = allocator.allocate();
+                let accept_state = allocator.allocate();
+                let rules = self
+                    .character_ranges()
+                    .unwrap()
+                    .into_iter()
+                    .map(|range| FARule::range(start_state.clone(), range, accept_state.clone()))
+                    .collect();
+                let rulebook = NFARuleBook { rules };
                 let mut accept_states = HashSet::new();
-                accept_states.insert(StateInt::new(accept_state));
+                accept_states.insert(accept_state);
                 return NFADesign {
-                    start_state: StateInt::new(start_state),
+                    start_state,
                     accept_states,
                     rulebook,
                 };
             }
             Concatenate { first, second } => {
-                let first_nfa_design = first.to_nfa_design();
-                let second_nfa_design = second.to_nfa_design();
+                let first_nfa_design = first.to_nfa_design_with(allocator);
+                let second_nfa_design = second.to_nfa_design_with(allocator);
                 let start_state = first_nfa_design.start_state;
                 let accept_states = second_nfa_design.accept_states;
                 let mut rules = first_nfa_design.rulebook.rules.clone();
                 rules.append(&mut second_nfa_design.rulebook.rules.clone());
                 let second_start_state = second_nfa_design.start_state.clone();
-                let mut extra_rules: Vec<FARule<StateInt>> = first_nfa_design
-                    .accept_states
-                    .iter()
-                    .map(|state| FARule::new(state.clone(), '\u{029e}', second_start_state.clone()))
-                    .collect();
+                let mut extra_rules: Vec<FARule<StateInt>> =
+                    sorted(&first_nfa_design.accept_states)
+                        .into_iter()
+                        .map(|state| FARule::free(state, second_start_state.clone()))
+                        .collect();
                 rules.append(&mut extra_rules);
                 let rulebook = NFARuleBook { rules: rules };
                 return NFADesign {
@@ -122,27 +296,18 @@ impl Pattern {
                 };
             }
             Choose { first, second } => {
-                let first_nfa_design = first.to_nfa_design();
-                let second_nfa_design = second.to_nfa_design();
+                let start_state = allocator.allocate();
+                let first_nfa_design = first.to_nfa_design_with(allocator);
+                let second_nfa_design = second.to_nfa_design_with(allocator);
 
-                let start_int: i32 = rand::random();
-                let start_state = StateInt::new(start_int);
                 let mut accept_states: HashSet<StateInt> = HashSet::new();
                 accept_states.extend(first_nfa_design.accept_states.clone());
                 accept_states.extend(second_nfa_design.accept_states.clone());
                 let mut rules = first_nfa_design.rulebook.rules.clone();
                 rules.append(&mut second_nfa_design.rulebook.rules.clone());
                 let mut extra_rules: Vec<FARule<StateInt>> = vec![
-                    FARule::new(
-                        start_state.clone(),
-                        '\u{029e}',
-                        first_nfa_design.start_state,
-                    ),
-                    FARule::new(
-                        start_state.clone(),
-                        '\u{029e}',
-                        second_nfa_design.start_state,
-                    ),
+                    FARule::free(start_state.clone(), first_nfa_design.start_state),
+                    FARule::free(start_state.clone(), second_nfa_design.start_state),
                 ];
                 rules.append(&mut extra_rules);
                 let rulebook = NFARuleBook { rules: rules };
@@ -153,29 +318,20 @@ impl Pattern {
                 };
             }
             Repeat(pat) => {
-                let pat_nfa_design = pat.to_nfa_design();
+                let start_state = allocator.allocate();
+                let pat_nfa_design = pat.to_nfa_design_with(allocator);
 
-                let start_int: i32 = rand::random();
-                let start_state = StateInt::new(start_int);
                 let mut accept_states: HashSet<StateInt> = HashSet::new();
                 accept_states.extend(pat_nfa_design.accept_states.clone());
                 accept_states.insert(start_state.clone());
                 let mut rules = pat_nfa_design.rulebook.rules.clone();
-                let mut extra_rules: Vec<FARule<StateInt>> = pat_nfa_design
-                    .accept_states
-                    .iter()
-                    .map(|state| {
-                        FARule::new(
-                            state.clone(),
-                            '\u{029e}',
-                            pat_nfa_design.start_state.clone(),
-                        )
-                    })
+                let mut extra_rules: Vec<FARule<StateInt>> = sorted(&pat_nfa_design.accept_states)
+                    .into_iter()
+                    .map(|state| FARule::free(state, pat_nfa_design.start_state.clone()))
                     .collect();
                 rules.append(&mut extra_rules);
-                rules.append(&mut vec![FARule::new(
+                rules.append(&mut vec![FARule::free(
                     start_state.clone(),
-                    '\u{029e}',
                     pat_nfa_design.start_state,
                 )]);
                 let rulebook = NFARuleBook { rules: rules };
@@ -185,6 +341,244 @@ impl Pattern {
                     rulebook,
                 };
             }
+            // 受理状態から開始状態へ戻る自由移動を足す。開始状態は受理しない
+            OneOrMore(pat) => {
+                let mut nfa_design = pat.to_nfa_design_with(allocator);
+                for state in sorted(&nfa_design.accept_states) {
+                    nfa_design
+                        .rulebook
+                        .rules
+                        .push(FARule::free(state, nfa_design.start_state.clone()));
+                }
+                nfa_design
+            }
+            // 受理状態の開始状態を新しく作り、元の開始状態へ自由移動でつなぐ
+            Optional(pat) => {
+                let start_state = allocator.allocate();
+                let mut nfa_design = pat.to_nfa_design_with(allocator);
+                nfa_design
+                    .rulebook
+                    .rules
+                    .push(FARule::free(start_state.clone(), nfa_design.start_state));
+                nfa_design.accept_states.insert(start_state.clone());
+                nfa_design.start_state = start_state;
+                nfa_design
+            }
+            // min 個を並べたあとに、max まで省略可能なもの (max がなければ繰り返し) をつなぐ。
+            // 回数が多くても再帰が深くならないよう、中身の NFA を1つずつ作って自由移動でつなぐ
+            Bounded { pattern, min, max } => {
+                let start_state = allocator.allocate();
+                let mut rules = vec![];
+                let mut accept_states: HashSet<StateInt> = HashSet::new();
+                // ここまでを読み終えた状態。次の中身の開始状態へつなぐ
+                let mut ends = vec![start_state.clone()];
+                let optional = max.map_or(1, |max| max - min);
+                for i in 0..min + optional {
+                    let nfa_design = pattern.to_nfa_design_with(allocator);
+                    rules.extend(nfa_design.rulebook.rules);
+                    for end in ends.iter() {
+                        rules.push(FARule::free(end.clone(), nfa_design.start_state.clone()));
+                    }
+                    let next_ends = sorted(&nfa_design.accept_states);
+                    // max がなければ最後の1つは繰り返す
+                    if max.is_none() && i == *min {
+                        for end in next_ends.iter() {
+                            rules.push(FARule::free(end.clone(), nfa_design.start_state.clone()));
+                        }
+                    }
+                    // 最低の回数を超えたら、中身を読まずに終わってもよい
+                    if i >= *min {
+                        accept_states.extend(ends);
+                    }
+                    ends = next_ends;
+                }
+                accept_states.extend(ends);
+                NFADesign {
+                    start_state,
+                    accept_states,
+                    rulebook: NFARuleBook { rules },
+                }
+            }
+            // オートマトンはマッチした位置を覚えないので、グループは中身と同じ
+            Capture { pattern, .. } => pattern.to_nfa_design_with(allocator),
+            // NFA のままでは作れないので、DFA の積と補集合を作ってから NFA に埋め込む
+            Intersect { first, second } => embed(
+                &first.to_dfa_design().intersection(&second.to_dfa_design()),
+                allocator,
+            ),
+            Complement(pat) => embed(&pat.to_dfa_design().complement(), allocator),
         }
     }
 }
+
+// DFA の状態に allocator から番号を付け直して、同じ言語を受理する NFA にする
+fn embed(dfa_design: &DFADesign<StateInt>, allocator: &mut StateAllocator) -> NFADesign<StateInt> {
+    let mut states: Vec<&StateInt> = dfa_design
+        .rulebook
+        .rules
+        .iter()
+        .flat_map(|rule| vec![rule.state(), rule.next_state()])
+        .chain(dfa_design.accept_states.iter())
+        .chain(Some(&dfa_design.start_state))
+        .collect();
+    states.sort();
+    states.dedup();
+    let numbers: HashMap<&StateInt, StateInt> = states
+        .into_iter()
+        .map(|state| (state, allocator.allocate()))
+        .collect();
+    let rules = dfa_design
+        .rulebook
+        .rules
+        .iter()
+        .map(|rule| {
+            FARule::with_label(
+                numbers[rule.state()].clone(),
+                rule.label(),
+                numbers[rule.next_state()].clone(),
+            )
+        })
+        .collect();
+    NFADesign {
+        start_state: numbers[&dfa_design.start_state].clone(),
+        accept_states: dfa_design
+            .accept_states
+            .iter()
+            .map(|state| numbers[state].clone())
+            .collect(),
+        rulebook: NFARuleBook { rules },
+    }
+}
+
+// HashSet の順序に依存しないよう、規則を足すときは状態を番号順に並べる
+fn sorted(states: &HashSet<StateInt>) -> Vec<StateInt> {
+    let mut states: Vec<StateInt> = states.iter().cloned().collect();
+    states.sort();
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nfa::StateAllocator;
+    use crate::parse::parse;
+
+    #[test]
+    fn test_to_nfa_design_is_deterministic() {
+        let pattern = parse("a(b|)*".to_string()).unwrap();
+        let design = pattern.to_nfa_design();
+        assert_eq!(design.inspect(), pattern.to_nfa_design().inspect());
+        assert_eq!(
+            design.inspect(),
+            "#<NFADesign start: 0, accept: [8]>
+  #<FARule #0 --#a--> #1>
+  #<FARule #4 --#b--> #5>
+  #<FARule #5 --#ε--> #6>
+  #<FARule #3 --#ε--> #4>
+  #<FARule #3 --#ε--> #7>
+  #<FARule #6 --#ε--> #3>
+  #<FARule #7 --#ε--> #3>
+  #<FARule #2 --#ε--> #3>
+  #<FARule #2 --#ε--> #8>
+  #<FARule #6 --#ε--> #8>
+  #<FARule #7 --#ε--> #8>
+  #<FARule #1 --#ε--> #2>
+"
+        );
+    }
+
+    #[test]
+    fn test_literal_epsilon_character() {
+        // 以前は U+029E を自由移動の印に使っていたので、この文字が読めなかった
+        let pattern = parse("aʞ*b".to_string()).unwrap();
+        assert!(pattern.matches("aʞʞb".to_string()));
+        assert!(!pattern.matches("ab ".to_string()));
+        let pattern = parse("ʞ".to_string()).unwrap();
+        assert!(pattern.matches("ʞ".to_string()));
+        assert!(!pattern.matches("".to_string()));
+        assert_eq!(pattern.to_dfa_design().rulebook.rules.len(), 1);
+        assert!(!pattern.to_dfa_design().accepts("".to_string()));
+    }
+
+    #[test]
+    fn test_to_s() {
+        for regex in [
+            "a(b|c)*",
+            "(ab)*",
+            "(a*)*",
+            "()*",
+            "a|",
+            "[a-z0-9_]+",
+            "[^\\-a\\]]",
+            "[]",
+            ".?(ab){2}c{1,}d{0,3}",
+            "\\*\\.\\n",
+        ]
+        .iter()
+        {
+            let pattern = parse(regex.to_string()).unwrap();
+            assert_eq!(&pattern.to_s(), regex);
+            assert_eq!(parse(pattern.to_s()).unwrap().to_s(), pattern.to_s());
+        }
+    }
+
+    #[test]
+    fn test_equivalent() {
+        let pattern = |regex: &str| parse(regex.to_string()).unwrap();
+        assert_eq!(pattern("(a|b)*").equivalent(&pattern("(a*b*)*")), Ok(()));
+        assert_eq!(
+            pattern("[0-9]{1,3}").equivalent(&pattern("[0-9][0-9]?[0-9]?")),
+            Ok(())
+        );
+        assert_eq!(pattern("a[]").equivalent(&pattern("[]")), Ok(()));
+        // 片方だけにマッチする最短の文字列が返る
+        assert_eq!(
+            pattern("a*").equivalent(&pattern("a+")),
+            Err("".to_string())
+        );
+        assert_eq!(
+            pattern("[a-z]+").equivalent(&pattern("[a-y]+")),
+            Err("z".to_string())
+        );
+        assert_eq!(
+            pattern("[0-9]{1,3}").equivalent(&pattern("[0-9]{1,4}")),
+            Err("0000".to_string())
+        );
+        assert_eq!(
+            pattern("ab|ba").equivalent(&pattern("ba|ab|")),
+            Err("".to_string())
+        );
+        // 文字列全体とのマッチしか比べないので、^ と $ は区別しない
+        assert_eq!(pattern("^a$").equivalent(&pattern("a")), Ok(()));
+    }
+
+    #[test]
+    fn test_is_subset_of() {
+        let pattern = |regex: &str| parse(regex.to_string()).unwrap();
+        assert_eq!(pattern("a+").is_subset_of(&pattern("a*")), Ok(()));
+        assert_eq!(
+            pattern("a*").is_subset_of(&pattern("a+")),
+            Err("".to_string())
+        );
+        assert_eq!(pattern("[a-c]x").is_subset_of(&pattern(".x")), Ok(()));
+        // 同じ長さなら小さい文字が先に見つかる
+        assert_eq!(
+            pattern(".x").is_subset_of(&pattern("[a-c]x")),
+            Err("\u{0}x".to_string())
+        );
+        assert_eq!(pattern("[]").is_subset_of(&pattern("[]")), Ok(()));
+    }
+
+    #[test]
+    fn test_states_are_dense() {
+        let pattern = parse("(a|b)*c(d|)".to_string()).unwrap();
+        let mut allocator = StateAllocator::new();
+        let first = pattern.to_nfa_design_with(&mut allocator);
+        let count = allocator.count();
+        // 同じ allocator で続けて作っても番号は重ならない
+        let second = pattern.to_nfa_design_with(&mut allocator);
+        assert_eq!(allocator.count(), count * 2);
+        assert!(first.accepts("abac".to_string()));
+        assert!(second.accepts("abacd".to_string()));
+        assert!(!first.accepts("abd".to_string()));
+    }
+}
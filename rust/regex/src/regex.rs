@@ -0,0 +1,537 @@
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use crate::index::NFAIndex;
+use crate::language::Language;
+use crate::lazy::LazyDFA;
+use crate::nfa::{CharRange, DFADesign, NFADesign, StateInt};
+use crate::parse::{parse, ParseError};
+use crate::pattern::Pattern;
+use crate::vm::Program;
+
+// 文字列全体がマッチするかを調べる方法
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+    // 部分集合構成法で作って最小化した DFA をたどる。入力の長さに比例する時間で済む。
+    // DFA は最初に matches を呼んだときに作る
+    Dfa,
+    // NFA の状態の集合をビット列で持ち回る。入力の長さと状態の数の積に比例する時間がかかる
+    Nfa,
+    // DFA の状態を読みながら作り、決まった数までキャッシュする。
+    // 状態の数が爆発するパターンでも、あらかじめ DFA 全体を作らずに済む
+    Lazy,
+    // パターンの微分で作って最小化した DFA をたどる。NFA を経由しないので、
+    // Dfa と突き合わせて構成の誤りを見つけるのに使える
+    Derivative,
+}
+
+// Engine::Lazy でキャッシュする状態の数
+pub(crate) const LAZY_CACHE_SIZE: usize = 1000;
+
+// DFA の状態ごとに、文字の範囲の順に並べた遷移
+#[derive(Debug)]
+struct Table {
+    start: usize,
+    accepting: Vec<bool>,
+    transitions: Vec<Vec<(CharRange, usize)>>,
+}
+
+impl Table {
+    fn new(dfa_design: &DFADesign<StateInt>) -> Self {
+        let index = |state: &StateInt| state.value() as usize;
+        let mut size = index(&dfa_design.start_state) + 1;
+        for rule in dfa_design.rulebook.rules.iter() {
+            size = size
+                .max(index(rule.state()) + 1)
+                .max(index(rule.next_state()) + 1);
+        }
+        let mut accepting = vec![false; size];
+        for state in dfa_design.accept_states.iter() {
+            accepting[index(state)] = true;
+        }
+        let mut transitions = vec![vec![]; size];
+        for rule in dfa_design.rulebook.rules.iter() {
+            // DFA の規則に自由移動はない
+            if let Some(range) = rule.label().characters() {
+                transitions[index(rule.state())].push((range, index(rule.next_state())));
+            }
+        }
+        for t in transitions.iter_mut() {
+            t.sort_by_key(|(range, _)| *range);
+        }
+        Table {
+            start: index(&dfa_design.start_state),
+            accepting,
+            transitions,
+        }
+    }
+
+    fn next_state(&self, state: usize, character: char) -> Option<usize> {
+        let transitions = &self.transitions[state];
+        let i = transitions.partition_point(|(range, _)| range.start <= character);
+        match i.checked_sub(1).map(|i| transitions[i]) {
+            Some((range, next_state)) if range.contains(character) => Some(next_state),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, string: &str) -> bool {
+        let mut state = self.start;
+        for character in string.chars() {
+            match self.next_state(state, character) {
+                Some(next_state) => state = next_state,
+                None => return false,
+            }
+        }
+        self.accepting[state]
+    }
+}
+
+// 文字列全体がマッチするかを調べるもの
+#[derive(Debug)]
+enum Matcher {
+    // 最初に使うときに DFA から作る
+    Dfa(OnceLock<Table>),
+    Nfa(NFAIndex<StateInt>),
+    Lazy(LazyDFA),
+}
+
+// 一度だけコンパイルして何度でも使える正規表現
+#[derive(Debug)]
+pub struct Regex {
+    pattern: Pattern,
+    engine: Engine,
+    nfa_design: NFADesign<StateInt>,
+    // 状態の数が爆発するパターンもあるので、必要になるまで作らない
+    dfa_design: OnceLock<DFADesign<StateInt>>,
+    matcher: Matcher,
+    // 部分文字列を探すときに使う
+    program: Program,
+}
+
+impl Regex {
+    pub fn new(regex: &str) -> Result<Regex, ParseError> {
+        Regex::with_engine(regex, Engine::Dfa)
+    }
+
+    pub fn with_engine(regex: &str, engine: Engine) -> Result<Regex, ParseError> {
+        Ok(Regex::from_pattern(parse(regex.to_string())?, engine))
+    }
+
+    pub fn from_pattern(pattern: Pattern, engine: Engine) -> Regex {
+        let nfa_design = pattern.to_nfa_design();
+        let matcher = match engine {
+            Engine::Dfa | Engine::Derivative => Matcher::Dfa(OnceLock::new()),
+            Engine::Lazy => Matcher::Lazy(LazyDFA::new(&nfa_design, LAZY_CACHE_SIZE)),
+            Engine::Nfa => Matcher::Nfa(nfa_design.index()),
+        };
+        let program = Program::compile(&pattern);
+        Regex {
+            pattern,
+            engine,
+            nfa_design,
+            dfa_design: OnceLock::new(),
+            matcher,
+            program,
+        }
+    }
+
+    // 最小化した DFA。最初に呼んだときに作って取っておく
+    fn minimal_dfa_design(&self) -> &DFADesign<StateInt> {
+        self.dfa_design.get_or_init(|| match self.engine {
+            Engine::Derivative => self.pattern.to_derivative_dfa_design().minimize(),
+            _ => self.nfa_design.to_dfa_design().minimize(),
+        })
+    }
+
+    // 文字列全体がマッチするか
+    pub fn matches(&self, string: &str) -> bool {
+        match &self.matcher {
+            Matcher::Dfa(table) => table
+                .get_or_init(|| Table::new(self.minimal_dfa_design()))
+                .matches(string),
+            Matcher::Nfa(index) => index.accepts(string),
+            Matcher::Lazy(lazy_dfa) => lazy_dfa.matches(string),
+        }
+    }
+
+    // text のどこかにマッチする部分があるか
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    // 最も左から始まるマッチ。同じ位置から始まるものが複数あれば、
+    // 選択は左の選択肢を、繰り返しは長い方を優先したときに見つかるものを返す
+    pub fn find<'t>(&self, text: &'t str) -> Option<Match<'t>> {
+        self.find_at(text, 0)
+    }
+
+    fn find_at<'t>(&self, text: &'t str, start: usize) -> Option<Match<'t>> {
+        self.program.search(text, start).map(|slots| Match {
+            text,
+            start: slots[0].unwrap(),
+            end: slots[1].unwrap(),
+        })
+    }
+
+    // 最も左のマッチと、各グループがマッチした部分
+    pub fn captures<'t>(&self, text: &'t str) -> Option<Captures<'t>> {
+        self.program
+            .search(text, 0)
+            .map(|slots| Captures { text, slots })
+    }
+
+    // マッチ全体を含めたグループの数
+    pub fn captures_len(&self) -> usize {
+        self.program.groups
+    }
+
+    // 重ならないマッチを左から順に返す
+    pub fn find_iter<'r, 't>(&'r self, text: &'t str) -> Matches<'r, 't> {
+        Matches {
+            regex: self,
+            text,
+            position: Some(0),
+            last_end: None,
+        }
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    // nfa_design と dfa_design と language は文字列全体とのマッチ (matches) のためのもので、
+    // ^ と $ は何も読まない。find などで部分文字列を探すときの意味とは違う
+    pub fn nfa_design(&self) -> &NFADesign<StateInt> {
+        &self.nfa_design
+    }
+
+    // マッチする文字列全体の集合。まだ DFA を作っていなければここで作る
+    pub fn language(&self) -> Language {
+        Language::new(self.minimal_dfa_design())
+    }
+
+    // Engine::Nfa か Engine::Lazy でコンパイルしたときは None。
+    // まだ DFA を作っていなければここで作る
+    pub fn dfa_design(&self) -> Option<&DFADesign<StateInt>> {
+        match self.engine {
+            Engine::Dfa | Engine::Derivative => Some(self.minimal_dfa_design()),
+            Engine::Nfa | Engine::Lazy => None,
+        }
+    }
+}
+
+// text の中でマッチした部分。位置はバイト単位
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Match<'t> {
+    text: &'t str,
+    start: usize,
+    end: usize,
+}
+
+impl<'t> Match<'t> {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    // 文字単位の位置
+    pub fn char_range(&self) -> Range<usize> {
+        let start = self.text[..self.start].chars().count();
+        start..start + self.as_str().chars().count()
+    }
+
+    pub fn as_str(&self) -> &'t str {
+        &self.text[self.start..self.end]
+    }
+}
+
+// Regex::captures が返す、グループごとのマッチ。0 番目はマッチ全体
+#[derive(Clone, Debug, PartialEq)]
+pub struct Captures<'t> {
+    text: &'t str,
+    slots: Vec<Option<usize>>,
+}
+
+impl<'t> Captures<'t> {
+    // グループ i がマッチした部分。グループを通らずにマッチしたときは None
+    pub fn get(&self, i: usize) -> Option<Match<'t>> {
+        match (self.slots.get(i * 2)?, self.slots.get(i * 2 + 1)?) {
+            (Some(start), Some(end)) => Some(Match {
+                text: self.text,
+                start: *start,
+                end: *end,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+// Regex::find_iter が返すイテレータ
+#[derive(Debug)]
+pub struct Matches<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    // 次に探し始める位置。文字列の終わりを過ぎたら None
+    position: Option<usize>,
+    last_end: Option<usize>,
+}
+
+impl<'r, 't> Iterator for Matches<'r, 't> {
+    type Item = Match<'t>;
+
+    fn next(&mut self) -> Option<Match<'t>> {
+        loop {
+            let m = self.regex.find_at(self.text, self.position?)?;
+            self.position = if m.start == m.end {
+                // 空のマッチの後は1文字進めないと同じ位置で止まってしまう
+                self.text[m.end..]
+                    .chars()
+                    .next()
+                    .map(|c| m.end + c.len_utf8())
+            } else {
+                Some(m.end)
+            };
+            // 直前のマッチのすぐ後ろの空のマッチは数えない
+            if m.start == m.end && self.last_end == Some(m.end) {
+                continue;
+            }
+            self.last_end = Some(m.end);
+            return Some(m);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Engine, Regex};
+
+    #[test]
+    fn test_engines_agree() {
+        let inputs = ["", "a", "ab", "abab", "abba", "ba", "xyz", "a-b", "aaaaab"];
+        for regex in ["(a|b)*abb?", "a*b", "[a-z]+(-[a-z]+)?", "x.z|", ".*b{2,}"].iter() {
+            let dfa = Regex::new(regex).unwrap();
+            let nfa = Regex::with_engine(regex, Engine::Nfa).unwrap();
+            let lazy = Regex::with_engine(regex, Engine::Lazy).unwrap();
+            let derivative = Regex::with_engine(regex, Engine::Derivative).unwrap();
+            assert_eq!(dfa.dfa_design(), derivative.dfa_design());
+            assert_eq!(dfa.engine(), Engine::Dfa);
+            assert!(nfa.dfa_design().is_none());
+            assert!(lazy.dfa_design().is_none());
+            for input in inputs.iter() {
+                assert_eq!(
+                    dfa.matches(input),
+                    nfa.matches(input),
+                    "{} on {:?}",
+                    regex,
+                    input
+                );
+                assert_eq!(
+                    dfa.matches(input),
+                    lazy.matches(input),
+                    "{} on {:?}",
+                    regex,
+                    input
+                );
+                assert_eq!(dfa.matches(input), dfa.pattern().matches(input.to_string()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_dfa() {
+        let regex = Regex::new("(a|b)*b").unwrap();
+        assert!(regex.matches("aab"));
+        assert!(!regex.matches("aba"));
+        // 規則に出てこない文字を読んだらそこで失敗する
+        assert!(!regex.matches("acb"));
+        let dfa_design = regex.dfa_design().unwrap();
+        assert!(dfa_design.accepts("abb".to_string()));
+        assert!(!dfa_design.accepts("abc".to_string()));
+    }
+
+    #[test]
+    fn test_minimal_dfa() {
+        let dfa_design = |regex: &str| Regex::new(regex).unwrap().dfa_design().unwrap().clone();
+        assert_eq!(dfa_design("(a|b)*abb").rulebook.rules.len(), 8);
+        // 同じ言語を表す正規表現は同じ DFA になる
+        assert_eq!(dfa_design("(a|b)*abb"), dfa_design("(a*b*)*a(b|b)b{1}b{0}"));
+        assert_eq!(dfa_design("[a-c]|d|e"), dfa_design("[a-e]"));
+        assert_eq!(dfa_design("[a-e]").rulebook.rules.len(), 1);
+        assert_eq!(dfa_design("a?a?"), dfa_design("a{0,2}"));
+        assert_eq!(dfa_design("[]"), dfa_design("a[]b*"));
+        assert_ne!(dfa_design("a*"), dfa_design("a+"));
+    }
+
+    #[test]
+    fn test_find() {
+        let regex = Regex::new("[0-9]+").unwrap();
+        let m = regex.find("id=42, n=7").unwrap();
+        assert_eq!((m.start(), m.end(), m.as_str()), (3, 5, "42"));
+        assert!(regex.is_match("x1"));
+        assert!(!regex.is_match("xyz"));
+        assert!(!regex.matches("x1"));
+        // 位置はバイト単位と文字単位の両方で取れる
+        let m = Regex::new("b+").unwrap().find("ébbé").unwrap();
+        assert_eq!((m.range(), m.char_range()), (2..4, 1..3));
+        // 左端優先の次は、選択肢の順で決まる
+        assert_eq!(
+            Regex::new("ab|abc").unwrap().find("xabc").unwrap().as_str(),
+            "ab"
+        );
+    }
+
+    #[test]
+    fn test_intersect_and_complement() {
+        // キーワードではない識別子
+        let regex = Regex::with_engine("[a-z]+&~(if|else)", Engine::Derivative).unwrap();
+        assert!(regex.matches("iff"));
+        assert!(!regex.matches("else"));
+        let found: Vec<&str> = Regex::new("[a-z]+&~(if|else)")
+            .unwrap()
+            .find_iter("if x else")
+            .map(|m| m.as_str())
+            .collect();
+        // キーワードそのものには長くマッチしないので、手前で切れる
+        assert_eq!(found, vec!["i", "f", "x", "els", "e"]);
+        let m = Regex::new("~(.*b.*)").unwrap().find("aab").unwrap();
+        assert_eq!(m.as_str(), "aa");
+    }
+
+    #[test]
+    fn test_find_iter() {
+        let all = |regex: &str, text: &str| -> Vec<(usize, usize)> {
+            Regex::new(regex)
+                .unwrap()
+                .find_iter(text)
+                .map(|m| (m.start(), m.end()))
+                .collect()
+        };
+        assert_eq!(all("[0-9]+", "a1b22c333"), vec![(1, 2), (3, 5), (6, 9)]);
+        assert_eq!(all("a*", "baab"), vec![(0, 0), (1, 3), (4, 4)]);
+        assert_eq!(all("", "éa"), vec![(0, 0), (2, 2), (3, 3)]);
+        assert_eq!(all("^a", "aaa"), vec![(0, 1)]);
+        assert_eq!(all("a$|b", "abba"), vec![(1, 2), (2, 3), (3, 4)]);
+        assert_eq!(all("x", ""), vec![]);
+    }
+
+    #[test]
+    fn test_captures() {
+        let regex = Regex::new("([a-z]+)=([0-9]+)?;").unwrap();
+        assert_eq!(regex.captures_len(), 3);
+        let captures = regex.captures("id: user=42;").unwrap();
+        assert_eq!(captures.len(), 3);
+        assert_eq!(captures.get(0).unwrap().as_str(), "user=42;");
+        assert_eq!(captures.get(1).unwrap().as_str(), "user");
+        assert_eq!(captures.get(2).unwrap().range(), 9..11);
+        assert_eq!(captures.get(3), None);
+        let captures = regex.captures("flag=;").unwrap();
+        assert_eq!(captures.get(1).unwrap().as_str(), "flag");
+        assert_eq!(captures.get(2), None);
+        assert!(regex.captures("=1;").is_none());
+    }
+
+    #[test]
+    fn test_field_extraction() {
+        let regex = Regex::new("^([0-9]{4})-([0-9]{2})-([0-9]{2}) (INFO|ERROR) (.*)$").unwrap();
+        let captures = regex.captures("2024-03-15 ERROR disk é full").unwrap();
+        let fields: Vec<&str> = (1..regex.captures_len())
+            .map(|i| captures.get(i).unwrap().as_str())
+            .collect();
+        assert_eq!(fields, vec!["2024", "03", "15", "ERROR", "disk é full"]);
+    }
+
+    #[test]
+    fn test_log_scanning() {
+        let regex = Regex::new("ERROR [a-z]+").unwrap();
+        let log = "INFO start\nERROR disk full\nINFO retry\nERROR timeout\n";
+        let errors: Vec<&str> = regex.find_iter(log).map(|m| m.as_str()).collect();
+        assert_eq!(errors, vec!["ERROR disk", "ERROR timeout"]);
+    }
+
+    #[test]
+    fn test_long_input() {
+        let input = "abc".repeat(100_000) + "d";
+        for engine in [Engine::Dfa, Engine::Nfa, Engine::Lazy].iter() {
+            let regex = Regex::with_engine("(ab|c)*d?", *engine).unwrap();
+            assert!(regex.matches(&input));
+            assert!(!regex.matches(&(input.clone() + "d")));
+        }
+    }
+
+    // DFA は文字列全体とのマッチで初めて作るので、状態が爆発するパターンでも
+    // 部分文字列を探すだけならすぐに終わる
+    #[test]
+    fn test_dfa_built_on_demand() {
+        let start = Instant::now();
+        let regex = Regex::new("(a|b)*a(a|b){20}").unwrap();
+        assert!(regex.is_match(&format!("b{}", "a".repeat(21))));
+        assert!(!regex.is_match(&"ab".repeat(10)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(regex.dfa_design.get(), None);
+        let regex = Regex::new("(a|b)*a(a|b){2}").unwrap();
+        assert!(regex.matches("baab"));
+        assert_eq!(regex.dfa_design().unwrap().accept_states.len(), 4);
+    }
+
+    // 上限いっぱいの回数でも、展開したパターンをたどってスタックがあふれない
+    #[test]
+    fn test_large_bound() {
+        let input = "a".repeat(1000);
+        for engine in [Engine::Dfa, Engine::Nfa, Engine::Lazy].iter() {
+            let regex = Regex::with_engine("a{1000}", *engine).unwrap();
+            assert!(regex.matches(&input));
+            assert!(!regex.matches(&input[1..]));
+            let regex = Regex::with_engine("a{0,1000}b", *engine).unwrap();
+            assert!(regex.is_match(&(input.clone() + "b")));
+        }
+    }
+
+    // どのエンジンでも、ひとつの Regex を複数のスレッドで共有できる
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Regex>();
+        let regex = Regex::with_engine("(a|b)*a(a|b){7}", Engine::Lazy).unwrap();
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let regex = &regex;
+                scope.spawn(move || {
+                    for j in 0..100 {
+                        let input = format!("{:b}", i * 1000 + j)
+                            .replace('0', "b")
+                            .replace('1', "a");
+                        let expected = input.chars().rev().nth(7) == Some('a');
+                        assert_eq!(regex.matches(&input), expected, "{}", input);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(Regex::new("(a").unwrap_err().position, 2);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::lazy::LazyDFA;
+use crate::parse::{parse, ParseError};
+use crate::pattern::Pattern;
+use crate::regex::LAZY_CACHE_SIZE;
+
+// たくさんのパターンをひとつの NFA にまとめ、文字列を1回読むだけで
+// どのパターンに (文字列全体が) マッチするかを調べる。
+// 状態の組み合わせが多くなりやすいので、DFA は読みながら作る (LazyDFA)
+#[derive(Debug)]
+pub struct RegexSet {
+    patterns: Vec<Pattern>,
+    lazy_dfa: LazyDFA,
+    // NFA の状態の番号ごとに、そこを受理状態とするパターンの番号
+    owners: Vec<Option<usize>>,
+}
+
+impl RegexSet {
+    pub fn new(regexes: &[&str]) -> Result<RegexSet, ParseError> {
+        let patterns = regexes
+            .iter()
+            .map(|regex| parse(regex.to_string()))
+            .collect::<Result<Vec<Pattern>, ParseError>>()?;
+        Ok(RegexSet::from_patterns(patterns))
+    }
+
+    pub fn from_patterns(patterns: Vec<Pattern>) -> RegexSet {
+        let (nfa_design, accept_states) = Pattern::combine(&patterns);
+        let lazy_dfa = LazyDFA::new(&nfa_design, LAZY_CACHE_SIZE);
+        let mut owners = vec![];
+        for (i, states) in accept_states.iter().enumerate() {
+            for state in lazy_dfa.index().to_bitset(states).iter() {
+                if owners.len() <= state {
+                    owners.resize(state + 1, None);
+                }
+                owners[state] = Some(i);
+            }
+        }
+        RegexSet {
+            patterns,
+            lazy_dfa,
+            owners,
+        }
+    }
+
+    // string 全体にマッチするパターンの番号を小さい順に返す
+    pub fn matches(&self, string: &str) -> Vec<usize> {
+        let states = match self.lazy_dfa.run(string) {
+            Some(states) => states,
+            None => return vec![],
+        };
+        let mut ids: Vec<usize> = states
+            .iter()
+            .filter_map(|state| self.owners.get(state).cloned().flatten())
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    // どれかのパターンにマッチするか
+    pub fn is_match(&self, string: &str) -> bool {
+        self.lazy_dfa.matches(string)
+    }
+
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::RegexSet;
+
+    #[test]
+    fn test_matches() {
+        let regexes = ["[a-z]+", "[0-9]+", "a.*", ".*z", "(ab)*", "x|y"];
+        let set = RegexSet::new(&regexes).unwrap();
+        assert_eq!(set.len(), 6);
+        assert_eq!(set.matches("abz"), vec![0, 2, 3]);
+        assert_eq!(set.matches("123"), vec![1]);
+        assert_eq!(set.matches(""), vec![4]);
+        assert_eq!(set.matches("-"), Vec::<usize>::new());
+        assert!(!set.is_match("-"));
+        // 1つずつ調べたときと同じになる
+        for input in ["", "a", "ab", "abab", "x", "xyz", "a1z", "99", "Abz"].iter() {
+            let expected: Vec<usize> = set
+                .patterns()
+                .iter()
+                .enumerate()
+                .filter(|(_, pattern)| pattern.matches(input.to_string()))
+                .map(|(i, _)| i)
+                .collect();
+            assert_eq!(set.matches(input), expected, "{}", input);
+            assert_eq!(set.is_match(input), !expected.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_many_patterns() {
+        // 数百個の規則でも1回読むだけで済む
+        let regexes: Vec<String> = (0..300).map(|i| format!("id-{}(-.*)?", i)).collect();
+        let regexes: Vec<&str> = regexes.iter().map(|regex| regex.as_str()).collect();
+        let set = RegexSet::new(&regexes).unwrap();
+        assert_eq!(set.matches("id-42"), vec![42]);
+        assert_eq!(set.matches("id-7-x"), vec![7]);
+        assert_eq!(set.matches("id-299"), vec![299]);
+        assert_eq!(set.matches("id-300"), Vec::<usize>::new());
+        assert!(RegexSet::new(&[]).unwrap().is_empty());
+        assert!(RegexSet::new(&["a", "("]).is_err());
+    }
+
+    // 規則の集合を Arc で共有して、複数のスレッドから使える
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<RegexSet>();
+        let set = Arc::new(RegexSet::new(&["[a-z]+", "[0-9]+", "x.*"]).unwrap());
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let set = Arc::clone(&set);
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        assert_eq!(set.matches("xyz"), vec![0, 2]);
+                        assert_eq!(set.matches(&i.to_string()), vec![1]);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use crate::nfa::CharRange;
+use crate::pattern::Pattern::{self, *};
+
+// パターンを、分岐に優先順位のある命令列にしたもの
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
+    // 範囲のどれかに入る1文字を読む
+    Characters(Vec<CharRange>),
+    // 両方に進むが、先に書いた方を優先する
+    Split(usize, usize),
+    Jump(usize),
+    // 文字列の先頭 (^) か末尾 ($) にいるときだけ進む
+    StartOfText,
+    EndOfText,
+    // いまの位置をスロットに書く。グループ i の開始と終了はスロット 2i と 2i+1 で、
+    // グループ 0 はマッチ全体
+    Save(usize),
+    Match,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    // マッチ全体を含めたグループの数
+    pub groups: usize,
+}
+
+impl Program {
+    // 繰り返しはできるだけ長く読む方を、選択は左の選択肢を優先する
+    pub fn compile(pattern: &Pattern) -> Program {
+        let mut program = Program {
+            instructions: vec![Instruction::Save(0)],
+            groups: 1,
+        };
+        program.emit(pattern);
+        program.instructions.push(Instruction::Save(1));
+        program.instructions.push(Instruction::Match);
+        program
+    }
+
+    fn emit(&mut self, pattern: &Pattern) {
+        match pattern {
+            Empty => {}
+            Literal { .. } | Any | CharClass { .. } => self
+                .instructions
+                .push(Instruction::Characters(pattern.character_ranges().unwrap())),
+            Concatenate { first, second } => {
+                self.emit(first);
+                self.emit(second);
+            }
+            Choose { first, second } => {
+                let split = self.placeholder();
+                self.emit(first);
+                let jump = self.placeholder();
+                let second_start = self.instructions.len();
+                self.emit(second);
+                self.instructions[split] = Instruction::Split(split + 1, second_start);
+                self.instructions[jump] = Instruction::Jump(self.instructions.len());
+            }
+            Repeat(pattern) => {
+                let split = self.placeholder();
+                self.emit(pattern);
+                self.instructions.push(Instruction::Jump(split));
+                self.instructions[split] = Instruction::Split(split + 1, self.instructions.len());
+            }
+            OneOrMore(pattern) => {
+                let start = self.instructions.len();
+                self.emit(pattern);
+                let next = self.instructions.len() + 1;
+                self.instructions.push(Instruction::Split(start, next));
+            }
+            Optional(pattern) => {
+                let split = self.placeholder();
+                self.emit(pattern);
+                self.instructions[split] = Instruction::Split(split + 1, self.instructions.len());
+            }
+            // min 個並べたあとに x(x(x)?)? と同じ命令を再帰せずに並べる。
+            // 読まずに抜ける Split はすべて最後へ飛ぶ
+            Bounded { pattern, min, max } => {
+                for _ in 0..*min {
+                    self.emit(pattern);
+                }
+                match max {
+                    None => self.emit(&Repeat(pattern.clone())),
+                    Some(max) => {
+                        let splits: Vec<usize> = (*min..*max)
+                            .map(|_| {
+                                let split = self.placeholder();
+                                self.emit(pattern);
+                                split
+                            })
+                            .collect();
+                        let end = self.instructions.len();
+                        for split in splits {
+                            self.instructions[split] = Instruction::Split(split + 1, end);
+                        }
+                    }
+                }
+            }
+            StartAnchor => self.instructions.push(Instruction::StartOfText),
+            EndAnchor => self.instructions.push(Instruction::EndOfText),
+            Capture { index, pattern } => {
+                self.groups = self.groups.max(index + 1);
+                self.instructions.push(Instruction::Save(index * 2));
+                self.emit(pattern);
+                self.instructions.push(Instruction::Save(index * 2 + 1));
+            }
+            // 命令列では表せないので、同じ言語を表すパターンを DFA から作り直す。
+            // 中にあるグループの位置は取れない
+            Intersect { .. } | Complement(_) => {
+                self.emit(&Pattern::from_dfa_design(&pattern.to_dfa_design()));
+            }
+        }
+    }
+
+    // 飛び先が決まってから書き換える命令
+    fn placeholder(&mut self) -> usize {
+        self.instructions.push(Instruction::Match);
+        self.instructions.len() - 1
+    }
+
+    // text の start バイト目以降で最も左から始まるマッチのうち、優先順位が最も高いものの
+    // スロットを返す。位置はバイト単位で、通らなかったグループのスロットは None になる。
+    // スレッドを優先順位の順に並べて1文字ずつ進める (Pike VM) ので、
+    // 時間は命令数と文字列の長さの積に比例する
+    pub fn search(&self, text: &str, start: usize) -> Option<Vec<Option<usize>>> {
+        let mut current = Threads::new(self.instructions.len());
+        let mut next = Threads::new(self.instructions.len());
+        let mut matched = None;
+        let mut position = start;
+        loop {
+            // まだ見つかっていなければ、ここから始まるスレッドを最も低い優先順位で足す
+            if matched.is_none() {
+                let slots = vec![None; self.groups * 2];
+                self.add_thread(&mut current, 0, slots, position, text);
+            }
+            if current.list.is_empty() {
+                break;
+            }
+            let character = text[position..].chars().next();
+            for (pc, slots) in current.list.drain(..) {
+                match &self.instructions[pc] {
+                    Instruction::Match => {
+                        // これより優先順位の低いスレッドは捨てる
+                        matched = Some(slots);
+                        break;
+                    }
+                    Instruction::Characters(ranges) => {
+                        if let Some(c) = character {
+                            if ranges.iter().any(|range| range.contains(c)) {
+                                let after = position + c.len_utf8();
+                                self.add_thread(&mut next, pc + 1, slots, after, text);
+                            }
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            match character {
+                Some(c) => position += c.len_utf8(),
+                None => break,
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        matched
+    }
+
+    // 文字を読まない命令をたどって、文字を読む命令か Match にいるスレッドを足す
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        mut slots: Vec<Option<usize>>,
+        position: usize,
+        text: &str,
+    ) {
+        if threads.visited[pc] {
+            return;
+        }
+        threads.visited[pc] = true;
+        match &self.instructions[pc] {
+            Instruction::Jump(to) => self.add_thread(threads, *to, slots, position, text),
+            Instruction::Split(first, second) => {
+                self.add_thread(threads, *first, slots.clone(), position, text);
+                self.add_thread(threads, *second, slots, position, text);
+            }
+            Instruction::StartOfText => {
+                if position == 0 {
+                    self.add_thread(threads, pc + 1, slots, position, text);
+                }
+            }
+            Instruction::EndOfText => {
+                if position == text.len() {
+                    self.add_thread(threads, pc + 1, slots, position, text);
+                }
+            }
+            Instruction::Save(slot) => {
+                slots[*slot] = Some(position);
+                self.add_thread(threads, pc + 1, slots, position, text);
+            }
+            Instruction::Characters(_) | Instruction::Match => threads.list.push((pc, slots)),
+        }
+    }
+}
+
+// 優先順位の順に並べた (命令の位置, スロット)
+struct Threads {
+    list: Vec<(usize, Vec<Option<usize>>)>,
+    visited: Vec<bool>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Self {
+        Threads {
+            list: vec![],
+            visited: vec![false; size],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        for visited in self.visited.iter_mut() {
+            *visited = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Program;
+    use crate::parse::parse;
+
+    fn search(regex: &str, text: &str) -> Option<(usize, usize)> {
+        Program::compile(&parse(regex.to_string()).unwrap())
+            .search(text, 0)
+            .map(|slots| (slots[0].unwrap(), slots[1].unwrap()))
+    }
+
+    fn groups(regex: &str, text: &str) -> Option<Vec<Option<usize>>> {
+        Program::compile(&parse(regex.to_string()).unwrap()).search(text, 0)
+    }
+
+    #[test]
+    fn test_search() {
+        assert_eq!(search("b+", "aabbbc"), Some((2, 5)));
+        assert_eq!(search("x", "aabbbc"), None);
+        assert_eq!(search("a*", "baa"), Some((0, 0)));
+        // 最も左から始まるものを選び、その中では優先順位で選ぶ
+        assert_eq!(search("b|bc", "abc"), Some((1, 2)));
+        assert_eq!(search("bc|b", "abc"), Some((1, 3)));
+        assert_eq!(search("a+a?", "aaa"), Some((0, 3)));
+        assert_eq!(search("(a|ab)(c|bcd)", "abcd"), Some((0, 4)));
+        assert_eq!(search("é+", "cafééé!"), Some((3, 9)));
+        // 繰り返しの中身が空文字列にマッチしても止まる
+        assert_eq!(search("(a*)*b", "aab"), Some((0, 3)));
+        assert_eq!(search("a{2,3}", "baaaa"), Some((1, 4)));
+        assert_eq!(search("a{2,}b", "aaaab"), Some((0, 5)));
+        assert_eq!(search("a{2}", "aba"), None);
+    }
+
+    #[test]
+    fn test_anchors() {
+        assert_eq!(search("^a", "aa"), Some((0, 1)));
+        assert_eq!(search("^b", "ab"), None);
+        assert_eq!(search("a$", "aa"), Some((1, 2)));
+        assert_eq!(search("^$", ""), Some((0, 0)));
+        assert_eq!(search("^$", "a"), None);
+        assert_eq!(search("x|b$", "abx"), Some((2, 3)));
+        let program = Program::compile(&parse("^a".to_string()).unwrap());
+        assert_eq!(program.search("aa", 1), None);
+    }
+
+    #[test]
+    fn test_slots() {
+        assert_eq!(
+            groups("(a+)(b)?c", "xaac"),
+            Some(vec![Some(1), Some(4), Some(1), Some(3), None, None])
+        );
+        // 繰り返しの中のグループは最後に通った位置になる
+        assert_eq!(
+            groups("(?:(a)|b)*", "abb"),
+            Some(vec![Some(0), Some(3), Some(0), Some(1)])
+        );
+        assert_eq!(
+            groups("(a|ab)(c|bcd)", "abcd"),
+            Some(vec![Some(0), Some(4), Some(0), Some(1), Some(1), Some(4)])
+        );
+        assert_eq!(groups("(x)", "abc"), None);
+        let program = Program::compile(&parse("((a)(b))".to_string()).unwrap());
+        assert_eq!(program.groups, 4);
+    }
+}
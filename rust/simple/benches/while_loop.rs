@@ -0,0 +1,81 @@
+// cargo bench --bench while_loop
+//
+// 関係のない変数を大量に持った環境で While を回し、1 回あたりの時間が
+// 繰り返しの回数によらずほぼ一定であることを確かめる
+use std::time::Instant;
+
+use simple::environment::Environment;
+use simple::expression::Expression;
+use simple::expression::Expression::{Add, LessThan, Number};
+use simple::statement::Statement::{Sequence, While};
+use simple::statement::{Machine, Statement};
+
+fn count_to(n: i32) -> Statement {
+    Sequence {
+        first: Box::new(Statement::new_assign("x", Number(0))),
+        second: Box::new(While {
+            condition: LessThan {
+                left: Box::new(Expression::new_var("x")),
+                right: Box::new(Number(n)),
+            },
+            body: Box::new(Statement::new_assign(
+                "x",
+                Add {
+                    left: Box::new(Expression::new_var("x")),
+                    right: Box::new(Number(1)),
+                },
+            )),
+        }),
+    }
+}
+
+// 1 回あたりの時間 (ns)。ばらつきを抑えるため、何度か測って一番速いものを使う
+fn per_iteration(env: &Environment, n: i32) -> (f64, f64) {
+    let mut evaluate = f64::INFINITY;
+    let mut run = f64::INFINITY;
+    for _ in 0..5 {
+        let start = Instant::now();
+        let result = count_to(n).evaluate(&mut env.clone());
+        evaluate = evaluate.min(start.elapsed().as_nanos() as f64 / n as f64);
+        assert_eq!(result.get("x"), Some(&Number(n)));
+
+        let mut m = Machine {
+            statement: count_to(n),
+            environment: env.clone(),
+        };
+        let start = Instant::now();
+        m.run();
+        run = run.min(start.elapsed().as_nanos() as f64 / n as f64);
+        assert_eq!(m.environment.get("x"), Some(&Number(n)));
+    }
+    (evaluate, run)
+}
+
+fn main() {
+    let mut env = Expression::new_env();
+    for i in 0..1000 {
+        env.insert(format!("unused{}", i), Number(i));
+    }
+
+    println!("{:>8} {:>14} {:>14}", "n", "evaluate ns/it", "run ns/it");
+    let mut times = vec![];
+    for &n in [1000, 10000].iter() {
+        let (evaluate, run) = per_iteration(&env, n);
+        println!("{:>8} {:>14.0} {:>14.0}", n, evaluate, run);
+        times.push((evaluate, run));
+    }
+
+    // 1 回あたりの時間が回数に比例して増えるなら (環境を毎回作り直すなど)、比は 10 に近くなる
+    let evaluate_ratio = times[1].0 / times[0].0;
+    let run_ratio = times[1].1 / times[0].1;
+    println!(
+        "{:>8} {:>14.2} {:>14.2}",
+        "10k/1k", evaluate_ratio, run_ratio
+    );
+    assert!(
+        evaluate_ratio < 3.0,
+        "evaluate slows down: {:.2}",
+        evaluate_ratio
+    );
+    assert!(run_ratio < 3.0, "run slows down: {:.2}", run_ratio);
+}
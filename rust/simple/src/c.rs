@@ -0,0 +1,449 @@
+use crate::expression::Expression;
+use crate::expression::Expression::{
+    Add, Boolean, Concat, Equal, Inspect, Length, LessThan, Multiply, Number, Parse, Slice, Str,
+    Variable,
+};
+use crate::statement::Statement;
+use crate::statement::Statement::{Assign, DoNothing, If, Sequence, While};
+
+// 変数は未定義・数値・真偽値・文字列のいずれかを持つ
+// 加算と乗算は Expression::evaluate と同じく 2 の補数で折り返す
+// 文字列は UTF-8 のバイト列で、長さと切り出しは文字単位で数える。確保したメモリは解放しない
+const PRELUDE: &str = r#"#include <limits.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+enum kind { UNDEFINED, NUMBER, BOOLEAN, STRING };
+
+typedef struct {
+    enum kind kind;
+    int32_t value;
+    const char *string;
+} value;
+
+static inline void fail(const char *message, const char *name) {
+    fprintf(stderr, message, name);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+static inline value number(int32_t n) {
+    value v = {NUMBER, n, NULL};
+    return v;
+}
+
+static inline value boolean(int b) {
+    value v = {BOOLEAN, b != 0, NULL};
+    return v;
+}
+
+static inline value string(const char *s) {
+    value v = {STRING, 0, s};
+    return v;
+}
+
+static inline int32_t get_number(value v) {
+    if (v.kind != NUMBER) {
+        fail("not a number", "");
+    }
+    return v.value;
+}
+
+static inline int get_bool(value v) {
+    if (v.kind != BOOLEAN) {
+        fail("not a boolean", "");
+    }
+    return v.value;
+}
+
+static inline const char *get_string(value v) {
+    if (v.kind != STRING) {
+        fail("not a string", "");
+    }
+    return v.string;
+}
+
+static inline value lookup(value v, const char *name) {
+    if (v.kind == UNDEFINED) {
+        fail("variable %s does not exist", name);
+    }
+    return v;
+}
+
+static inline value add(value left, value right) {
+    return number((int32_t)((uint32_t)get_number(left) + (uint32_t)get_number(right)));
+}
+
+static inline value multiply(value left, value right) {
+    return number((int32_t)((uint32_t)get_number(left) * (uint32_t)get_number(right)));
+}
+
+static inline value less_than(value left, value right) {
+    return boolean(get_number(left) < get_number(right));
+}
+
+static inline value concat(value left, value right) {
+    const char *l = get_string(left), *r = get_string(right);
+    size_t nl = strlen(l), nr = strlen(r);
+    char *s = malloc(nl + nr + 1);
+    memcpy(s, l, nl);
+    memcpy(s + nl, r, nr + 1);
+    return string(s);
+}
+
+static inline value equal(value left, value right) {
+    if (left.kind != right.kind) {
+        return boolean(0);
+    }
+    if (left.kind == STRING) {
+        return boolean(strcmp(left.string, right.string) == 0);
+    }
+    return boolean(left.value == right.value);
+}
+
+/* UTF-8 の継続バイト以外を数える */
+static inline int32_t count_chars(const char *s, const char *end) {
+    int32_t n = 0;
+    for (; s < end && *s; s++) {
+        n += ((unsigned char)*s & 0xc0) != 0x80;
+    }
+    return n;
+}
+
+static inline const char *skip_chars(const char *s, int32_t n) {
+    while (*s && n > 0) {
+        s++;
+        while (((unsigned char)*s & 0xc0) == 0x80) {
+            s++;
+        }
+        n--;
+    }
+    return s;
+}
+
+static inline value length(value v) {
+    const char *s = get_string(v);
+    return number(count_chars(s, s + strlen(s)));
+}
+
+static inline value slice(value v, value start, value end) {
+    const char *s = get_string(v);
+    int32_t a = get_number(start), b = get_number(end);
+    if (a < 0) {
+        a = 0;
+    }
+    if (b <= a) {
+        return string("");
+    }
+    const char *from = skip_chars(s, a);
+    const char *to = skip_chars(from, b - a);
+    char *result = malloc(to - from + 1);
+    memcpy(result, from, to - from);
+    result[to - from] = '\0';
+    return string(result);
+}
+
+static inline value inspect(value v) {
+    char *result;
+    switch (v.kind) {
+    case NUMBER:
+        result = malloc(12);
+        sprintf(result, "%d", v.value);
+        return string(result);
+    case BOOLEAN:
+        return string(v.value ? "true" : "false");
+    case STRING: {
+        char *p = result = malloc(2 * strlen(v.string) + 3);
+        *p++ = '"';
+        for (const char *s = v.string; *s; s++) {
+            switch (*s) {
+            case '"': *p++ = '\\'; *p++ = '"'; break;
+            case '\\': *p++ = '\\'; *p++ = '\\'; break;
+            case '\n': *p++ = '\\'; *p++ = 'n'; break;
+            case '\t': *p++ = '\\'; *p++ = 't'; break;
+            case '\r': *p++ = '\\'; *p++ = 'r'; break;
+            default: *p++ = *s;
+            }
+        }
+        *p++ = '"';
+        *p = '\0';
+        return string(result);
+    }
+    default:
+        fail("not a value", "");
+        return v;
+    }
+}
+
+static inline value parse(value v) {
+    const char *s = get_string(v);
+    size_t n = strlen(s);
+    if (strcmp(s, "true") == 0 || strcmp(s, "false") == 0) {
+        return boolean(s[0] == 't');
+    }
+    if (n > 0 && (s[0] == '+' || s[0] == '-' || (s[0] >= '0' && s[0] <= '9'))) {
+        size_t i = (s[0] == '+' || s[0] == '-') ? 1 : 0;
+        int64_t n64 = 0;
+        int ok = i < n;
+        for (; ok && i < n; i++) {
+            ok = s[i] >= '0' && s[i] <= '9';
+            n64 = n64 * 10 + (s[i] - '0');
+            ok = ok && n64 <= (int64_t)INT32_MAX + 1;
+        }
+        if (s[0] == '-') {
+            n64 = -n64;
+        }
+        if (ok && n64 >= INT32_MIN && n64 <= INT32_MAX) {
+            return number((int32_t)n64);
+        }
+    }
+    if (n >= 2 && s[0] == '"' && s[n - 1] == '"') {
+        char *result = malloc(n), *p = result;
+        for (size_t i = 1; i < n - 1; i++) {
+            if (s[i] == '\\' && i + 1 < n - 1) {
+                switch (s[++i]) {
+                case 'n': *p++ = '\n'; break;
+                case 't': *p++ = '\t'; break;
+                case 'r': *p++ = '\r'; break;
+                case '"': case '\\': *p++ = s[i]; break;
+                default: fail("cannot parse %s", s);
+                }
+            } else if (s[i] == '\\' || s[i] == '"') {
+                fail("cannot parse %s", s);
+            } else {
+                *p++ = s[i];
+            }
+        }
+        *p = '\0';
+        return string(result);
+    }
+    fail("cannot parse %s", s);
+    return v;
+}
+
+static inline void print(const char *name, value v) {
+    switch (v.kind) {
+    case UNDEFINED:
+        break;
+    case NUMBER:
+        printf("%s = %d\n", name, v.value);
+        break;
+    case BOOLEAN:
+        printf("%s = %s\n", name, v.value ? "true" : "false");
+        break;
+    case STRING:
+        printf("%s = %s\n", name, inspect(v).string);
+        break;
+    }
+}
+"#;
+
+// SIMPLE の変数名はどんな文字列でもよいので、バイト列を16進にして C の識別子にする
+fn c_name(name: &str) -> String {
+    let hex: String = name.bytes().map(|b| format!("{:02x}", b)).collect();
+    format!("v_{}", hex)
+}
+
+fn c_string(s: &str) -> String {
+    let escaped: String = s
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b == b'_' || b == b' ' {
+                (b as char).to_string()
+            } else {
+                format!("\\{:03o}", b)
+            }
+        })
+        .collect();
+    format!("\"{}\"", escaped)
+}
+
+fn indent(level: usize) -> String {
+    "    ".repeat(level)
+}
+
+impl Expression {
+    pub fn to_c(&self) -> String {
+        match self {
+            Number(value) if *value == i32::MIN => "number(INT32_MIN)".to_string(),
+            Number(value) => format!("number({})", value),
+            Boolean(value) => format!("boolean({})", *value as i32),
+            Variable(name) => format!("lookup({}, {})", c_name(name), c_string(name)),
+            Add { left, right } => format!("add({}, {})", left.to_c(), right.to_c()),
+            Multiply { left, right } => format!("multiply({}, {})", left.to_c(), right.to_c()),
+            LessThan { left, right } => format!("less_than({}, {})", left.to_c(), right.to_c()),
+            Str(value) => format!("string({})", c_string(value)),
+            Concat { left, right } => format!("concat({}, {})", left.to_c(), right.to_c()),
+            Equal { left, right } => format!("equal({}, {})", left.to_c(), right.to_c()),
+            Length(string) => format!("length({})", string.to_c()),
+            Slice { string, start, end } => {
+                format!("slice({}, {}, {})", string.to_c(), start.to_c(), end.to_c())
+            }
+            Inspect(value) => format!("inspect({})", value.to_c()),
+            Parse(string) => format!("parse({})", string.to_c()),
+        }
+    }
+
+    pub(crate) fn collect_variables(&self, names: &mut Vec<String>) {
+        match self {
+            Number(_) | Boolean(_) | Str(_) => {}
+            Variable(name) => names.push(name.clone()),
+            Add { left, right }
+            | Multiply { left, right }
+            | LessThan { left, right }
+            | Concat { left, right }
+            | Equal { left, right } => {
+                left.collect_variables(names);
+                right.collect_variables(names);
+            }
+            Length(value) | Inspect(value) | Parse(value) => value.collect_variables(names),
+            Slice { string, start, end } => {
+                string.collect_variables(names);
+                start.collect_variables(names);
+                end.collect_variables(names);
+            }
+        }
+    }
+}
+
+impl Statement {
+    // 空の環境から実行し、最後に定義済みの変数を名前順に `name = value` の形で出力する
+    pub fn to_c(&self) -> String {
+        let mut names = vec![];
+        self.collect_variables(&mut names);
+        names.sort();
+        names.dedup();
+
+        let mut program = String::from(PRELUDE);
+        program += "\n";
+        for name in names.iter() {
+            program += &format!("static value {} = {{UNDEFINED, 0}};\n", c_name(name));
+        }
+        program += "\nint main(void) {\n";
+        program += &self.to_c_statement(1);
+        for name in names.iter() {
+            program += &format!(
+                "{}print({}, {});\n",
+                indent(1),
+                c_string(name),
+                c_name(name)
+            );
+        }
+        program += &format!("{}return 0;\n}}\n", indent(1));
+        program
+    }
+
+    fn to_c_statement(&self, level: usize) -> String {
+        match self {
+            DoNothing => String::new(),
+            Assign { name, expression } => format!(
+                "{}{} = {};\n",
+                indent(level),
+                c_name(name),
+                expression.to_c()
+            ),
+            If {
+                condition,
+                consequence,
+                alternative,
+            } => format!(
+                "{}if (get_bool({})) {{\n{}{}}} else {{\n{}{}}}\n",
+                indent(level),
+                condition.to_c(),
+                consequence.to_c_statement(level + 1),
+                indent(level),
+                alternative.to_c_statement(level + 1),
+                indent(level)
+            ),
+            While { condition, body } => format!(
+                "{}while (get_bool({})) {{\n{}{}}}\n",
+                indent(level),
+                condition.to_c(),
+                body.to_c_statement(level + 1),
+                indent(level)
+            ),
+            Sequence { first, second } => format!(
+                "{}{}",
+                first.to_c_statement(level),
+                second.to_c_statement(level)
+            ),
+        }
+    }
+
+    pub(crate) fn collect_variables(&self, names: &mut Vec<String>) {
+        match self {
+            DoNothing => {}
+            Assign { name, expression } => {
+                names.push(name.clone());
+                expression.collect_variables(names);
+            }
+            If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                condition.collect_variables(names);
+                consequence.collect_variables(names);
+                alternative.collect_variables(names);
+            }
+            While { condition, body } => {
+                condition.collect_variables(names);
+                body.collect_variables(names);
+            }
+            Sequence { first, second } => {
+                first.collect_variables(names);
+                second.collect_variables(names);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{c_name, c_string};
+    use crate::expression::Expression;
+    use crate::expression::Expression::{Boolean, LessThan, Number};
+    use crate::statement::Statement;
+    use crate::statement::Statement::While;
+
+    #[test]
+    fn test_names() {
+        assert_eq!(c_name("x"), "v_78");
+        assert_eq!(c_name("a b"), "v_612062");
+        assert_eq!(c_string("x y"), "\"x y\"");
+        assert_eq!(c_string("\"é"), "\"\\042\\303\\251\"");
+    }
+
+    #[test]
+    fn test_expression_to_c() {
+        let lt = LessThan {
+            left: Box::new(Expression::new_var("x")),
+            right: Box::new(Expression::new_add(1, -2)),
+        };
+        assert_eq!(
+            lt.to_c(),
+            "less_than(lookup(v_78, \"x\"), add(number(1), number(-2)))"
+        );
+        assert_eq!(Boolean(true).to_c(), "boolean(1)");
+        assert_eq!(Number(i32::MIN).to_c(), "number(INT32_MIN)");
+    }
+
+    #[test]
+    fn test_statement_to_c() {
+        let st = While {
+            condition: LessThan {
+                left: Box::new(Expression::new_var("x")),
+                right: Box::new(Number(3)),
+            },
+            body: Box::new(Statement::new_assign("x", Number(3))),
+        };
+        let program = st.to_c();
+        assert!(program.contains("static value v_78 = {UNDEFINED, 0};\n"));
+        assert!(program.contains(
+            "    while (get_bool(less_than(lookup(v_78, \"x\"), number(3)))) {\n        v_78 = number(3);\n    }\n"
+        ));
+        assert!(program.contains("    print(\"x\", v_78);\n"));
+    }
+}
@@ -0,0 +1,291 @@
+use std::cmp::{max, Ordering};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::expression::Expression;
+
+// 永続的な AVL 木。更新は根からの経路だけをコピーするので O(log n) で済み、
+// 残りの部分木は古い環境と共有される
+struct Node {
+    name: String,
+    value: Expression,
+    height: usize,
+    left: Tree,
+    right: Tree,
+}
+
+type Tree = Option<Rc<Node>>;
+
+fn height(tree: &Tree) -> usize {
+    tree.as_ref().map_or(0, |node| node.height)
+}
+
+fn node(name: String, value: Expression, left: Tree, right: Tree) -> Rc<Node> {
+    Rc::new(Node {
+        name,
+        value,
+        height: max(height(&left), height(&right)) + 1,
+        left,
+        right,
+    })
+}
+
+fn rotate_right(name: String, value: Expression, left: Rc<Node>, right: Tree) -> Rc<Node> {
+    node(
+        left.name.clone(),
+        left.value.clone(),
+        left.left.clone(),
+        Some(node(name, value, left.right.clone(), right)),
+    )
+}
+
+fn rotate_left(name: String, value: Expression, left: Tree, right: Rc<Node>) -> Rc<Node> {
+    node(
+        right.name.clone(),
+        right.value.clone(),
+        Some(node(name, value, left, right.left.clone())),
+        right.right.clone(),
+    )
+}
+
+fn balance(name: String, value: Expression, left: Tree, right: Tree) -> Rc<Node> {
+    let (hl, hr) = (height(&left), height(&right));
+    if hl > hr + 1 {
+        let l = left.unwrap();
+        if height(&l.left) >= height(&l.right) {
+            rotate_right(name, value, l, right)
+        } else {
+            let inner = l.right.clone().unwrap();
+            let l = rotate_left(l.name.clone(), l.value.clone(), l.left.clone(), inner);
+            rotate_right(name, value, l, right)
+        }
+    } else if hr > hl + 1 {
+        let r = right.unwrap();
+        if height(&r.right) >= height(&r.left) {
+            rotate_left(name, value, left, r)
+        } else {
+            let inner = r.left.clone().unwrap();
+            let r = rotate_right(r.name.clone(), r.value.clone(), inner, r.right.clone());
+            rotate_left(name, value, left, r)
+        }
+    } else {
+        node(name, value, left, right)
+    }
+}
+
+fn insert(tree: &Tree, name: String, value: Expression) -> Rc<Node> {
+    match tree {
+        None => node(name, value, None, None),
+        Some(n) => match name.cmp(&n.name) {
+            Ordering::Less => balance(
+                n.name.clone(),
+                n.value.clone(),
+                Some(insert(&n.left, name, value)),
+                n.right.clone(),
+            ),
+            Ordering::Greater => balance(
+                n.name.clone(),
+                n.value.clone(),
+                n.left.clone(),
+                Some(insert(&n.right, name, value)),
+            ),
+            Ordering::Equal => node(name, value, n.left.clone(), n.right.clone()),
+        },
+    }
+}
+
+fn lookup<'a>(tree: &'a Tree, name: &str) -> Option<&'a Expression> {
+    let mut current = tree;
+    while let Some(n) = current {
+        match name.cmp(&n.name) {
+            Ordering::Less => current = &n.left,
+            Ordering::Greater => current = &n.right,
+            Ordering::Equal => return Some(&n.value),
+        }
+    }
+    None
+}
+
+fn walk<'a>(tree: &'a Tree, bindings: &mut Vec<(&'a String, &'a Expression)>) {
+    if let Some(n) = tree {
+        walk(&n.left, bindings);
+        bindings.push((&n.name, &n.value));
+        walk(&n.right, bindings);
+    }
+}
+
+// 変数の束縛をスコープごとに持つ。clone は O(1) で、元の環境は変更されない
+#[derive(Clone, Default)]
+pub struct Environment {
+    scope: Tree,
+    parent: Option<Rc<Environment>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            scope: None,
+            parent: None,
+        }
+    }
+
+    // 現在のスコープに束縛を追加する。外側のスコープの同名の変数は隠れる
+    pub fn insert(&mut self, name: String, value: Expression) {
+        self.scope = Some(insert(&self.scope, name, value));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Expression> {
+        lookup(&self.scope, name).or_else(|| self.parent.as_ref().and_then(|p| p.get(name)))
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn enter_scope(&self) -> Environment {
+        Environment {
+            scope: None,
+            parent: Some(Rc::new(self.clone())),
+        }
+    }
+
+    // 一番外側のスコープでは None を返す
+    pub fn leave_scope(&self) -> Option<Environment> {
+        self.parent.as_ref().map(|p| (**p).clone())
+    }
+
+    // 見えている束縛を名前順にたどる
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Expression)> {
+        self.bindings().into_iter()
+    }
+
+    // 見えている束縛を名前順に並べる。内側のスコープと外側のスコープの並びを合わせる
+    fn bindings(&self) -> Vec<(&String, &Expression)> {
+        let mut bindings = vec![];
+        walk(&self.scope, &mut bindings);
+        if let Some(parent) = &self.parent {
+            let inner = bindings;
+            let outer = parent.bindings();
+            bindings = Vec::with_capacity(inner.len() + outer.len());
+            let (mut i, mut o) = (0, 0);
+            while i < inner.len() || o < outer.len() {
+                if o == outer.len() || (i < inner.len() && inner[i].0 <= outer[o].0) {
+                    if o < outer.len() && inner[i].0 == outer[o].0 {
+                        o += 1;
+                    }
+                    bindings.push(inner[i]);
+                    i += 1;
+                } else {
+                    bindings.push(outer[o]);
+                    o += 1;
+                }
+            }
+        }
+        bindings
+    }
+
+    pub fn keys(&self) -> Vec<&String> {
+        self.iter().map(|(name, _)| name).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bindings().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl PartialEq for Environment {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{height, Environment};
+    use crate::expression::Expression::{Boolean, Number};
+
+    #[test]
+    fn test_insert_and_get() {
+        let mut env = Environment::new();
+        assert!(env.is_empty());
+        env.insert("x".to_string(), Number(1));
+        env.insert("y".to_string(), Boolean(true));
+        env.insert("x".to_string(), Number(2));
+        assert_eq!(env.get("x"), Some(&Number(2)));
+        assert_eq!(env.get("y"), Some(&Boolean(true)));
+        assert_eq!(env.get("z"), None);
+        assert_eq!(env.len(), 2);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let mut old = Environment::new();
+        old.insert("x".to_string(), Number(1));
+        let mut new = old.clone();
+        new.insert("x".to_string(), Number(2));
+        new.insert("y".to_string(), Number(3));
+        assert_eq!(old.get("x"), Some(&Number(1)));
+        assert!(!old.contains_key("y"));
+        assert_eq!(new.get("x"), Some(&Number(2)));
+    }
+
+    #[test]
+    fn test_balanced() {
+        let mut env = Environment::new();
+        for i in 0..10000 {
+            env.insert(format!("{:05}", i), Number(i));
+        }
+        // AVL 木の高さは 1.44 log2(n) 程度に収まる
+        assert!(height(&env.scope) <= 20);
+        assert_eq!(env.get("01234"), Some(&Number(1234)));
+        let names = env.keys();
+        assert_eq!(names.len(), 10000);
+        assert!(names.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_scopes() {
+        let mut outer = Environment::new();
+        outer.insert("x".to_string(), Number(1));
+        outer.insert("z".to_string(), Number(3));
+        let mut inner = outer.enter_scope();
+        inner.insert("x".to_string(), Number(10));
+        inner.insert("y".to_string(), Number(20));
+        assert_eq!(inner.get("x"), Some(&Number(10)));
+        assert_eq!(inner.get("z"), Some(&Number(3)));
+        assert_eq!(
+            inner.iter().collect::<Vec<_>>(),
+            vec![
+                (&"x".to_string(), &Number(10)),
+                (&"y".to_string(), &Number(20)),
+                (&"z".to_string(), &Number(3)),
+            ]
+        );
+        let left = inner.leave_scope().unwrap();
+        assert_eq!(left, outer);
+        assert_eq!(left.get("x"), Some(&Number(1)));
+        assert!(!left.contains_key("y"));
+        assert_eq!(outer.leave_scope(), None);
+    }
+
+    #[test]
+    fn test_equality_ignores_structure() {
+        let mut a = Environment::new();
+        a.insert("x".to_string(), Number(1));
+        let mut b = Environment::new().enter_scope();
+        b.insert("x".to_string(), Number(1));
+        assert_eq!(a, b);
+        b.insert("x".to_string(), Number(2));
+        assert_ne!(a, b);
+    }
+}
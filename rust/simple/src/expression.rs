@@ -1,5 +1,4 @@
-use std::collections::HashMap;
-pub type Environment = HashMap<String, Expression>;
+pub use crate::environment::Environment;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expression {
@@ -18,9 +17,29 @@ pub enum Expression {
         right: Box<Expression>,
     },
     Variable(String),
+    Str(String),
+    Concat {
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    Equal {
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    Length(Box<Expression>),
+    Slice {
+        string: Box<Expression>,
+        start: Box<Expression>,
+        end: Box<Expression>,
+    },
+    Inspect(Box<Expression>),
+    Parse(Box<Expression>),
 }
 
-use Expression::{Add, Boolean, LessThan, Multiply, Number, Variable};
+use Expression::{
+    Add, Boolean, Concat, Equal, Inspect, Length, LessThan, Multiply, Number, Parse, Slice, Str,
+    Variable,
+};
 
 impl Expression {
     pub fn to_string(&self) -> String {
@@ -31,12 +50,24 @@ impl Expression {
             Multiply { left, right } => format!("{} * {}", left.to_string(), right.to_string()),
             LessThan { left, right } => format!("{} < {}", left.to_string(), right.to_string()),
             Variable(name) => name.to_string(),
+            Str(_) => self.inspect(),
+            Concat { left, right } => format!("{} ++ {}", left.to_string(), right.to_string()),
+            Equal { left, right } => format!("{} == {}", left.to_string(), right.to_string()),
+            Length(string) => format!("length({})", string.to_string()),
+            Slice { string, start, end } => format!(
+                "slice({}, {}, {})",
+                string.to_string(),
+                start.to_string(),
+                end.to_string()
+            ),
+            Inspect(value) => format!("inspect({})", value.to_string()),
+            Parse(string) => format!("parse({})", string.to_string()),
         }
     }
 
     pub fn reducible(&self) -> bool {
         match self {
-            Number(_) | Boolean(_) => false,
+            Number(_) | Boolean(_) | Str(_) => false,
             _ => true,
         }
     }
@@ -45,11 +76,18 @@ impl Expression {
         match self {
             Number(_) => unreachable!(),
             Boolean(_) => unreachable!(),
+            Str(_) => unreachable!(),
             // Box<T>のdereferenceは*でよい
             Add { left, right } => reduce_add(*left, *right, env),
             Multiply { left, right } => reduce_multiply(*left, *right, env),
             LessThan { left, right } => reduce_lessthan(*left, *right, env),
             Variable(name) => reduce_variable(name, env),
+            Concat { left, right } => reduce_concat(*left, *right, env),
+            Equal { left, right } => reduce_equal(*left, *right, env),
+            Length(string) => reduce_length(*string, env),
+            Slice { string, start, end } => reduce_slice(*string, *start, *end, env),
+            Inspect(value) => reduce_inspect(*value, env),
+            Parse(string) => reduce_parse(*string, env),
         }
     }
 
@@ -57,15 +95,26 @@ impl Expression {
         match self {
             Number(_) => self,
             Boolean(_) => self,
+            Str(_) => self,
             Add { left, right } => {
                 let eval_l = left.evaluate(env);
                 let eval_r = right.evaluate(env);
-                Number(eval_l.get_number().unwrap() + eval_r.get_number().unwrap())
+                Number(
+                    eval_l
+                        .get_number()
+                        .unwrap()
+                        .wrapping_add(eval_r.get_number().unwrap()),
+                )
             }
             Multiply { left, right } => {
                 let eval_l = left.evaluate(env);
                 let eval_r = right.evaluate(env);
-                Number(eval_l.get_number().unwrap() * eval_r.get_number().unwrap())
+                Number(
+                    eval_l
+                        .get_number()
+                        .unwrap()
+                        .wrapping_mul(eval_r.get_number().unwrap()),
+                )
             }
             LessThan { left, right } => {
                 let eval_l = left.evaluate(env);
@@ -76,6 +125,33 @@ impl Expression {
                 .get(&name)
                 .expect(&format!("variable {} does not exist", name))
                 .clone(),
+            Concat { left, right } => {
+                let eval_l = left.evaluate(env);
+                let eval_r = right.evaluate(env);
+                Str(eval_l.get_str().unwrap() + &eval_r.get_str().unwrap())
+            }
+            Equal { left, right } => {
+                let eval_l = left.evaluate(env);
+                let eval_r = right.evaluate(env);
+                Boolean(eval_l == eval_r)
+            }
+            Length(string) => {
+                Number(string.evaluate(env).get_str().unwrap().chars().count() as i32)
+            }
+            Slice { string, start, end } => {
+                let eval_s = string.evaluate(env);
+                let eval_start = start.evaluate(env);
+                let eval_end = end.evaluate(env);
+                Str(slice(
+                    &eval_s.get_str().unwrap(),
+                    eval_start.get_number().unwrap(),
+                    eval_end.get_number().unwrap(),
+                ))
+            }
+            Inspect(value) => Str(value.evaluate(env).inspect()),
+            Parse(string) => {
+                Expression::parse_literal(&string.evaluate(env).get_str().unwrap()).unwrap()
+            }
         }
     }
 
@@ -98,9 +174,90 @@ impl Expression {
                 left.to_ruby(),
                 right.to_ruby()
             ),
+            Str(value) => format!("-> e {{ {} }}", ruby_string(value)),
+            Concat { left, right } => format!(
+                "-> e {{ ({}).call(e) + ({}).call(e) }}",
+                left.to_ruby(),
+                right.to_ruby()
+            ),
+            Equal { left, right } => format!(
+                "-> e {{ ({}).call(e) == ({}).call(e) }}",
+                left.to_ruby(),
+                right.to_ruby()
+            ),
+            Length(string) => format!("-> e {{ ({}).call(e).length }}", string.to_ruby()),
+            Slice { string, start, end } => format!(
+                "-> e {{ ({}).call(e)[[({}).call(e), 0].max...[({}).call(e), 0].max].to_s }}",
+                string.to_ruby(),
+                start.to_ruby(),
+                end.to_ruby()
+            ),
+            Inspect(value) => format!("-> e {{ ({}).call(e).inspect }}", value.to_ruby()),
+            Parse(string) => format!(
+                "-> e {{ s = ({}).call(e); s =~ /\\A[-+]?\\d+\\z/ ? s.to_i : eval(s) }}",
+                string.to_ruby()
+            ),
         }
     }
 
+    // 値をそのまま読み戻せるリテラルの文字列にする
+    pub fn inspect(&self) -> String {
+        match self {
+            Number(value) => value.to_string(),
+            Boolean(value) => value.to_string(),
+            Str(value) => {
+                let mut s = String::from("\"");
+                for c in value.chars() {
+                    match c {
+                        '"' => s += "\\\"",
+                        '\\' => s += "\\\\",
+                        '\n' => s += "\\n",
+                        '\t' => s += "\\t",
+                        '\r' => s += "\\r",
+                        _ => s.push(c),
+                    }
+                }
+                s += "\"";
+                s
+            }
+            _ => panic!("{} is not a value", self.to_string()),
+        }
+    }
+
+    // inspect の逆
+    pub fn parse_literal(s: &str) -> Result<Expression, String> {
+        if s == "true" || s == "false" {
+            return Ok(Boolean(s == "true"));
+        }
+        if let Ok(value) = s.parse::<i32>() {
+            return Ok(Number(value));
+        }
+        let chars: Vec<char> = s.chars().collect();
+        if chars.len() < 2 || chars[0] != '"' || chars[chars.len() - 1] != '"' {
+            return Err(format!("cannot parse {}", s));
+        }
+        let mut value = String::new();
+        let mut i = 1;
+        while i < chars.len() - 1 {
+            match chars[i] {
+                '\\' if i + 1 < chars.len() - 1 => {
+                    i += 1;
+                    value.push(match chars[i] {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        '"' | '\\' => chars[i],
+                        _ => return Err(format!("cannot parse {}", s)),
+                    });
+                }
+                '\\' | '"' => return Err(format!("cannot parse {}", s)),
+                c => value.push(c),
+            }
+            i += 1;
+        }
+        Ok(Str(value))
+    }
+
     pub fn get_number(&self) -> Result<i32, String> {
         match self {
             Number(value) => Ok(value.clone()),
@@ -115,6 +272,13 @@ impl Expression {
         }
     }
 
+    pub fn get_str(&self) -> Result<String, String> {
+        match self {
+            Str(value) => Ok(value.clone()),
+            _ => Err("not a string".to_string()),
+        }
+    }
+
     pub fn new_add(left: i32, right: i32) -> Expression {
         Add {
             left: Box::new(Number(left)),
@@ -130,8 +294,7 @@ impl Expression {
     }
 
     pub fn new_env() -> Environment {
-        let env: Environment = HashMap::new();
-        return env;
+        Environment::new()
     }
 
     pub fn new_var<T: ToString>(name: T) -> Expression {
@@ -151,7 +314,11 @@ fn reduce_add(left: Expression, right: Expression, env: &Environment) -> Express
             right: Box::new(right.reduce(env)),
         }
     } else {
-        Number(left.get_number().unwrap() + right.get_number().unwrap())
+        Number(
+            left.get_number()
+                .unwrap()
+                .wrapping_add(right.get_number().unwrap()),
+        )
     }
 }
 
@@ -167,7 +334,11 @@ fn reduce_multiply(left: Expression, right: Expression, env: &Environment) -> Ex
             right: Box::new(right.reduce(env)),
         }
     } else {
-        Number(left.get_number().unwrap() * right.get_number().unwrap())
+        Number(
+            left.get_number()
+                .unwrap()
+                .wrapping_mul(right.get_number().unwrap()),
+        )
     }
 }
 
@@ -193,17 +364,137 @@ fn reduce_variable(name: String, env: &Environment) -> Expression {
         .clone()
 }
 
+fn reduce_concat(left: Expression, right: Expression, env: &Environment) -> Expression {
+    if left.reducible() {
+        Concat {
+            left: Box::new(left.reduce(env)),
+            right: Box::new(right),
+        }
+    } else if right.reducible() {
+        Concat {
+            left: Box::new(left),
+            right: Box::new(right.reduce(env)),
+        }
+    } else {
+        Str(left.get_str().unwrap() + &right.get_str().unwrap())
+    }
+}
+
+fn reduce_equal(left: Expression, right: Expression, env: &Environment) -> Expression {
+    if left.reducible() {
+        Equal {
+            left: Box::new(left.reduce(env)),
+            right: Box::new(right),
+        }
+    } else if right.reducible() {
+        Equal {
+            left: Box::new(left),
+            right: Box::new(right.reduce(env)),
+        }
+    } else {
+        Boolean(left == right)
+    }
+}
+
+fn reduce_length(string: Expression, env: &Environment) -> Expression {
+    if string.reducible() {
+        Length(Box::new(string.reduce(env)))
+    } else {
+        Number(string.get_str().unwrap().chars().count() as i32)
+    }
+}
+
+fn reduce_slice(
+    string: Expression,
+    start: Expression,
+    end: Expression,
+    env: &Environment,
+) -> Expression {
+    if string.reducible() {
+        Slice {
+            string: Box::new(string.reduce(env)),
+            start: Box::new(start),
+            end: Box::new(end),
+        }
+    } else if start.reducible() {
+        Slice {
+            string: Box::new(string),
+            start: Box::new(start.reduce(env)),
+            end: Box::new(end),
+        }
+    } else if end.reducible() {
+        Slice {
+            string: Box::new(string),
+            start: Box::new(start),
+            end: Box::new(end.reduce(env)),
+        }
+    } else {
+        Str(slice(
+            &string.get_str().unwrap(),
+            start.get_number().unwrap(),
+            end.get_number().unwrap(),
+        ))
+    }
+}
+
+fn reduce_inspect(value: Expression, env: &Environment) -> Expression {
+    if value.reducible() {
+        Inspect(Box::new(value.reduce(env)))
+    } else {
+        Str(value.inspect())
+    }
+}
+
+fn reduce_parse(string: Expression, env: &Environment) -> Expression {
+    if string.reducible() {
+        Parse(Box::new(string.reduce(env)))
+    } else {
+        Expression::parse_literal(&string.get_str().unwrap()).unwrap()
+    }
+}
+
+// 文字単位で [start, end) を切り出す。範囲外は切り詰める
+fn slice(string: &str, start: i32, end: i32) -> String {
+    let start = start.max(0) as usize;
+    let end = end.max(0) as usize;
+    if start >= end {
+        return String::new();
+    }
+    string.chars().skip(start).take(end - start).collect()
+}
+
+// Ruby の二重引用符の文字列では #{ が式展開になるので # もエスケープする
+fn ruby_string(value: &str) -> String {
+    let mut s = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' | '#' => {
+                s.push('\\');
+                s.push(c);
+            }
+            '\n' => s += "\\n",
+            '\t' => s += "\\t",
+            '\r' => s += "\\r",
+            _ => s.push(c),
+        }
+    }
+    s += "\"";
+    s
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Expression::{Add, Boolean, LessThan, Multiply, Number, Variable};
+    use super::Expression::{
+        Add, Boolean, Concat, Equal, Inspect, Length, LessThan, Multiply, Number, Parse, Slice,
+        Str, Variable,
+    };
     use super::{
         reduce_add, reduce_lessthan, reduce_multiply, reduce_variable, Environment, Expression,
     };
-    use std::collections::HashMap;
 
     #[test]
     fn test_reduce_add() {
-        let env: Environment = HashMap::new();
+        let env: Environment = Environment::new();
         let a = Expression::new_add(1, 2);
         let b = Number(11);
         assert_eq!(
@@ -218,7 +509,7 @@ mod tests {
 
     #[test]
     fn test_reduce_multiply() {
-        let env: Environment = HashMap::new();
+        let env: Environment = Environment::new();
         let a = Expression::new_multiply(2, 3);
         let b = Number(11);
         assert_eq!(
@@ -231,9 +522,25 @@ mod tests {
         );
     }
 
+    // 桁あふれはビルドによらず 2 の補数で折り返す
+    #[test]
+    fn test_overflow_wraps() {
+        let env: Environment = Environment::new();
+        assert_eq!(
+            reduce_add(Number(i32::MAX), Number(1), &env),
+            Number(i32::MIN)
+        );
+        assert_eq!(
+            reduce_multiply(Number(65536), Number(65536), &env),
+            Number(0)
+        );
+        let e = Expression::new_multiply(i32::MAX, 2);
+        assert_eq!(e.evaluate(&Expression::new_env()), Number(-2));
+    }
+
     #[test]
     fn test_reduce_lessthan() {
-        let env: Environment = HashMap::new();
+        let env: Environment = Environment::new();
         let a = Number(3);
         let b = Number(5);
         assert_eq!(reduce_lessthan(a.clone(), b.clone(), &env), Boolean(true));
@@ -242,7 +549,7 @@ mod tests {
 
     #[test]
     fn test_reduce_variable() {
-        let mut env: Environment = HashMap::new();
+        let mut env: Environment = Environment::new();
         env.insert("x".to_string(), Number(10));
         let expected = Number(10);
         assert_eq!(reduce_variable("x".to_string(), &env), expected);
@@ -252,7 +559,7 @@ mod tests {
     #[should_panic]
     fn test_reduce_number() {
         let n = Number(10);
-        let env: Environment = HashMap::new();
+        let env: Environment = Environment::new();
         n.reduce(&env);
     }
 
@@ -260,13 +567,13 @@ mod tests {
     #[should_panic]
     fn test_reduce_boolean() {
         let n = Boolean(false);
-        let env: Environment = HashMap::new();
+        let env: Environment = Environment::new();
         n.reduce(&env);
     }
 
     #[test]
     fn test_reduce() {
-        let mut env: Environment = HashMap::new();
+        let mut env: Environment = Environment::new();
         let a = Add {
             left: Box::new(Number(10)),
             right: Box::new(Number(20)),
@@ -355,4 +662,60 @@ mod tests {
         assert_eq!(m.evaluate(&env), Number(27));
         assert_eq!(lt.evaluate(&env), Boolean(true));
     }
+
+    #[test]
+    fn test_strings() {
+        let mut env = Expression::new_env();
+        env.insert("s".to_string(), Str("héllo".to_string()));
+        let s = || Box::new(Expression::new_var("s"));
+        let e = Concat {
+            left: Box::new(Slice {
+                string: s(),
+                start: Box::new(Number(1)),
+                end: Box::new(Length(s())),
+            }),
+            right: Box::new(Inspect(s())),
+        };
+        assert_eq!(e.to_string(), "slice(s, 1, length(s)) ++ inspect(s)");
+        assert_eq!(e.clone().evaluate(&env), Str("éllo\"héllo\"".to_string()));
+        let mut reduced = e;
+        while reduced.reducible() {
+            reduced = reduced.reduce(&env);
+        }
+        assert_eq!(reduced, Str("éllo\"héllo\"".to_string()));
+
+        let eq = Equal {
+            left: Box::new(Parse(Box::new(Str("-12".to_string())))),
+            right: Box::new(Number(-12)),
+        };
+        assert_eq!(eq.evaluate(&env), Boolean(true));
+        let eq = Equal {
+            left: Box::new(Number(1)),
+            right: Box::new(Boolean(true)),
+        };
+        assert_eq!(eq.evaluate(&env), Boolean(false));
+        let clamped = Slice {
+            string: s(),
+            start: Box::new(Number(-3)),
+            end: Box::new(Number(2)),
+        };
+        assert_eq!(clamped.evaluate(&env), Str("hé".to_string()));
+    }
+
+    #[test]
+    fn test_inspect_and_parse() {
+        for value in [
+            Number(-7),
+            Boolean(false),
+            Str("a \"b\"\\\n\t".to_string()),
+            Str(String::new()),
+        ] {
+            assert_eq!(Expression::parse_literal(&value.inspect()), Ok(value));
+        }
+        assert_eq!(Expression::parse_literal("+5"), Ok(Number(5)));
+        assert!(Expression::parse_literal("2147483648").is_err());
+        assert!(Expression::parse_literal("\"a\"b\"").is_err());
+        assert!(Expression::parse_literal("\"\\q\"").is_err());
+        assert!(Expression::parse_literal("x").is_err());
+    }
 }
@@ -0,0 +1,284 @@
+use crate::expression::Expression::{Add, Boolean, LessThan, Multiply, Number, Str, Variable};
+use crate::expression::{Environment, Expression};
+use crate::statement::Statement;
+use crate::statement::Statement::{Assign, DoNothing, If, Sequence, While};
+
+// Statement を SIMPLE の文字列で扱える命令列にする。命令は `<op><引数>;` の形で
+//   #値;  値を積む         $名前;  変数の値を積む      +; *; <;  二つ取り出して計算する
+//   =名前;  取り出して代入  ?差分;  取り出した値が false なら pc を進める  j差分;  pc を進める
+// 変数名は ; や = を含まないよう16進にする。差分は命令の直後からの文字数で、
+// 長さを先に決めるため符号付き8桁に揃える
+pub fn encode(statement: &Statement) -> Result<String, String> {
+    match statement {
+        DoNothing => Ok(String::new()),
+        Assign { name, expression } => {
+            Ok(encode_expression(expression)? + &format!("={};", hex(name)))
+        }
+        If {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            let alternative = encode(alternative)?;
+            let consequence = encode(consequence)? + &jump("j", alternative.len() as i32);
+            Ok(encode_expression(condition)?
+                + &jump("?", consequence.len() as i32)
+                + &consequence
+                + &alternative)
+        }
+        While { condition, body } => {
+            let condition = encode_expression(condition)?;
+            let body = encode(body)?;
+            let skip = jump("?", (body.len() + JUMP_LENGTH) as i32);
+            let total = condition.len() + skip.len() + body.len() + JUMP_LENGTH;
+            Ok(condition + &skip + &body + &jump("j", -(total as i32)))
+        }
+        Sequence { first, second } => Ok(encode(first)? + &encode(second)?),
+    }
+}
+
+const JUMP_LENGTH: usize = 11;
+
+fn jump(op: &str, offset: i32) -> String {
+    format!("{}{:+09};", op, offset)
+}
+
+fn encode_expression(expression: &Expression) -> Result<String, String> {
+    match expression {
+        Number(_) | Boolean(_) => Ok(format!("#{};", expression.inspect())),
+        Variable(name) => Ok(format!("${};", hex(name))),
+        Add { left, right } => Ok(encode_expression(left)? + &encode_expression(right)? + "+;"),
+        Multiply { left, right } => {
+            Ok(encode_expression(left)? + &encode_expression(right)? + "*;")
+        }
+        LessThan { left, right } => {
+            Ok(encode_expression(left)? + &encode_expression(right)? + "<;")
+        }
+        _ => Err(format!("{} cannot be encoded", expression.to_string())),
+    }
+}
+
+fn hex(name: &str) -> String {
+    name.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Result<String, String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or(format!("invalid name {}", hex))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    String::from_utf8(bytes).map_err(|_| format!("invalid name {}", hex))
+}
+
+// program に encode した命令列を入れて実行すると、environment に `名前=値;` を並べた文字列が残る。
+// 未定義の変数を参照したときは error にその名前を入れて止まる
+const INTERPRETER: &str = r##"
+(sequence
+  (assign pc 0)
+  (assign size (length program))
+  (assign stack (string ""))
+  (assign environment (string ""))
+  (assign error (string ""))
+  (assign left (string ""))
+  (assign right (string ""))
+  (while (< pc size)
+    (sequence
+      ; 次の ; までが一つの命令
+      (assign end pc)
+      (while (== (== (slice program end (+ end 1)) (string ";")) false)
+        (assign end (+ end 1)))
+      (assign op (slice program pc (+ pc 1)))
+      (assign arg (slice program (+ pc 1) end))
+      (assign pc (+ end 1))
+
+      ; 命令ごとにスタックから取り出す個数。最後に取り出したものが left に入る
+      (assign pops 0)
+      (if (== op (string "=")) (assign pops 1) (do-nothing))
+      (if (== op (string "?")) (assign pops 1) (do-nothing))
+      (if (== op (string "+")) (assign pops 2) (do-nothing))
+      (if (== op (string "*")) (assign pops 2) (do-nothing))
+      (if (== op (string "<")) (assign pops 2) (do-nothing))
+      (while (< 0 pops)
+        (sequence
+          (assign right left)
+          (assign top 0)
+          (while (== (== (slice stack top (+ top 1)) (string ";")) false)
+            (assign top (+ top 1)))
+          (assign left (slice stack 0 top))
+          (assign stack (slice stack (+ top 1) (length stack)))
+          (assign pops (+ pops -1))))
+
+      ; 変数の参照と代入では ";名前=" を探し、値の終わりの ; の位置を stop に入れる
+      (assign key (++ (++ (string ";") arg) (string "=")))
+      (assign haystack (++ (string ";") environment))
+      (assign found -1)
+      (if (== op (string "$"))
+        (assign search true)
+        (assign search (== op (string "="))))
+      (if search
+        (sequence
+          (assign i 0)
+          (while (< i (length haystack))
+            (if (== (slice haystack i (+ i (length key))) key)
+              (sequence (assign found i) (assign i (length haystack)))
+              (assign i (+ i 1))))
+          (if (< found 0)
+            (do-nothing)
+            (sequence
+              (assign stop (+ found (length key)))
+              (while (== (== (slice haystack stop (+ stop 1)) (string ";")) false)
+                (assign stop (+ stop 1))))))
+        (do-nothing))
+
+      (if (== op (string "#"))
+        (assign stack (++ (++ arg (string ";")) stack))
+        (do-nothing))
+      (if (== op (string "$"))
+        (if (< found 0)
+          (sequence (assign error arg) (assign pc size))
+          (assign stack
+            (++ (++ (slice haystack (+ found (length key)) stop) (string ";")) stack)))
+        (do-nothing))
+      (if (== op (string "="))
+        (sequence
+          (if (< found 0)
+            (do-nothing)
+            (assign environment
+              (++ (slice haystack 1 (+ found 1))
+                  (slice haystack (+ stop 1) (length haystack)))))
+          (assign environment
+            (++ (++ (++ (++ arg (string "=")) left) (string ";")) environment)))
+        (do-nothing))
+      (if (== op (string "+"))
+        (assign stack (++ (++ (inspect (+ (parse left) (parse right))) (string ";")) stack))
+        (do-nothing))
+      (if (== op (string "*"))
+        (assign stack (++ (++ (inspect (* (parse left) (parse right))) (string ";")) stack))
+        (do-nothing))
+      (if (== op (string "<"))
+        (assign stack (++ (++ (inspect (< (parse left) (parse right))) (string ";")) stack))
+        (do-nothing))
+      (if (== op (string "?"))
+        (if (parse left) (do-nothing) (assign pc (+ pc (parse arg))))
+        (do-nothing))
+      (if (== op (string "j"))
+        (assign pc (+ pc (parse arg)))
+        (do-nothing)))))
+"##;
+
+// SIMPLE で書いた SIMPLE のインタプリタ
+pub fn interpreter() -> Statement {
+    Statement::from_sexp(INTERPRETER).unwrap()
+}
+
+// インタプリタを実行した後の環境から、解釈したプログラムの環境を取り出す
+pub fn decode_environment(env: &Environment) -> Result<Environment, String> {
+    let error = env
+        .get("error")
+        .ok_or("interpreter has not run")?
+        .get_str()?;
+    if !error.is_empty() {
+        return Err(format!("variable {} does not exist", unhex(&error)?));
+    }
+    let mut result = Environment::new();
+    let encoded = env
+        .get("environment")
+        .ok_or("interpreter has not run")?
+        .get_str()?;
+    for entry in encoded.split_terminator(';') {
+        let mut parts = entry.splitn(2, '=');
+        let name = unhex(parts.next().unwrap())?;
+        let value = parts.next().ok_or(format!("invalid entry {}", entry))?;
+        result.insert(name, Expression::parse_literal(value)?);
+    }
+    Ok(result)
+}
+
+// 空の環境から statement をインタプリタ上で実行する
+pub fn run(statement: &Statement) -> Result<Environment, String> {
+    let mut env = Environment::new();
+    env.insert("program".to_string(), Str(encode(statement)?));
+    decode_environment(&interpreter().evaluate(&mut env))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, interpreter, run, unhex};
+    use crate::expression::Expression;
+    use crate::expression::Expression::Number;
+    use crate::statement::Statement;
+
+    fn check(source: &str) {
+        let statement = Statement::from_sexp(source).unwrap();
+        let expected = statement.clone().evaluate(&mut Expression::new_env());
+        assert_eq!(run(&statement), Ok(expected));
+    }
+
+    #[test]
+    fn test_encode() {
+        let statement = Statement::from_sexp("(if (< x 1) (assign y true) (do-nothing))").unwrap();
+        assert_eq!(
+            encode(&statement),
+            Ok("$78;#1;<;?+00000021;#true;=79;j+00000000;".to_string())
+        );
+        let statement = Statement::from_sexp("(while (< x 1) (assign x 2))").unwrap();
+        assert_eq!(
+            encode(&statement),
+            Ok("$78;#1;<;?+00000018;#2;=78;j-00000038;".to_string())
+        );
+        assert!(encode(&Statement::new_assign(
+            "s",
+            Expression::Str("a".to_string())
+        ))
+        .is_err());
+        assert_eq!(unhex("612062"), Ok("a b".to_string()));
+    }
+
+    #[test]
+    fn test_interpreter_is_simple() {
+        // インタプリタ自身も普通の SIMPLE のプログラムとして表示できる
+        assert!(interpreter().to_string().starts_with("pc = 0; "));
+    }
+
+    #[test]
+    fn test_run() {
+        check("(sequence (assign x (+ 1 2)) (assign y (* x 3)) (assign z (< x y)))");
+        check(
+            "(sequence (assign n 5) (assign result 1)
+               (while (< 0 n)
+                 (sequence (assign result (* result n)) (assign n (+ n -1))))
+               (if (< 100 result) (assign big true) (assign big false)))",
+        );
+        check(
+            "(sequence (assign i 0) (assign total 0)
+               (while (< i 3)
+                 (sequence
+                   (assign j 0)
+                   (while (< j i)
+                     (sequence (assign total (+ total j)) (assign j (+ j 1))))
+                   (assign i (+ i 1)))))",
+        );
+        check(r#"(sequence (assign "odd name=;" -2147483648) (do-nothing))"#);
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let statement = Statement::from_sexp("(sequence (assign x 1) (assign y (+ x z)))").unwrap();
+        assert_eq!(
+            run(&statement),
+            Err("variable z does not exist".to_string())
+        );
+        assert_eq!(
+            run(&Statement::from_sexp("(assign x y)").unwrap()).map(|env| env.get("x").cloned()),
+            Err("variable y does not exist".to_string())
+        );
+        assert_eq!(
+            run(&Statement::new_assign("x", Number(1))).map(|env| env.get("x").cloned()),
+            Ok(Some(Number(1)))
+        );
+    }
+}
@@ -0,0 +1,655 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::expression::Expression::{
+    Add, Boolean, Concat, Equal, Inspect, Length, LessThan, Multiply, Number, Parse, Slice, Str,
+    Variable,
+};
+use crate::expression::{Environment, Expression};
+use crate::statement::Statement::{Assign, DoNothing, If, Sequence, While};
+use crate::statement::{Machine, Statement};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Boolean(bool),
+    Number(i64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(input: &str) -> Result<Json, String> {
+        let mut reader = Reader::new(input);
+        let json = reader.read()?;
+        reader.skip_whitespace();
+        if !reader.at_end() {
+            return Err(format!("unexpected input at {}", reader.index));
+        }
+        Ok(json)
+    }
+
+    fn get(&self, key: &str) -> Result<&Json, String> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value)
+                .ok_or(format!("missing field \"{}\"", key)),
+            _ => Err(format!("expected an object but got {}", self)),
+        }
+    }
+
+    fn get_str(&self, key: &str) -> Result<&str, String> {
+        match self.get(key)? {
+            Json::Str(value) => Ok(value),
+            value => Err(format!("expected a string but got {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Boolean(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::Str(value) => write!(f, "\"{}\"", escape(value)),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "\"{}\":{}", escape(name), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+struct Reader {
+    input: Vec<char>,
+    index: usize,
+}
+
+impl Reader {
+    fn new(input: &str) -> Self {
+        Reader {
+            input: input.chars().collect(),
+            index: 0,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.index >= self.input.len()
+    }
+
+    fn current(&self) -> char {
+        self.input[self.index]
+    }
+
+    fn skip_whitespace(&mut self) {
+        while !self.at_end() && self.current().is_whitespace() {
+            self.index += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.at_end() {
+            return Err(format!("expected '{}' but input ends", c));
+        }
+        if self.current() != c {
+            return Err(format!(
+                "expected '{}' but got '{}' at {}",
+                c,
+                self.current(),
+                self.index
+            ));
+        }
+        self.index += 1;
+        Ok(())
+    }
+
+    fn is(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        !self.at_end() && self.current() == c
+    }
+
+    fn read(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        if self.at_end() {
+            return Err("unexpected end of input".to_string());
+        }
+        match self.current() {
+            '{' => self.read_object(),
+            '[' => self.read_array(),
+            '"' => Ok(Json::Str(self.read_string()?)),
+            '-' | '0'..='9' => self.read_number(),
+            _ => self.read_keyword(),
+        }
+    }
+
+    fn read_object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        if self.is('}') {
+            self.index += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if !self.is('"') {
+                return Err(format!("expected a field name at {}", self.index));
+            }
+            let name = self.read_string()?;
+            self.expect(':')?;
+            fields.push((name, self.read()?));
+            if self.is(',') {
+                self.index += 1;
+            } else {
+                self.expect('}')?;
+                return Ok(Json::Object(fields));
+            }
+        }
+    }
+
+    fn read_array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = vec![];
+        if self.is(']') {
+            self.index += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.read()?);
+            if self.is(',') {
+                self.index += 1;
+            } else {
+                self.expect(']')?;
+                return Ok(Json::Array(items));
+            }
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            if self.at_end() {
+                return Err("unterminated string".to_string());
+            }
+            let c = self.current();
+            self.index += 1;
+            match c {
+                '"' => return Ok(value),
+                '\\' => {
+                    if self.at_end() {
+                        return Err("unterminated string".to_string());
+                    }
+                    let escaped = self.current();
+                    self.index += 1;
+                    match escaped {
+                        '"' | '\\' | '/' => value.push(escaped),
+                        'b' => value.push('\u{8}'),
+                        'f' => value.push('\u{c}'),
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        'u' => value.push(self.read_unicode_escape()?),
+                        _ => return Err(format!("unknown escape \\{}", escaped)),
+                    }
+                }
+                _ => value.push(c),
+            }
+        }
+    }
+
+    fn read_hex4(&mut self) -> Result<u32, String> {
+        if self.index + 4 > self.input.len() {
+            return Err("unterminated \\u escape".to_string());
+        }
+        let digits: String = self.input[self.index..self.index + 4].iter().collect();
+        self.index += 4;
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("invalid \\u escape {}", digits))
+    }
+
+    // \uXXXX はサロゲートペアで書かれていることがある
+    fn read_unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.read_hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !(self.input.get(self.index) == Some(&'\\')
+                && self.input.get(self.index + 1) == Some(&'u'))
+            {
+                return Err("unpaired surrogate in \\u escape".to_string());
+            }
+            self.index += 2;
+            let low = self.read_hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err("unpaired surrogate in \\u escape".to_string());
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or(format!("invalid \\u escape {:x}", code))
+    }
+
+    fn read_number(&mut self) -> Result<Json, String> {
+        let start = self.index;
+        if self.current() == '-' {
+            self.index += 1;
+        }
+        while !self.at_end() && self.current().is_ascii_digit() {
+            self.index += 1;
+        }
+        if !self.at_end() && ['.', 'e', 'E'].contains(&self.current()) {
+            return Err(format!("only integers are supported at {}", start));
+        }
+        let digits: String = self.input[start..self.index].iter().collect();
+        digits
+            .parse::<i64>()
+            .map(Json::Number)
+            .map_err(|_| format!("invalid number {}", digits))
+    }
+
+    fn read_keyword(&mut self) -> Result<Json, String> {
+        for (keyword, value) in [
+            ("null", Json::Null),
+            ("true", Json::Boolean(true)),
+            ("false", Json::Boolean(false)),
+        ]
+        .iter()
+        {
+            let end = self.index + keyword.len();
+            if end <= self.input.len()
+                && self.input[self.index..end]
+                    .iter()
+                    .copied()
+                    .eq(keyword.chars())
+            {
+                self.index = end;
+                return Ok(value.clone());
+            }
+        }
+        Err(format!("unexpected '{}' at {}", self.current(), self.index))
+    }
+}
+
+fn object(kind: &str, mut fields: Vec<(&str, Json)>) -> Json {
+    fields.insert(0, ("type", Json::Str(kind.to_string())));
+    Json::Object(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    )
+}
+
+pub fn expression_to_json(expression: &Expression) -> Json {
+    let binary = |kind: &str, left: &Expression, right: &Expression| {
+        object(
+            kind,
+            vec![
+                ("left", expression_to_json(left)),
+                ("right", expression_to_json(right)),
+            ],
+        )
+    };
+    match expression {
+        Number(value) => object("Number", vec![("value", Json::Number(*value as i64))]),
+        Boolean(value) => object("Boolean", vec![("value", Json::Boolean(*value))]),
+        Variable(name) => object("Variable", vec![("name", Json::Str(name.clone()))]),
+        Add { left, right } => binary("Add", left, right),
+        Multiply { left, right } => binary("Multiply", left, right),
+        LessThan { left, right } => binary("LessThan", left, right),
+        Str(value) => object("Str", vec![("value", Json::Str(value.clone()))]),
+        Concat { left, right } => binary("Concat", left, right),
+        Equal { left, right } => binary("Equal", left, right),
+        Length(string) => object("Length", vec![("string", expression_to_json(string))]),
+        Slice { string, start, end } => object(
+            "Slice",
+            vec![
+                ("string", expression_to_json(string)),
+                ("start", expression_to_json(start)),
+                ("end", expression_to_json(end)),
+            ],
+        ),
+        Inspect(value) => object("Inspect", vec![("value", expression_to_json(value))]),
+        Parse(string) => object("Parse", vec![("string", expression_to_json(string))]),
+    }
+}
+
+pub fn expression_from_json(json: &Json) -> Result<Expression, String> {
+    let operand = |key: &str| -> Result<Box<Expression>, String> {
+        Ok(Box::new(expression_from_json(json.get(key)?)?))
+    };
+    match json.get_str("type")? {
+        "Number" => match json.get("value")? {
+            Json::Number(value) => i32::try_from(*value)
+                .map(Number)
+                .map_err(|_| format!("number {} is out of range", value)),
+            value => Err(format!("expected a number but got {}", value)),
+        },
+        "Boolean" => match json.get("value")? {
+            Json::Boolean(value) => Ok(Boolean(*value)),
+            value => Err(format!("expected a boolean but got {}", value)),
+        },
+        "Variable" => Ok(Variable(json.get_str("name")?.to_string())),
+        "Add" => Ok(Add {
+            left: operand("left")?,
+            right: operand("right")?,
+        }),
+        "Multiply" => Ok(Multiply {
+            left: operand("left")?,
+            right: operand("right")?,
+        }),
+        "LessThan" => Ok(LessThan {
+            left: operand("left")?,
+            right: operand("right")?,
+        }),
+        "Str" => Ok(Str(json.get_str("value")?.to_string())),
+        "Concat" => Ok(Concat {
+            left: operand("left")?,
+            right: operand("right")?,
+        }),
+        "Equal" => Ok(Equal {
+            left: operand("left")?,
+            right: operand("right")?,
+        }),
+        "Length" => Ok(Length(operand("string")?)),
+        "Slice" => Ok(Slice {
+            string: operand("string")?,
+            start: operand("start")?,
+            end: operand("end")?,
+        }),
+        "Inspect" => Ok(Inspect(operand("value")?)),
+        "Parse" => Ok(Parse(operand("string")?)),
+        kind => Err(format!("unknown expression type {}", kind)),
+    }
+}
+
+pub fn statement_to_json(statement: &Statement) -> Json {
+    match statement {
+        DoNothing => object("DoNothing", vec![]),
+        Assign { name, expression } => object(
+            "Assign",
+            vec![
+                ("name", Json::Str(name.clone())),
+                ("expression", expression_to_json(expression)),
+            ],
+        ),
+        If {
+            condition,
+            consequence,
+            alternative,
+        } => object(
+            "If",
+            vec![
+                ("condition", expression_to_json(condition)),
+                ("consequence", statement_to_json(consequence)),
+                ("alternative", statement_to_json(alternative)),
+            ],
+        ),
+        While { condition, body } => object(
+            "While",
+            vec![
+                ("condition", expression_to_json(condition)),
+                ("body", statement_to_json(body)),
+            ],
+        ),
+        Sequence { first, second } => object(
+            "Sequence",
+            vec![
+                ("first", statement_to_json(first)),
+                ("second", statement_to_json(second)),
+            ],
+        ),
+    }
+}
+
+pub fn statement_from_json(json: &Json) -> Result<Statement, String> {
+    let statement = |key: &str| -> Result<Box<Statement>, String> {
+        Ok(Box::new(statement_from_json(json.get(key)?)?))
+    };
+    match json.get_str("type")? {
+        "DoNothing" => Ok(DoNothing),
+        "Assign" => Ok(Assign {
+            name: json.get_str("name")?.to_string(),
+            expression: expression_from_json(json.get("expression")?)?,
+        }),
+        "If" => Ok(If {
+            condition: expression_from_json(json.get("condition")?)?,
+            consequence: statement("consequence")?,
+            alternative: statement("alternative")?,
+        }),
+        "While" => Ok(While {
+            condition: expression_from_json(json.get("condition")?)?,
+            body: statement("body")?,
+        }),
+        "Sequence" => Ok(Sequence {
+            first: statement("first")?,
+            second: statement("second")?,
+        }),
+        kind => Err(format!("unknown statement type {}", kind)),
+    }
+}
+
+pub fn environment_to_json(env: &Environment) -> Json {
+    Json::Object(
+        env.iter()
+            .map(|(name, value)| (name.clone(), expression_to_json(value)))
+            .collect(),
+    )
+}
+
+pub fn environment_from_json(json: &Json) -> Result<Environment, String> {
+    let mut env = Expression::new_env();
+    match json {
+        Json::Object(fields) => {
+            for (name, value) in fields.iter() {
+                env.insert(name.clone(), expression_from_json(value)?);
+            }
+            Ok(env)
+        }
+        _ => Err(format!("expected an environment but got {}", json)),
+    }
+}
+
+impl Expression {
+    pub fn to_json(&self) -> String {
+        expression_to_json(self).to_string()
+    }
+
+    pub fn from_json(input: &str) -> Result<Expression, String> {
+        expression_from_json(&Json::parse(input)?)
+    }
+}
+
+impl Statement {
+    pub fn to_json(&self) -> String {
+        statement_to_json(self).to_string()
+    }
+
+    pub fn from_json(input: &str) -> Result<Statement, String> {
+        statement_from_json(&Json::parse(input)?)
+    }
+}
+
+impl Machine {
+    pub fn to_json(&self) -> String {
+        Json::Object(vec![
+            ("statement".to_string(), statement_to_json(&self.statement)),
+            (
+                "environment".to_string(),
+                environment_to_json(&self.environment),
+            ),
+        ])
+        .to_string()
+    }
+
+    pub fn from_json(input: &str) -> Result<Machine, String> {
+        let json = Json::parse(input)?;
+        Ok(Machine {
+            statement: statement_from_json(json.get("statement")?)?,
+            environment: environment_from_json(json.get("environment")?)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{environment_from_json, environment_to_json, Json};
+    use crate::expression::Expression;
+    use crate::expression::Expression::{
+        Boolean, Concat, Inspect, Length, LessThan, Multiply, Number, Parse, Slice, Str,
+    };
+    use crate::statement::Statement::{If, Sequence, While};
+    use crate::statement::{Machine, Statement};
+
+    fn program() -> Statement {
+        Sequence {
+            first: Box::new(Statement::new_assign("x", Number(1))),
+            second: Box::new(While {
+                condition: LessThan {
+                    left: Box::new(Expression::new_var("x")),
+                    right: Box::new(Number(100)),
+                },
+                body: Box::new(If {
+                    condition: Boolean(false),
+                    consequence: Box::new(Statement::DoNothing),
+                    alternative: Box::new(Statement::new_assign(
+                        "x",
+                        Multiply {
+                            left: Box::new(Expression::new_var("x")),
+                            right: Box::new(Number(2)),
+                        },
+                    )),
+                }),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Json::parse(r#" {"a": [1, -2, true, null], "b\n": "é😀"} "#),
+            Ok(Json::Object(vec![
+                (
+                    "a".to_string(),
+                    Json::Array(vec![
+                        Json::Number(1),
+                        Json::Number(-2),
+                        Json::Boolean(true),
+                        Json::Null
+                    ])
+                ),
+                ("b\n".to_string(), Json::Str("é😀".to_string())),
+            ]))
+        );
+        assert_eq!(
+            Json::parse(r#""\ud83d\ude00\u00e9""#),
+            Ok(Json::Str("😀é".to_string()))
+        );
+        assert!(Json::parse(r#""\ud83d""#).is_err());
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("1.5").is_err());
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("nul").is_err());
+    }
+
+    #[test]
+    fn test_expression() {
+        let e = LessThan {
+            left: Box::new(Number(3)),
+            right: Box::new(Expression::new_var("x\"y")),
+        };
+        assert_eq!(
+            e.to_json(),
+            r#"{"type":"LessThan","left":{"type":"Number","value":3},"right":{"type":"Variable","name":"x\"y"}}"#
+        );
+        assert_eq!(Expression::from_json(&e.to_json()), Ok(e));
+        assert!(Expression::from_json(r#"{"type":"Number","value":true}"#).is_err());
+        assert!(Expression::from_json(r#"{"type":"Number","value":4294967296}"#).is_err());
+        assert!(
+            Expression::from_json(r#"{"type":"Add","left":{"type":"Number","value":1}}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_string_primitives() {
+        let e = Slice {
+            string: Box::new(Concat {
+                left: Box::new(Str("a\"b".to_string())),
+                right: Box::new(Inspect(Box::new(Number(1)))),
+            }),
+            start: Box::new(Number(0)),
+            end: Box::new(Length(Box::new(Parse(Box::new(Expression::new_var("x")))))),
+        };
+        assert_eq!(Expression::from_json(&e.to_json()), Ok(e));
+        assert_eq!(
+            Str("é".to_string()).to_json(),
+            r#"{"type":"Str","value":"é"}"#
+        );
+    }
+
+    #[test]
+    fn test_statement() {
+        let s = program();
+        assert_eq!(Statement::from_json(&s.to_json()), Ok(s));
+        assert!(Statement::from_json(r#"{"type":"Repeat"}"#).is_err());
+    }
+
+    #[test]
+    fn test_environment() {
+        let mut env = Expression::new_env();
+        env.insert("y".to_string(), Boolean(false));
+        env.insert("x".to_string(), Number(-7));
+        let json = environment_to_json(&env);
+        assert_eq!(
+            json.to_string(),
+            r#"{"x":{"type":"Number","value":-7},"y":{"type":"Boolean","value":false}}"#
+        );
+        assert_eq!(environment_from_json(&json), Ok(env));
+    }
+
+    #[test]
+    fn test_machine() {
+        let mut m = Machine {
+            statement: program(),
+            environment: Expression::new_env(),
+        };
+        m.run();
+        assert_eq!(Machine::from_json(&m.to_json()), Ok(m.clone()));
+        assert_eq!(Machine::from_sexp(&m.to_sexp()), Ok(m));
+    }
+}
@@ -0,0 +1,616 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::expression::Expression::{Add, Boolean, LessThan, Multiply, Number, Variable};
+use crate::expression::{Environment, Expression};
+use crate::statement::Statement;
+use crate::statement::Statement::{Assign, DoNothing, If, Sequence, While};
+
+// 型なしラムダ計算の項。表示と読み込みは本の Ruby の proc と同じ
+// `-> x { x[y] }` の形を使う
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    Variable(String),
+    Function { parameter: String, body: Box<Term> },
+    Call { left: Box<Term>, right: Box<Term> },
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Term::Variable(name) => write!(f, "{}", name),
+            Term::Function { parameter, body } => write!(f, "-> {} {{ {} }}", parameter, body),
+            Term::Call { left, right } => write!(f, "{}[{}]", left, right),
+        }
+    }
+}
+
+fn var(name: &str) -> Term {
+    Term::Variable(name.to_string())
+}
+
+fn fun(parameter: &str, body: Term) -> Term {
+    Term::Function {
+        parameter: parameter.to_string(),
+        body: Box::new(body),
+    }
+}
+
+fn call(left: Term, right: Term) -> Term {
+    Term::Call {
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+impl Term {
+    pub fn parse(input: &str) -> Result<Term, String> {
+        let mut reader = Reader {
+            input: input.chars().collect(),
+            index: 0,
+        };
+        let term = reader.read_term()?;
+        reader.skip_whitespace();
+        if reader.index < reader.input.len() {
+            return Err(format!("unexpected input at {}", reader.index));
+        }
+        Ok(term)
+    }
+
+    pub fn free_variables(&self) -> HashSet<String> {
+        let mut names = HashSet::new();
+        self.collect_free_variables(&mut vec![], &mut names);
+        names
+    }
+
+    fn collect_free_variables<'a>(&'a self, bound: &mut Vec<&'a str>, names: &mut HashSet<String>) {
+        match self {
+            Term::Variable(name) => {
+                if !bound.contains(&name.as_str()) {
+                    names.insert(name.clone());
+                }
+            }
+            Term::Function { parameter, body } => {
+                bound.push(parameter);
+                body.collect_free_variables(bound, names);
+                bound.pop();
+            }
+            Term::Call { left, right } => {
+                left.collect_free_variables(bound, names);
+                right.collect_free_variables(bound, names);
+            }
+        }
+    }
+
+    // 自由変数 name を replacement で置き換える。replacement の自由変数が
+    // 束縛されてしまう場合は仮引数の名前を付け替える
+    pub fn replace(&self, name: &str, replacement: &Term) -> Term {
+        self.substitute(name, replacement, &replacement.free_variables())
+    }
+
+    fn substitute(&self, name: &str, replacement: &Term, free: &HashSet<String>) -> Term {
+        match self {
+            Term::Variable(n) if n == name => replacement.clone(),
+            Term::Variable(_) => self.clone(),
+            Term::Call { left, right } => call(
+                left.substitute(name, replacement, free),
+                right.substitute(name, replacement, free),
+            ),
+            Term::Function { parameter, .. } if parameter == name => self.clone(),
+            Term::Function { parameter, body } => {
+                let body_free = body.free_variables();
+                if !body_free.contains(name) {
+                    return self.clone();
+                }
+                if free.contains(parameter) {
+                    let mut fresh = parameter.clone();
+                    while fresh == name || free.contains(&fresh) || body_free.contains(&fresh) {
+                        fresh.push('\'');
+                    }
+                    let body = body.replace(parameter, &var(&fresh));
+                    fun(&fresh, body.substitute(name, replacement, free))
+                } else {
+                    fun(parameter, body.substitute(name, replacement, free))
+                }
+            }
+        }
+    }
+
+    pub fn reducible(&self) -> bool {
+        match self {
+            Term::Variable(_) => false,
+            Term::Function { body, .. } => body.reducible(),
+            Term::Call { left, right } => match **left {
+                Term::Function { .. } => true,
+                _ => left.reducible() || right.reducible(),
+            },
+        }
+    }
+
+    // 正規順序で、一番左外側の簡約基を一つだけ簡約する
+    pub fn reduce(self) -> Term {
+        match self {
+            Term::Variable(_) => unreachable!(),
+            Term::Function { parameter, body } => fun(&parameter, body.reduce()),
+            Term::Call { left, right } => match *left {
+                Term::Function { parameter, body } => body.replace(&parameter, &right),
+                left if left.reducible() => call(left.reduce(), *right),
+                left => call(left, right.reduce()),
+            },
+        }
+    }
+
+    // reduce を繰り返したときと同じ正規形を、毎回根から簡約基を探さずに求める。
+    // 正規形を持たない項では止まらない
+    pub fn evaluate(self) -> Term {
+        match self.head_normal_form() {
+            Term::Function { parameter, body } => fun(&parameter, body.evaluate()),
+            Term::Call { left, right } => call(left.evaluate(), right.evaluate()),
+            term => term,
+        }
+    }
+
+    fn head_normal_form(self) -> Term {
+        let mut term = self;
+        loop {
+            match term {
+                Term::Call { left, right } => match left.head_normal_form() {
+                    Term::Function { parameter, body } => {
+                        term = body.replace(&parameter, &right);
+                    }
+                    left => return call(left, *right),
+                },
+                _ => return term,
+            }
+        }
+    }
+}
+
+struct Reader {
+    input: Vec<char>,
+    index: usize,
+}
+
+impl Reader {
+    fn skip_whitespace(&mut self) {
+        while self.index < self.input.len() && self.input[self.index].is_whitespace() {
+            self.index += 1;
+        }
+    }
+
+    fn is(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let chars: Vec<char> = token.chars().collect();
+        self.input[self.index..].starts_with(&chars)
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.is(token) {
+            self.index += token.chars().count();
+            Ok(())
+        } else {
+            Err(format!("expected '{}' at {}", token, self.index))
+        }
+    }
+
+    fn read_name(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let start = self.index;
+        while self.index < self.input.len()
+            && (self.input[self.index].is_alphanumeric()
+                || self.input[self.index] == '_'
+                || self.input[self.index] == '\'')
+        {
+            self.index += 1;
+        }
+        if start == self.index {
+            return Err(format!("expected a name at {}", self.index));
+        }
+        Ok(self.input[start..self.index].iter().collect())
+    }
+
+    fn read_term(&mut self) -> Result<Term, String> {
+        let mut term = if self.is("->") {
+            self.expect("->")?;
+            let parameter = self.read_name()?;
+            self.expect("{")?;
+            let body = self.read_term()?;
+            self.expect("}")?;
+            fun(&parameter, body)
+        } else {
+            Term::Variable(self.read_name()?)
+        };
+        while self.is("[") {
+            self.expect("[")?;
+            let argument = self.read_term()?;
+            self.expect("]")?;
+            term = call(term, argument);
+        }
+        Ok(term)
+    }
+}
+
+// 本の7章と同じく、チャーチ数とチャーチ真偽値で数と真偽値を表す
+fn definition(source: &str, uses: &[(&str, Term)]) -> Term {
+    let mut term = Term::parse(source).unwrap();
+    for (name, value) in uses {
+        term = term.replace(name, value);
+    }
+    term
+}
+
+fn truth() -> Term {
+    definition("-> x { -> y { x } }", &[])
+}
+
+fn falsity() -> Term {
+    definition("-> x { -> y { y } }", &[])
+}
+
+fn zero() -> Term {
+    definition("-> p { -> x { x } }", &[])
+}
+
+fn increment() -> Term {
+    definition("-> n { -> p { -> x { p[n[p][x]] } } }", &[])
+}
+
+fn add() -> Term {
+    definition(
+        "-> m { -> n { n[INCREMENT][m] } }",
+        &[("INCREMENT", increment())],
+    )
+}
+
+fn multiply() -> Term {
+    definition(
+        "-> m { -> n { n[ADD[m]][ZERO] } }",
+        &[("ADD", add()), ("ZERO", zero())],
+    )
+}
+
+fn pair() -> Term {
+    definition("-> x { -> y { -> f { f[x][y] } } }", &[])
+}
+
+fn left() -> Term {
+    definition("-> p { p[TRUE] }", &[("TRUE", truth())])
+}
+
+fn right() -> Term {
+    definition("-> p { p[FALSE] }", &[("FALSE", falsity())])
+}
+
+fn decrement() -> Term {
+    let slide = definition(
+        "-> p { PAIR[RIGHT[p]][INCREMENT[RIGHT[p]]] }",
+        &[
+            ("PAIR", pair()),
+            ("RIGHT", right()),
+            ("INCREMENT", increment()),
+        ],
+    );
+    definition(
+        "-> n { LEFT[n[SLIDE][PAIR[ZERO][ZERO]]] }",
+        &[
+            ("LEFT", left()),
+            ("SLIDE", slide),
+            ("PAIR", pair()),
+            ("ZERO", zero()),
+        ],
+    )
+}
+
+fn less_than() -> Term {
+    let subtract = definition(
+        "-> m { -> n { n[DECREMENT][m] } }",
+        &[("DECREMENT", decrement())],
+    );
+    let is_zero = definition(
+        "-> n { n[-> x { FALSE }][TRUE] }",
+        &[("FALSE", falsity()), ("TRUE", truth())],
+    );
+    // m < n は m + 1 - n が 0 になること
+    definition(
+        "-> m { -> n { IS_ZERO[SUBTRACT[INCREMENT[m]][n]] } }",
+        &[
+            ("IS_ZERO", is_zero),
+            ("SUBTRACT", subtract),
+            ("INCREMENT", increment()),
+        ],
+    )
+}
+
+fn y_combinator() -> Term {
+    definition("-> f { -> x { f[x[x]] }[-> x { f[x[x]] }] }", &[])
+}
+
+fn numeral(n: u32) -> Term {
+    let mut body = var("x");
+    for _ in 0..n {
+        body = call(var("p"), body);
+    }
+    fun("p", fun("x", body))
+}
+
+// SIMPLE の値は (数かどうか, 中身) の対にし、環境は変数ごとに (定義済みかどうか, 値) の対を
+// 並べた組 -> s { s[slot1][slot2]... } にする
+fn value(is_number: bool, payload: Term) -> Term {
+    let tag = if is_number { truth() } else { falsity() };
+    call(call(pair(), tag), payload)
+}
+
+fn payload(value: Term) -> Term {
+    call(right(), value)
+}
+
+fn slot_name(index: usize) -> String {
+    format!("v{}", index)
+}
+
+fn select(index: usize, count: usize) -> Term {
+    (0..count)
+        .rev()
+        .fold(var(&slot_name(index)), |body, i| fun(&slot_name(i), body))
+}
+
+fn empty_environment(count: usize) -> Term {
+    let undefined = call(call(pair(), falsity()), falsity());
+    let tuple = (0..count).fold(var("s"), |tuple, _| call(tuple, undefined.clone()));
+    fun("s", tuple)
+}
+
+fn position(variables: &[String], name: &str) -> usize {
+    variables.iter().position(|v| v == name).unwrap()
+}
+
+impl Expression {
+    // 環境を受け取って値を返す閉じた項 -> e { ... } にする。
+    // variables はプログラムに出てくる変数を環境の中の順に並べたもの
+    pub fn to_lambda(&self, variables: &[String]) -> Result<Term, String> {
+        let operand = |e: &Expression| -> Result<Term, String> {
+            Ok(payload(call(e.to_lambda(variables)?, var("e"))))
+        };
+        let body = match self {
+            Number(n) if *n < 0 => {
+                return Err(format!("negative number {} cannot be compiled", n));
+            }
+            Number(n) => value(true, numeral(*n as u32)),
+            Boolean(b) => value(false, if *b { truth() } else { falsity() }),
+            Variable(name) => {
+                if !variables.contains(name) {
+                    return Err(format!("variable {} is not in the environment", name));
+                }
+                let slot = call(var("e"), select(position(variables, name), variables.len()));
+                payload(slot)
+            }
+            Add { left, right } => value(true, call(call(add(), operand(left)?), operand(right)?)),
+            Multiply { left, right } => value(
+                true,
+                call(call(multiply(), operand(left)?), operand(right)?),
+            ),
+            LessThan { left, right } => value(
+                false,
+                call(call(less_than(), operand(left)?), operand(right)?),
+            ),
+            _ => return Err(format!("{} cannot be compiled", self.to_string())),
+        };
+        Ok(fun("e", body))
+    }
+}
+
+impl Statement {
+    // 環境を受け取って新しい環境を返す閉じた項 -> e { ... } にする
+    pub fn to_lambda(&self, variables: &[String]) -> Result<Term, String> {
+        let apply = |t: Term| call(t, var("e"));
+        let body = match self {
+            DoNothing => var("e"),
+            Assign { name, expression } => {
+                if !variables.contains(name) {
+                    return Err(format!("variable {} is not in the environment", name));
+                }
+                let index = position(variables, name);
+                let defined = call(
+                    call(pair(), truth()),
+                    apply(expression.to_lambda(variables)?),
+                );
+                let tuple = (0..variables.len()).fold(var("s"), |tuple, i| {
+                    if i == index {
+                        call(tuple, defined.clone())
+                    } else {
+                        call(tuple, var(&slot_name(i)))
+                    }
+                });
+                let update = (0..variables.len())
+                    .rev()
+                    .fold(tuple, |body, i| fun(&slot_name(i), body));
+                fun("s", call(var("e"), update))
+            }
+            If {
+                condition,
+                consequence,
+                alternative,
+            } => call(
+                call(
+                    payload(apply(condition.to_lambda(variables)?)),
+                    apply(consequence.to_lambda(variables)?),
+                ),
+                apply(alternative.to_lambda(variables)?),
+            ),
+            // Y[-> loop { -> e { 条件[loop[本体[e]]][e] } }]
+            While { condition, body } => {
+                let step = fun(
+                    "loop",
+                    fun(
+                        "e",
+                        call(
+                            call(
+                                payload(apply(condition.to_lambda(variables)?)),
+                                call(var("loop"), apply(body.to_lambda(variables)?)),
+                            ),
+                            var("e"),
+                        ),
+                    ),
+                );
+                return Ok(call(y_combinator(), step));
+            }
+            Sequence { first, second } => call(
+                second.to_lambda(variables)?,
+                apply(first.to_lambda(variables)?),
+            ),
+        };
+        Ok(fun("e", body))
+    }
+}
+
+// 空の環境から実行する文を一つの項にしたもの
+pub struct Program {
+    pub variables: Vec<String>,
+    pub term: Term,
+}
+
+pub fn compile(statement: &Statement) -> Result<Program, String> {
+    let mut variables = vec![];
+    statement.collect_variables(&mut variables);
+    variables.sort();
+    variables.dedup();
+    let term = call(
+        statement.to_lambda(&variables)?,
+        empty_environment(variables.len()),
+    );
+    Ok(Program { variables, term })
+}
+
+impl Program {
+    // 項を簡約して、定義された変数だけを SIMPLE の環境に読み戻す。
+    // 未定義の変数の参照や型の合わない演算は検出できず、でたらめな値か読み戻しの失敗になる
+    pub fn evaluate(&self) -> Result<Environment, String> {
+        let mut env = Environment::new();
+        for (index, name) in self.variables.iter().enumerate() {
+            let slot = call(self.term.clone(), select(index, self.variables.len()));
+            if decode_boolean(call(left(), slot.clone()))? {
+                env.insert(name.clone(), decode_value(payload(slot))?);
+            }
+        }
+        Ok(env)
+    }
+}
+
+pub fn decode_boolean(term: Term) -> Result<bool, String> {
+    match call(call(term, var("true")), var("false")).evaluate() {
+        Term::Variable(name) if name == "true" => Ok(true),
+        Term::Variable(name) if name == "false" => Ok(false),
+        other => Err(format!("{} is not a boolean", other)),
+    }
+}
+
+pub fn decode_number(term: Term) -> Result<i32, String> {
+    let mut result = call(call(term, var("p")), var("x")).evaluate();
+    let mut n: i32 = 0;
+    loop {
+        match result {
+            Term::Variable(name) if name == "x" => return Ok(n),
+            Term::Call { left, right } if *left == var("p") => {
+                n = n.checked_add(1).ok_or("number is out of range")?;
+                result = *right;
+            }
+            other => return Err(format!("{} is not a number", other)),
+        }
+    }
+}
+
+// Expression::to_lambda の値を表す項を読み戻す
+pub fn decode_value(term: Term) -> Result<Expression, String> {
+    if decode_boolean(call(left(), term.clone()))? {
+        Ok(Number(decode_number(payload(term))?))
+    } else {
+        Ok(Boolean(decode_boolean(payload(term))?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, decode_number, decode_value, numeral, Term};
+    use crate::expression::Expression;
+    use crate::expression::Expression::{Boolean, Number};
+    use crate::statement::Statement;
+
+    fn term(source: &str) -> Term {
+        Term::parse(source).unwrap()
+    }
+
+    fn check(source: &str) {
+        let statement = Statement::from_sexp(source).unwrap();
+        let expected = statement.clone().evaluate(&mut Expression::new_env());
+        assert_eq!(compile(&statement).unwrap().evaluate(), Ok(expected));
+    }
+
+    #[test]
+    fn test_parse() {
+        let t = term("-> x { -> y { x[y][-> z { z }] } }[a]");
+        assert_eq!(t.to_string(), "-> x { -> y { x[y][-> z { z }] } }[a]");
+        assert!(Term::parse("-> x { x").is_err());
+        assert!(Term::parse("x]").is_err());
+    }
+
+    #[test]
+    fn test_replace_avoids_capture() {
+        let t = term("-> y { x[y] }");
+        assert_eq!(t.replace("x", &term("y")), term("-> y' { y[y'] }"));
+        assert_eq!(t.replace("y", &term("z")), t);
+        assert_eq!(
+            term("-> x { x }").replace("x", &term("z")),
+            term("-> x { x }")
+        );
+    }
+
+    #[test]
+    fn test_reduce() {
+        // 正規順序なので、止まらない引数を捨てる項も正規形になる
+        let omega = "-> x { x[x] }[-> x { x[x] }]";
+        let t = term(&format!("-> x {{ -> y {{ y }} }}[{}][z]", omega));
+        let mut reduced = t.clone();
+        while reduced.reducible() {
+            reduced = reduced.reduce();
+        }
+        assert_eq!(reduced, term("z"));
+        assert_eq!(t.evaluate(), term("z"));
+    }
+
+    #[test]
+    fn test_numerals() {
+        assert_eq!(decode_number(numeral(3)), Ok(3));
+        assert!(decode_number(term("-> p { -> x { p } }")).is_err());
+    }
+
+    #[test]
+    fn test_expression() {
+        let e = Expression::from_sexp("(< (* 2 3) (+ 4 3))").unwrap();
+        let value = super::call(e.to_lambda(&[]).unwrap(), term("e"));
+        assert_eq!(decode_value(value), Ok(Boolean(true)));
+        let e = Expression::from_sexp("(+ (* 2 3) 4)").unwrap();
+        let value = super::call(e.to_lambda(&[]).unwrap(), term("e"));
+        assert_eq!(decode_value(value), Ok(Number(10)));
+        assert!(Expression::from_sexp("-1").unwrap().to_lambda(&[]).is_err());
+        assert!(Expression::from_sexp("(length (string \"a\"))")
+            .unwrap()
+            .to_lambda(&[])
+            .is_err());
+    }
+
+    #[test]
+    fn test_statement() {
+        check("(sequence (assign x 2) (assign y (* x 3)) (assign x (< y x)))");
+        check("(if (< 3 2) (assign x 1) (assign y true))");
+        check(
+            "(sequence (assign i 0) (assign result 1)
+               (while (< i 4)
+                 (sequence (assign i (+ i 1)) (assign result (* result i)))))",
+        );
+        let statement = Statement::from_sexp("(assign y 1)").unwrap();
+        assert_eq!(
+            statement.to_lambda(&["x".to_string()]),
+            Err("variable y is not in the environment".to_string())
+        );
+    }
+}
@@ -1,3 +1,10 @@
+pub mod c;
+pub mod environment;
 pub mod expression;
 pub mod functions;
+pub mod interpreter;
+pub mod json;
+pub mod lambda;
+pub mod quine;
+pub mod sexp;
 pub mod statement;
@@ -0,0 +1,75 @@
+use crate::expression::Expression;
+use crate::expression::Expression::{Concat, Inspect, Str};
+use crate::statement::Statement;
+use crate::statement::Statement::Sequence;
+
+// name = "..."; name = "name = " ++ inspect(name) ++ name; program
+// の形の文を作る。二つ目の代入の後、name にはこの文全体の to_string が入っている
+fn reproduce(name: &str, program: Option<Statement>) -> Statement {
+    let mut rest = Statement::new_assign(
+        name,
+        Concat {
+            left: Box::new(Concat {
+                left: Box::new(Str(format!("{} = ", name))),
+                right: Box::new(Inspect(Box::new(Expression::new_var(name)))),
+            }),
+            right: Box::new(Expression::new_var(name)),
+        },
+    );
+    if let Some(program) = program {
+        rest = Sequence {
+            first: Box::new(rest),
+            second: Box::new(program),
+        };
+    }
+    Sequence {
+        first: Box::new(Statement::new_assign(
+            name,
+            Str(format!("; {}", rest.to_string())),
+        )),
+        second: Box::new(rest),
+    }
+}
+
+// program の前に、変数 name へ自分自身のソースを代入する文を付け足す
+pub fn with_source(name: &str, program: Statement) -> Statement {
+    reproduce(name, Some(program))
+}
+
+// 実行すると source に自分自身のソースが入る
+pub fn quine() -> Statement {
+    reproduce("source", None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quine, with_source};
+    use crate::expression::Expression;
+    use crate::expression::Expression::{Number, Str};
+    use crate::statement::Statement;
+
+    #[test]
+    fn test_quine() {
+        let q = quine();
+        let env = q.clone().evaluate(&mut Expression::new_env());
+        assert_eq!(env.get("source"), Some(&Str(q.to_string())));
+        assert_eq!(env.len(), 1);
+        assert_eq!(
+            q.to_string(),
+            r#"source = "; source = \"source = \" ++ inspect(source) ++ source"; source = "source = " ++ inspect(source) ++ source"#
+        );
+    }
+
+    #[test]
+    fn test_with_source() {
+        let program = Statement::from_sexp(
+            r#"(sequence (assign n (length me)) (assign text (string "a \"quoted\"\nline")))"#,
+        )
+        .unwrap();
+        let s = with_source("me", program);
+        let source = s.to_string();
+        let env = s.evaluate(&mut Expression::new_env());
+        assert_eq!(env.get("me"), Some(&Str(source.clone())));
+        assert_eq!(env.get("n"), Some(&Number(source.chars().count() as i32)));
+    }
+}
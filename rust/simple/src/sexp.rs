@@ -0,0 +1,628 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::expression::Expression::{
+    Add, Boolean, Concat, Equal, Inspect, Length, LessThan, Multiply, Number, Parse, Slice, Str,
+    Variable,
+};
+use crate::expression::{Environment, Expression};
+use crate::statement::Statement::{Assign, DoNothing, If, Sequence, While};
+use crate::statement::{Machine, Statement};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SExp {
+    Integer(i64),
+    Symbol(String),
+    Str(String),
+    List(Vec<SExp>),
+}
+
+impl SExp {
+    pub fn parse(input: &str) -> Result<SExp, String> {
+        let mut reader = Reader::new(input);
+        let sexp = reader.read()?;
+        reader.skip_whitespace();
+        if !reader.at_end() {
+            return Err(format!("unexpected input at {}", reader.index));
+        }
+        Ok(sexp)
+    }
+}
+
+impl fmt::Display for SExp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SExp::Integer(value) => write!(f, "{}", value),
+            SExp::Symbol(name) => write!(f, "{}", name),
+            SExp::Str(value) => write!(f, "\"{}\"", escape(value)),
+            SExp::List(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\0"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+struct Reader {
+    input: Vec<char>,
+    index: usize,
+}
+
+impl Reader {
+    fn new(input: &str) -> Self {
+        Reader {
+            input: input.chars().collect(),
+            index: 0,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.index >= self.input.len()
+    }
+
+    fn current(&self) -> char {
+        self.input[self.index]
+    }
+
+    // `;` から行末まではコメント
+    fn skip_whitespace(&mut self) {
+        while !self.at_end() {
+            if self.current() == ';' {
+                while !self.at_end() && self.current() != '\n' {
+                    self.index += 1;
+                }
+            } else if self.current().is_whitespace() {
+                self.index += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<SExp, String> {
+        self.skip_whitespace();
+        if self.at_end() {
+            return Err("unexpected end of input".to_string());
+        }
+        match self.current() {
+            '(' => self.read_list(),
+            ')' => Err(format!("unexpected ')' at {}", self.index)),
+            '"' => self.read_string(),
+            _ => self.read_atom(),
+        }
+    }
+
+    fn read_list(&mut self) -> Result<SExp, String> {
+        self.index += 1;
+        let mut items = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.at_end() {
+                return Err("expected ')' but input ends".to_string());
+            }
+            if self.current() == ')' {
+                self.index += 1;
+                return Ok(SExp::List(items));
+            }
+            items.push(self.read()?);
+        }
+    }
+
+    fn read_string(&mut self) -> Result<SExp, String> {
+        self.index += 1;
+        let mut value = String::new();
+        loop {
+            if self.at_end() {
+                return Err("unterminated string".to_string());
+            }
+            let c = self.current();
+            self.index += 1;
+            match c {
+                '"' => return Ok(SExp::Str(value)),
+                '\\' => {
+                    if self.at_end() {
+                        return Err("unterminated string".to_string());
+                    }
+                    let escaped = self.current();
+                    self.index += 1;
+                    value.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        '0' => '\0',
+                        '"' | '\\' | '\'' => escaped,
+                        _ => return Err(format!("unknown escape \\{}", escaped)),
+                    });
+                }
+                _ => value.push(c),
+            }
+        }
+    }
+
+    // 数字だけの atom は、i64 に収まらなくてもシンボルにはしない
+    fn read_atom(&mut self) -> Result<SExp, String> {
+        let mut atom = String::new();
+        while !self.at_end() && is_atom_char(self.current()) {
+            atom.push(self.current());
+            self.index += 1;
+        }
+        match atom.parse::<i64>() {
+            Ok(value) => Ok(SExp::Integer(value)),
+            Err(_) if is_integer(&atom) => Err(format!("number {} is out of range", atom)),
+            Err(_) => Ok(SExp::Symbol(atom)),
+        }
+    }
+}
+
+fn is_atom_char(c: char) -> bool {
+    !(c.is_whitespace() || c == '(' || c == ')' || c == '"' || c == ';')
+}
+
+// 符号が付いていてもよい数字の並び
+fn is_integer(atom: &str) -> bool {
+    let digits = atom.strip_prefix(|c| c == '+' || c == '-').unwrap_or(atom);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+// 名前がシンボルとして読み戻せない場合は文字列で書く
+fn name_to_sexp(name: &str) -> SExp {
+    let plain = !name.is_empty()
+        && name.chars().all(is_atom_char)
+        && !is_integer(name)
+        && name != "true"
+        && name != "false";
+    if plain {
+        SExp::Symbol(name.to_string())
+    } else {
+        SExp::Str(name.to_string())
+    }
+}
+
+fn name_from_sexp(sexp: &SExp) -> Result<String, String> {
+    match sexp {
+        SExp::Symbol(name) | SExp::Str(name) => Ok(name.clone()),
+        _ => Err(format!("expected a name but got {}", sexp)),
+    }
+}
+
+fn apply(operator: &str, args: Vec<&Expression>) -> SExp {
+    let mut items = vec![SExp::Symbol(operator.to_string())];
+    items.extend(args.into_iter().map(expression_to_sexp));
+    SExp::List(items)
+}
+
+pub fn expression_to_sexp(expression: &Expression) -> SExp {
+    match expression {
+        Number(value) => SExp::Integer(*value as i64),
+        Boolean(value) => SExp::Symbol(value.to_string()),
+        Variable(name) => name_to_sexp(name),
+        Add { left, right } => apply("+", vec![left, right]),
+        Multiply { left, right } => apply("*", vec![left, right]),
+        LessThan { left, right } => apply("<", vec![left, right]),
+        Str(value) => SExp::List(vec![
+            SExp::Symbol("string".to_string()),
+            SExp::Str(value.clone()),
+        ]),
+        Concat { left, right } => apply("++", vec![left, right]),
+        Equal { left, right } => apply("==", vec![left, right]),
+        Length(string) => apply("length", vec![string]),
+        Slice { string, start, end } => apply("slice", vec![string, start, end]),
+        Inspect(value) => apply("inspect", vec![value]),
+        Parse(string) => apply("parse", vec![string]),
+    }
+}
+
+pub fn expression_from_sexp(sexp: &SExp) -> Result<Expression, String> {
+    match sexp {
+        SExp::Integer(value) => match i32::try_from(*value) {
+            Ok(value) => Ok(Number(value)),
+            Err(_) => Err(format!("number {} is out of range", value)),
+        },
+        SExp::Symbol(name) if name == "true" => Ok(Boolean(true)),
+        SExp::Symbol(name) if name == "false" => Ok(Boolean(false)),
+        SExp::Symbol(name) | SExp::Str(name) => Ok(Variable(name.clone())),
+        SExp::List(items) => {
+            let arity = match items.first() {
+                Some(SExp::Symbol(operator)) => match operator.as_str() {
+                    "string" | "length" | "inspect" | "parse" => 1,
+                    "slice" => 3,
+                    _ => 2,
+                },
+                _ => 2,
+            };
+            let (operator, args) = split_form(items, arity)?;
+            if operator == "string" {
+                return match &args[0] {
+                    SExp::Str(value) => Ok(Str(value.clone())),
+                    other => Err(format!("expected a string but got {}", other)),
+                };
+            }
+            let mut operands = vec![];
+            for arg in args.iter() {
+                operands.push(Box::new(expression_from_sexp(arg)?));
+            }
+            let mut operands = operands.into_iter();
+            let mut next = || operands.next().unwrap();
+            match operator {
+                "+" => Ok(Add {
+                    left: next(),
+                    right: next(),
+                }),
+                "*" => Ok(Multiply {
+                    left: next(),
+                    right: next(),
+                }),
+                "<" => Ok(LessThan {
+                    left: next(),
+                    right: next(),
+                }),
+                "++" => Ok(Concat {
+                    left: next(),
+                    right: next(),
+                }),
+                "==" => Ok(Equal {
+                    left: next(),
+                    right: next(),
+                }),
+                "length" => Ok(Length(next())),
+                "slice" => Ok(Slice {
+                    string: next(),
+                    start: next(),
+                    end: next(),
+                }),
+                "inspect" => Ok(Inspect(next())),
+                "parse" => Ok(Parse(next())),
+                _ => Err(format!("unknown operator {}", operator)),
+            }
+        }
+    }
+}
+
+// (operator arg ...) の形を分解して引数の個数を確かめる
+fn split_form(items: &[SExp], arity: usize) -> Result<(&str, &[SExp]), String> {
+    match items.first() {
+        Some(SExp::Symbol(operator)) => {
+            if items.len() - 1 == arity {
+                Ok((operator, &items[1..]))
+            } else {
+                Err(format!(
+                    "{} expects {} arguments but got {}",
+                    operator,
+                    arity,
+                    items.len() - 1
+                ))
+            }
+        }
+        _ => Err("expected a form starting with a symbol".to_string()),
+    }
+}
+
+pub fn statement_to_sexp(statement: &Statement) -> SExp {
+    let form = |name: &str, mut args: Vec<SExp>| {
+        args.insert(0, SExp::Symbol(name.to_string()));
+        SExp::List(args)
+    };
+    match statement {
+        DoNothing => form("do-nothing", vec![]),
+        Assign { name, expression } => form(
+            "assign",
+            vec![name_to_sexp(name), expression_to_sexp(expression)],
+        ),
+        If {
+            condition,
+            consequence,
+            alternative,
+        } => form(
+            "if",
+            vec![
+                expression_to_sexp(condition),
+                statement_to_sexp(consequence),
+                statement_to_sexp(alternative),
+            ],
+        ),
+        While { condition, body } => form(
+            "while",
+            vec![expression_to_sexp(condition), statement_to_sexp(body)],
+        ),
+        Sequence { first, second } => form(
+            "sequence",
+            vec![statement_to_sexp(first), statement_to_sexp(second)],
+        ),
+    }
+}
+
+pub fn statement_from_sexp(sexp: &SExp) -> Result<Statement, String> {
+    let items = match sexp {
+        SExp::List(items) => items,
+        _ => return Err(format!("expected a statement but got {}", sexp)),
+    };
+    let operator = match items.first() {
+        Some(SExp::Symbol(operator)) => operator.as_str(),
+        _ => return Err("expected a form starting with a symbol".to_string()),
+    };
+    match operator {
+        "do-nothing" => {
+            split_form(items, 0)?;
+            Ok(DoNothing)
+        }
+        "assign" => {
+            let (_, args) = split_form(items, 2)?;
+            Ok(Assign {
+                name: name_from_sexp(&args[0])?,
+                expression: expression_from_sexp(&args[1])?,
+            })
+        }
+        "if" => {
+            let (_, args) = split_form(items, 3)?;
+            Ok(If {
+                condition: expression_from_sexp(&args[0])?,
+                consequence: Box::new(statement_from_sexp(&args[1])?),
+                alternative: Box::new(statement_from_sexp(&args[2])?),
+            })
+        }
+        "while" => {
+            let (_, args) = split_form(items, 2)?;
+            Ok(While {
+                condition: expression_from_sexp(&args[0])?,
+                body: Box::new(statement_from_sexp(&args[1])?),
+            })
+        }
+        // (sequence a b c) は (sequence a (sequence b c)) として読む
+        "sequence" if items.len() > 3 => {
+            let mut rest = vec![SExp::Symbol("sequence".to_string())];
+            rest.extend_from_slice(&items[2..]);
+            Ok(Sequence {
+                first: Box::new(statement_from_sexp(&items[1])?),
+                second: Box::new(statement_from_sexp(&SExp::List(rest))?),
+            })
+        }
+        "sequence" => {
+            let (_, args) = split_form(items, 2)?;
+            Ok(Sequence {
+                first: Box::new(statement_from_sexp(&args[0])?),
+                second: Box::new(statement_from_sexp(&args[1])?),
+            })
+        }
+        _ => Err(format!("unknown statement {}", operator)),
+    }
+}
+
+pub fn environment_to_sexp(env: &Environment) -> SExp {
+    SExp::List(
+        env.iter()
+            .map(|(name, value)| SExp::List(vec![name_to_sexp(name), expression_to_sexp(value)]))
+            .collect(),
+    )
+}
+
+pub fn environment_from_sexp(sexp: &SExp) -> Result<Environment, String> {
+    let mut env = Expression::new_env();
+    match sexp {
+        SExp::List(bindings) => {
+            for binding in bindings.iter() {
+                match binding {
+                    SExp::List(pair) if pair.len() == 2 => {
+                        env.insert(name_from_sexp(&pair[0])?, expression_from_sexp(&pair[1])?);
+                    }
+                    _ => return Err(format!("expected (name value) but got {}", binding)),
+                }
+            }
+            Ok(env)
+        }
+        _ => Err(format!("expected an environment but got {}", sexp)),
+    }
+}
+
+impl Expression {
+    pub fn to_sexp(&self) -> String {
+        expression_to_sexp(self).to_string()
+    }
+
+    pub fn from_sexp(input: &str) -> Result<Expression, String> {
+        expression_from_sexp(&SExp::parse(input)?)
+    }
+}
+
+impl Statement {
+    pub fn to_sexp(&self) -> String {
+        statement_to_sexp(self).to_string()
+    }
+
+    pub fn from_sexp(input: &str) -> Result<Statement, String> {
+        statement_from_sexp(&SExp::parse(input)?)
+    }
+}
+
+impl Machine {
+    pub fn to_sexp(&self) -> String {
+        SExp::List(vec![
+            SExp::Symbol("machine".to_string()),
+            statement_to_sexp(&self.statement),
+            environment_to_sexp(&self.environment),
+        ])
+        .to_string()
+    }
+
+    pub fn from_sexp(input: &str) -> Result<Machine, String> {
+        match SExp::parse(input)? {
+            SExp::List(items) => {
+                let (operator, args) = split_form(&items, 2)?;
+                if operator != "machine" {
+                    return Err(format!("expected machine but got {}", operator));
+                }
+                Ok(Machine {
+                    statement: statement_from_sexp(&args[0])?,
+                    environment: environment_from_sexp(&args[1])?,
+                })
+            }
+            sexp => Err(format!("expected a machine but got {}", sexp)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{environment_from_sexp, environment_to_sexp, SExp};
+    use crate::expression::Expression;
+    use crate::expression::Expression::{Add, Boolean, LessThan, Number, Variable};
+    use crate::statement::Statement::{If, Sequence, While};
+    use crate::statement::{Machine, Statement};
+
+    fn program() -> Statement {
+        Sequence {
+            first: Box::new(Statement::new_assign("x", Number(1))),
+            second: Box::new(While {
+                condition: LessThan {
+                    left: Box::new(Expression::new_var("x")),
+                    right: Box::new(Number(5)),
+                },
+                body: Box::new(If {
+                    condition: Boolean(true),
+                    consequence: Box::new(Statement::new_assign(
+                        "x",
+                        Add {
+                            left: Box::new(Expression::new_var("x")),
+                            right: Box::new(Number(-1)),
+                        },
+                    )),
+                    alternative: Box::new(Statement::DoNothing),
+                }),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            SExp::parse("(a -1 \"b c\" ()) ; comment"),
+            Ok(SExp::List(vec![
+                SExp::Symbol("a".to_string()),
+                SExp::Integer(-1),
+                SExp::Str("b c".to_string()),
+                SExp::List(vec![]),
+            ]))
+        );
+        assert!(SExp::parse("(a").is_err());
+        assert!(SExp::parse("a)").is_err());
+        assert!(SExp::parse("\"abc").is_err());
+    }
+
+    #[test]
+    fn test_expression() {
+        let e = Add {
+            left: Box::new(Variable("x".to_string())),
+            right: Box::new(LessThan {
+                left: Box::new(Number(3)),
+                right: Box::new(Boolean(false)),
+            }),
+        };
+        assert_eq!(e.to_sexp(), "(+ x (< 3 false))");
+        assert_eq!(Expression::from_sexp(&e.to_sexp()), Ok(e));
+        assert!(Expression::from_sexp("(- 1 2)").is_err());
+        assert!(Expression::from_sexp("(+ 1)").is_err());
+        assert!(Expression::from_sexp("99999999999").is_err());
+        // i64 にも収まらない数字は変数にならない
+        assert_eq!(
+            Expression::from_sexp("99999999999999999999"),
+            Err("number 99999999999999999999 is out of range".to_string())
+        );
+        assert_eq!(
+            Expression::from_sexp("(+ x -99999999999999999999)"),
+            Err("number -99999999999999999999 is out of range".to_string())
+        );
+    }
+
+    #[test]
+    fn test_string_primitives() {
+        let e = Expression::from_sexp(
+            "(== (slice (++ (string \"a\\\"b\") (inspect 1)) 0 (length x)) (parse (string \"2\")))",
+        )
+        .unwrap();
+        assert_eq!(
+            e.to_string(),
+            "slice(\"a\\\"b\" ++ inspect(1), 0, length(x)) == parse(\"2\")"
+        );
+        assert_eq!(Expression::from_sexp(&e.to_sexp()), Ok(e));
+        assert!(Expression::from_sexp("(string x)").is_err());
+        assert!(Expression::from_sexp("(slice x 1)").is_err());
+    }
+
+    #[test]
+    fn test_unusual_names() {
+        for name in [
+            "true",
+            "12",
+            "a b",
+            "",
+            "x;y",
+            "\"q\"",
+            "-99999999999999999999",
+        ]
+        .iter()
+        {
+            let e = Variable(name.to_string());
+            assert_eq!(Expression::from_sexp(&e.to_sexp()), Ok(e));
+        }
+    }
+
+    #[test]
+    fn test_statement() {
+        let s = program();
+        assert_eq!(
+            s.to_sexp(),
+            "(sequence (assign x 1) (while (< x 5) (if true (assign x (+ x -1)) (do-nothing))))"
+        );
+        assert_eq!(Statement::from_sexp(&s.to_sexp()), Ok(s));
+        assert_eq!(
+            Statement::from_sexp("(sequence (assign x 1) (assign y 2) (assign z 3))"),
+            Statement::from_sexp("(sequence (assign x 1) (sequence (assign y 2) (assign z 3)))")
+        );
+        assert!(Statement::from_sexp("(sequence (do-nothing))").is_err());
+        assert!(Statement::from_sexp("(repeat (do-nothing))").is_err());
+        assert!(Statement::from_sexp("(do-nothing 1)").is_err());
+    }
+
+    #[test]
+    fn test_environment() {
+        let mut env = Expression::new_env();
+        env.insert("y".to_string(), Boolean(true));
+        env.insert("x".to_string(), Number(3));
+        let sexp = environment_to_sexp(&env);
+        assert_eq!(sexp.to_string(), "((x 3) (y true))");
+        assert_eq!(environment_from_sexp(&sexp), Ok(env));
+    }
+
+    #[test]
+    fn test_machine() {
+        let mut env = Expression::new_env();
+        env.insert("x".to_string(), Number(3));
+        let m = Machine {
+            statement: program(),
+            environment: env,
+        };
+        assert_eq!(Machine::from_sexp(&m.to_sexp()), Ok(m));
+        assert!(Machine::from_sexp("(machine (do-nothing))").is_err());
+    }
+}
@@ -196,17 +196,15 @@ fn reduce_sequence(f: Statement, s: Statement, env: &mut Environment) -> (Statem
     }
 }
 
+// 再帰すると繰り返しの回数だけスタックが伸びるのでループで回す
 fn evaluate_while(cond: Expression, body: Statement, env: &mut Environment) -> Environment {
-    match cond.clone().evaluate(env) {
-        Expression::Boolean(true) => {
-            let while_s = While {
-                condition: cond.clone(),
-                body: Box::new(body.clone()),
-            };
-            while_s.evaluate(&mut body.evaluate(env))
+    let mut env = env.clone();
+    loop {
+        match cond.clone().evaluate(&env) {
+            Expression::Boolean(true) => env = body.clone().evaluate(&mut env),
+            Expression::Boolean(false) => return env,
+            _ => unreachable!(),
         }
-        Expression::Boolean(false) => env.clone(),
-        _ => unreachable!(),
     }
 }
 
@@ -219,7 +217,7 @@ pub struct Machine {
 impl Machine {
     fn step(&mut self) {
         let mut env = self.environment.clone();
-        let st = self.statement.clone();
+        let st = std::mem::replace(&mut self.statement, DoNothing);
         let (statement, environment) = st.reduce(&mut env);
         self.statement = statement;
         self.environment = environment;
@@ -385,6 +383,39 @@ mod tests {
         assert_eq!(seq.evaluate(&mut env), expected);
     }
 
+    fn count_to(n: i32) -> Statement {
+        Sequence {
+            first: Box::new(Statement::new_assign("x", Number(0))),
+            second: Box::new(While {
+                condition: Expression::LessThan {
+                    left: Box::new(Expression::new_var("x")),
+                    right: Box::new(Number(n)),
+                },
+                body: Box::new(Statement::new_assign(
+                    "x",
+                    Expression::Add {
+                        left: Box::new(Expression::new_var("x")),
+                        right: Box::new(Number(1)),
+                    },
+                )),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_long_while() {
+        let mut env = Expression::new_env();
+        let result = count_to(10000).evaluate(&mut env);
+        assert_eq!(result.get("x"), Some(&Number(10000)));
+
+        let mut m = Machine {
+            statement: count_to(10000),
+            environment: Expression::new_env(),
+        };
+        m.run();
+        assert_eq!(m.environment.get("x"), Some(&Number(10000)));
+    }
+
     #[test]
     fn test_machine() {
         let mut m = Machine {
@@ -0,0 +1,191 @@
+use std::fs;
+use std::process::Command;
+
+use simple::expression::Expression::{Add, Boolean, LessThan, Multiply, Number};
+use simple::expression::{Environment, Expression};
+use simple::statement::Statement;
+use simple::statement::Statement::{If, Sequence, While};
+
+fn seq(first: Statement, second: Statement) -> Statement {
+    Sequence {
+        first: Box::new(first),
+        second: Box::new(second),
+    }
+}
+
+fn var(name: &str) -> Box<Expression> {
+    Box::new(Expression::new_var(name))
+}
+
+fn fibonacci(n: i32) -> Statement {
+    seq(
+        seq(
+            Statement::new_assign("a", Number(0)),
+            Statement::new_assign("b", Number(1)),
+        ),
+        seq(
+            Statement::new_assign("count", Number(0)),
+            While {
+                condition: LessThan {
+                    left: var("count"),
+                    right: Box::new(Number(n)),
+                },
+                body: Box::new(seq(
+                    seq(
+                        Statement::new_assign("tmp", Expression::new_var("b")),
+                        Statement::new_assign(
+                            "b",
+                            Add {
+                                left: var("a"),
+                                right: var("b"),
+                            },
+                        ),
+                    ),
+                    seq(
+                        Statement::new_assign("a", Expression::new_var("tmp")),
+                        Statement::new_assign(
+                            "count",
+                            Add {
+                                left: var("count"),
+                                right: Box::new(Number(1)),
+                            },
+                        ),
+                    ),
+                )),
+            },
+        ),
+    )
+}
+
+fn factorial_and_flags() -> Statement {
+    seq(
+        seq(
+            Statement::new_assign("n", Number(10)),
+            Statement::new_assign("result", Number(1)),
+        ),
+        seq(
+            While {
+                condition: LessThan {
+                    left: Box::new(Number(0)),
+                    right: var("n"),
+                },
+                body: Box::new(seq(
+                    Statement::new_assign(
+                        "result",
+                        Multiply {
+                            left: var("result"),
+                            right: var("n"),
+                        },
+                    ),
+                    Statement::new_assign(
+                        "n",
+                        Add {
+                            left: var("n"),
+                            right: Box::new(Number(-1)),
+                        },
+                    ),
+                )),
+            },
+            If {
+                condition: LessThan {
+                    left: Box::new(Number(1000)),
+                    right: var("result"),
+                },
+                consequence: Box::new(seq(
+                    Statement::new_assign("big", Boolean(true)),
+                    Statement::new_assign("odd name\"", Number(-2147483648)),
+                )),
+                alternative: Box::new(Statement::new_assign("small", Boolean(true))),
+            },
+        ),
+    )
+}
+
+fn format_environment(env: &Environment) -> String {
+    env.iter()
+        .map(|(name, value)| format!("{} = {}\n", name, value.to_string()))
+        .collect()
+}
+
+// C コンパイラがなければ飛ばさずに失敗させる。使うコンパイラは CC で変えられる
+fn run_c(name: &str, statement: &Statement) -> String {
+    let dir = std::env::temp_dir().join(format!("simple-c-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.c");
+    let binary = dir.join("program");
+    fs::write(&source, statement.to_c()).unwrap();
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let compiled = Command::new(&cc)
+        .arg("-std=c99")
+        .arg("-O2")
+        .arg("-o")
+        .arg(&binary)
+        .arg(&source)
+        .status()
+        .unwrap_or_else(|e| panic!("could not run the C compiler `{}`: {}", cc, e));
+    assert!(compiled.success(), "{} failed on {}", cc, source.display());
+    let output = Command::new(&binary).output().unwrap();
+    assert!(output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn check(name: &str, statement: Statement) {
+    let output = run_c(name, &statement);
+    let expected = statement.evaluate(&mut Expression::new_env());
+    assert_eq!(output, format_environment(&expected));
+}
+
+#[test]
+fn test_fibonacci() {
+    check("fibonacci", fibonacci(30));
+}
+
+#[test]
+fn test_factorial_and_flags() {
+    check("factorial", factorial_and_flags());
+}
+
+#[test]
+fn test_untaken_branch() {
+    let statement = If {
+        condition: Boolean(false),
+        consequence: Box::new(Statement::new_assign("x", Number(1))),
+        alternative: Box::new(Statement::new_assign("y", Number(2))),
+    };
+    check("untaken", statement);
+}
+
+// 桁あふれしても C と Rust で同じ値になる
+#[test]
+fn test_overflow() {
+    let statement = Statement::from_sexp(
+        "(sequence
+           (assign a (+ 2147483647 1))
+           (assign b (* 65536 65536))
+           (assign c (* -2147483648 -1))
+           (assign i 1)
+           (assign f 1)
+           (while (< i 20) (sequence (assign i (+ i 1)) (assign f (* f i)))))",
+    )
+    .unwrap();
+    check("overflow", statement);
+}
+
+#[test]
+fn test_strings() {
+    let statement = Statement::from_sexp(
+        r#"(sequence
+             (assign s (string "héllo, \"w\"\n"))
+             (assign n (length s))
+             (assign t (slice s 1 (+ n -3)))
+             (assign u (++ (inspect t) (inspect (< n 3))))
+             (assign v (parse (inspect u)))
+             (assign w (== v u))
+             (assign x (parse (string "-2147483648")))
+             (assign y (slice s -5 100))
+             (assign z (== n (string "9"))))"#,
+    )
+    .unwrap();
+    check("strings", statement);
+}